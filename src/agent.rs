@@ -3,18 +3,20 @@ use std::sync::Arc;
 
 use hoot::BodyMode;
 use http::uri::Scheme;
-use http::{HeaderName, HeaderValue, Method, Request, Response, Uri};
+use http::{header, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri};
 
 use crate::body::{Body, ResponseInfo};
 use crate::middleware::MiddlewareNext;
 use crate::pool::{Connection, ConnectionPool};
-use crate::resolver::{DefaultResolver, Resolver};
+use crate::progress::{DownloadProgress, ProgressHook, UploadProgress};
+use crate::resolver::{DefaultResolver, ResolvedSocketAddrs, Resolver};
 use crate::send_body::AsSendBody;
-use crate::transport::time::Instant;
+use crate::sse::EventSource;
+use crate::transport::time::{Instant, NextTimeout};
 use crate::transport::{ConnectionDetails, Connector, DefaultConnector, NoBuffers};
 use crate::unit::{Event, Input, Unit};
 use crate::util::{DebugResponse, HeaderMapExt, UriExt};
-use crate::{AgentConfig, Error, RequestBuilder, SendBody, Timeouts};
+use crate::{AgentConfig, Download, Error, RequestBuilder, SendBody, Timeouts};
 use crate::{WithBody, WithoutBody};
 
 /// Agents keep state between requests.
@@ -114,6 +116,66 @@ impl Agent {
         self.jar.lock()
     }
 
+    /// Download a resource to a file, resuming the transfer on failures.
+    ///
+    /// See [`Download`] for details.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    ///
+    /// agent.download("https://example.com/big.iso").to_file("big.iso")?;
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    pub fn download<T>(&self, uri: T) -> Download
+    where
+        Uri: TryFrom<T>,
+        <Uri as TryFrom<T>>::Error: Into<http::Error>,
+    {
+        Download::new(self.clone(), uri)
+    }
+
+    /// Read a stream of server-sent events, reconnecting when the stream ends.
+    ///
+    /// See [`EventSource`] for details.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    ///
+    /// for event in agent.event_source("https://example.com/feed") {
+    ///     println!("{:?}", event?);
+    /// }
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    pub fn event_source<T>(&self, uri: T) -> EventSource
+    where
+        Uri: TryFrom<T>,
+        <Uri as TryFrom<T>>::Error: Into<http::Error>,
+    {
+        EventSource::new(self.clone(), uri)
+    }
+
+    /// Open a WebSocket using the `ws://` or `wss://` scheme.
+    ///
+    /// See [`WebSocketBuilder`](crate::websocket::WebSocketBuilder) for details.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    ///
+    /// let socket = agent
+    ///     .websocket("wss://example.com/chat")
+    ///     .protocols(&["chat"])
+    ///     .connect()?;
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    #[cfg(feature = "websocket")]
+    pub fn websocket<T>(&self, uri: T) -> crate::websocket::WebSocketBuilder
+    where
+        Uri: TryFrom<T>,
+        <Uri as TryFrom<T>>::Error: Into<http::Error>,
+    {
+        crate::websocket::WebSocketBuilder::new(self.clone(), uri)
+    }
+
     /// Run a [`http::Request<impl AsSendBody>`].
     pub fn run(&self, request: Request<impl AsSendBody>) -> Result<Response<Body>, Error> {
         let (parts, mut body) = request.into_parts();
@@ -153,6 +215,16 @@ impl Agent {
         let has_header_accept_enc = headers.has_accept_encoding();
         let has_header_ua = headers.has_user_agent();
 
+        let is_connect = request.method() == Method::CONNECT;
+        // Upgrades are only possible on HTTP/1.1.
+        let http1_only = is_connect || headers.contains_key(header::UPGRADE);
+
+        let progress = request.extensions().get::<ProgressHook>().cloned();
+        let send_total = match send_body_mode {
+            Some(BodyMode::LengthDelimited(v)) => Some(v),
+            _ => headers.content_length(),
+        };
+
         // Timeouts on the request level overrides the agent level.
         let timeouts = *request
             .extensions()
@@ -161,10 +233,17 @@ impl Agent {
 
         let mut unit = Unit::new(self.config.clone(), timeouts, current_time(), request, body)?;
 
+        if let Some(hook) = &progress {
+            unit.set_upload_progress(UploadProgress::new(hook.clone(), send_total));
+        }
+
         // For CONNECT proxy, this is the address of the proxy server, for
         // all other cases it's the address of the URL being requested.
         let mut addrs = None;
 
+        // The config of the current uri, with the proxy for it.
+        let mut config = self.config.clone();
+
         let mut connection: Option<Connection> = None;
         let mut response;
         let mut no_buffers = NoBuffers;
@@ -258,8 +337,10 @@ impl Agent {
                 }
 
                 Event::Resolve { uri, timeout } => {
+                    config = self.config_for(uri, http1_only, timeout)?;
+
                     // If we're using a CONNECT proxy, we need to resolve that hostname.
-                    let maybe_connect_uri = self.config.connect_proxy_uri();
+                    let maybe_connect_uri = config.connect_proxy_uri();
 
                     let effective_uri = maybe_connect_uri.unwrap_or(uri);
 
@@ -267,10 +348,20 @@ impl Agent {
                     // cannot make requests with partial uri like "/path".
                     effective_uri.ensure_valid_url()?;
 
-                    addrs = Some(
-                        self.resolver
-                            .resolve(effective_uri, &self.config, timeout)?,
-                    );
+                    // A SOCKS proxy with remote DNS gets the hostname, which must not
+                    // leak through a local lookup.
+                    let remote_dns = config
+                        .proxy
+                        .as_ref()
+                        .is_some_and(|p| p.proto().resolves_remotely());
+
+                    addrs = Some(if remote_dns {
+                        ResolvedSocketAddrs::new()
+                    } else {
+                        self.resolver.resolve(effective_uri, &config, timeout)?
+                    });
+
+                    unit.set_config(config.clone());
                     unit.handle_input(current_time(), Input::Resolved, &mut [])?;
                 }
 
@@ -283,7 +374,7 @@ impl Agent {
                         uri,
                         addrs,
                         resolver: &*self.resolver,
-                        config: &self.config,
+                        config: &config,
                         now: current_time(),
                         timeout,
                     };
@@ -391,8 +482,32 @@ impl Agent {
             return Err(Error::StatusCode(status.as_u16()));
         }
 
-        let (parts, _) = response.into_parts();
-        let info = ResponseInfo::new(&parts.headers, recv_body_mode);
+        #[allow(unused_mut)]
+        let (mut parts, _) = response.into_parts();
+
+        #[cfg(feature = "_tls")]
+        if let Some(chain) = connection.peer_certificates() {
+            parts
+                .extensions
+                .insert(crate::tls::PeerCertificates::new(chain));
+        }
+        #[cfg(feature = "_tls")]
+        if let Some(handshake) = connection.tls_handshake() {
+            parts.extensions.insert(handshake);
+        }
+
+        let download_progress = progress.map(|hook| {
+            let total = match recv_body_mode {
+                BodyMode::LengthDelimited(v) => Some(v),
+                _ => None,
+            };
+            Arc::new(DownloadProgress::new(hook, total))
+        });
+        // After these responses the connection is no longer HTTP.
+        let upgrade =
+            status == StatusCode::SWITCHING_PROTOCOLS || is_connect && status.is_success();
+
+        let info = ResponseInfo::new(&parts.headers, recv_body_mode, download_progress, upgrade);
         let recv_body = Body::new(unit, connection, info, current_time);
         let response = Response::from_parts(parts, recv_body);
 
@@ -405,6 +520,41 @@ impl Agent {
     pub(crate) fn config(&self) -> &AgentConfig {
         &self.config
     }
+
+    /// Config for a request to `uri`, with the proxy chosen by the proxy auto-config.
+    ///
+    /// With `http1_only`, for upgrades and CONNECT requests, HTTP/2 is not offered.
+    fn config_for(
+        &self,
+        uri: &Uri,
+        http1_only: bool,
+        timeout: NextTimeout,
+    ) -> Result<Arc<AgentConfig>, Error> {
+        #[cfg_attr(not(any(feature = "pac", feature = "http2")), allow(unused_mut))]
+        let mut config: Option<AgentConfig> = None;
+
+        #[cfg(not(feature = "pac"))]
+        let _ = (uri, timeout);
+        #[cfg(feature = "pac")]
+        if let Some(pac) = &self.config.proxy_auto_config {
+            let proxy = pac.find_proxy_with(uri, &*self.resolver, &self.config, timeout)?;
+            if proxy != self.config.proxy {
+                config.get_or_insert_with(|| (*self.config).clone()).proxy = proxy;
+            }
+        }
+
+        #[cfg(not(feature = "http2"))]
+        let _ = http1_only;
+        #[cfg(feature = "http2")]
+        if http1_only {
+            let config = config.get_or_insert_with(|| (*self.config).clone());
+            #[cfg(feature = "_tls")]
+            config.tls_config.alpn_protocols.retain(|p| p != "h2");
+            config.http2_prior_knowledge = false;
+        }
+
+        Ok(config.map(Arc::new).unwrap_or_else(|| self.config.clone()))
+    }
 }
 
 fn set_header(unit: &mut Unit<SendBody>, now: Instant, name: &'static str, value: HeaderValue) {
@@ -452,6 +602,7 @@ impl From<AgentConfig> for Agent {
 
 #[cfg(test)]
 impl crate::Agent {
+    /// Number of idle connections in the pool (test only).
     pub fn pool_count(&self) -> usize {
         self.pool.pool_count()
     }
@@ -1,6 +1,8 @@
 use std::io;
+use std::sync::Arc;
 
 use crate::pool::Connection;
+use crate::progress::DownloadProgress;
 use crate::transport::time::Instant;
 use crate::unit::{Event, Input, Unit};
 use crate::Error;
@@ -8,6 +10,9 @@ use crate::Error;
 pub(crate) struct UnitHandler {
     unit: Unit<()>,
     connection: Option<Connection>,
+    progress: Option<Arc<DownloadProgress>>,
+    // The connection speaks another protocol after the response.
+    upgrade: bool,
     current_time: Box<dyn Fn() -> Instant + Send + Sync>,
 }
 
@@ -29,16 +34,32 @@ impl UnitHandler {
     pub fn new(
         unit: Unit<()>,
         connection: Connection,
+        progress: Option<Arc<DownloadProgress>>,
+        upgrade: bool,
         current_time: impl Fn() -> Instant + Send + Sync + 'static,
     ) -> Self {
         Self {
             unit,
             connection: Some(connection),
+            progress,
+            upgrade,
             current_time: Box::new(current_time),
         }
     }
 
+    pub fn take_connection(&mut self) -> Option<Connection> {
+        self.connection.take()
+    }
+
     fn do_read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let amount = self.do_read_unit(buf)?;
+        if let Some(p) = &self.progress {
+            p.add_raw(amount);
+        }
+        Ok(amount)
+    }
+
+    fn do_read_unit(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
         let Some(connection) = &mut self.connection else {
             return Ok(0);
         };
@@ -57,6 +78,9 @@ impl UnitHandler {
                         if must_close {
                             trace!("Must close");
                             connection.close()
+                        } else if self.upgrade {
+                            debug!("Close upgraded connection");
+                            connection.close()
                         } else if has_buffered_input {
                             debug!("Close due to excess body data");
                             connection.close()
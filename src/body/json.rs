@@ -0,0 +1,395 @@
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+use super::BodyReader;
+use crate::Error;
+
+/// Iterator of JSON values in a response body.
+///
+/// Obtained via one of:
+///
+/// * [`Body::into_json_stream()`](crate::Body::into_json_stream) for newline-delimited JSON.
+/// * [`Body::into_json_array_stream()`](crate::Body::into_json_array_stream) for the elements
+///   of a JSON array.
+///
+/// Only one value at a time is held in memory, which means the body can be of
+/// any size. A value larger than [`JsonStream::max_item_size()`] results in
+/// [`Error::BodyExceedsLimit`], which ends the stream.
+///
+/// Requires the **json** feature.
+pub struct JsonStream<T> {
+    reader: BufReader<BodyReader<'static>>,
+    mode: Mode,
+    max_item_size: usize,
+    buf: Vec<u8>,
+    _ph: PhantomData<fn() -> T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Lines,
+    ArrayStart,
+    ArrayElements,
+    End,
+}
+
+/// Max size of a value until changed with `max_item_size()`.
+const DEFAULT_MAX_ITEM_SIZE: usize = 10 * 1024 * 1024;
+
+impl<T: DeserializeOwned> JsonStream<T> {
+    pub(crate) fn lines(reader: BodyReader<'static>) -> Self {
+        Self::new(reader, Mode::Lines)
+    }
+
+    pub(crate) fn array(reader: BodyReader<'static>) -> Self {
+        Self::new(reader, Mode::ArrayStart)
+    }
+
+    fn new(reader: BodyReader<'static>, mode: Mode) -> Self {
+        JsonStream {
+            reader: BufReader::new(reader),
+            mode,
+            max_item_size: DEFAULT_MAX_ITEM_SIZE,
+            buf: Vec::new(),
+            _ph: PhantomData,
+        }
+    }
+
+    /// Max size in bytes of a line, or of an array element.
+    ///
+    /// This protects against a server sending a value that never ends.
+    ///
+    /// Defaults to 10MB.
+    pub fn max_item_size(mut self, value: usize) -> Self {
+        self.max_item_size = value;
+        self
+    }
+
+    fn next_line(&mut self) -> Result<Option<T>, Error> {
+        loop {
+            if !self.read_line()? {
+                return Ok(None);
+            }
+
+            // Blank lines are allowed between values.
+            if self.buf.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let value = serde_json::from_slice(&self.buf)?;
+            return Ok(Some(value));
+        }
+    }
+
+    fn next_element(&mut self) -> Result<Option<T>, Error> {
+        if self.mode == Mode::ArrayStart {
+            if self.skip_whitespace()? != Some(b'[') {
+                return Err(json_error("expected start of array"));
+            }
+            self.reader.consume(1);
+
+            if self.skip_whitespace()? == Some(b']') {
+                self.reader.consume(1);
+                self.mode = Mode::End;
+            } else {
+                self.mode = Mode::ArrayElements;
+            }
+        }
+
+        if self.mode == Mode::End {
+            return Ok(None);
+        }
+
+        let last = self.read_element()?;
+        if last {
+            self.mode = Mode::End;
+        }
+
+        let value = serde_json::from_slice(&self.buf)?;
+        Ok(Some(value))
+    }
+
+    /// Read a line, including the `\n`, into self.buf. Returns false at the end of the body.
+    fn read_line(&mut self) -> Result<bool, Error> {
+        self.buf.clear();
+
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(!self.buf.is_empty());
+            }
+
+            let (len, done) = match buf.iter().position(|b| *b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+
+            self.buf.extend_from_slice(&buf[..len]);
+            self.reader.consume(len);
+            self.check_size()?;
+
+            if done {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn check_size(&self) -> Result<(), Error> {
+        if self.buf.len() > self.max_item_size {
+            return Err(Error::BodyExceedsLimit(self.max_item_size as u64));
+        }
+        Ok(())
+    }
+
+    /// Skip whitespace and peek the next byte.
+    fn skip_whitespace(&mut self) -> Result<Option<u8>, Error> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+                Some(i) => {
+                    let next = buf[i];
+                    self.reader.consume(i);
+                    return Ok(Some(next));
+                }
+                None => {
+                    let len = buf.len();
+                    self.reader.consume(len);
+                }
+            }
+        }
+    }
+
+    /// Read an array element into self.buf, consuming the following `,` or `]`.
+    ///
+    /// Returns true if the element is the last in the array.
+    fn read_element(&mut self) -> Result<bool, Error> {
+        self.buf.clear();
+
+        let mut depth = 0_usize;
+        let mut in_string = false;
+        let mut escape = false;
+
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Err(json_error("unexpected end of array"));
+            }
+
+            for (i, b) in buf.iter().enumerate() {
+                if in_string {
+                    if escape {
+                        escape = false;
+                    } else if *b == b'\\' {
+                        escape = true;
+                    } else if *b == b'"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b',' | b']' if depth == 0 => {
+                        self.buf.extend_from_slice(&buf[..i]);
+                        let last = *b == b']';
+                        self.reader.consume(i + 1);
+                        self.check_size()?;
+                        return Ok(last);
+                    }
+                    b'}' | b']' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            let len = buf.len();
+            self.buf.extend_from_slice(buf);
+            self.reader.consume(len);
+            self.check_size()?;
+        }
+    }
+}
+
+fn json_error(msg: &str) -> Error {
+    Error::Json(serde::de::Error::custom(msg))
+}
+
+impl<T: DeserializeOwned> Iterator for JsonStream<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = if self.mode == Mode::Lines {
+            let result = self.next_line();
+            if matches!(result, Err(Error::BodyExceedsLimit(_))) {
+                // The rest of the line is not read.
+                self.mode = Mode::End;
+            }
+            result
+        } else {
+            let result = self.next_element();
+            if result.is_err() {
+                // The position in the array is unknown after an error.
+                self.mode = Mode::End;
+            }
+            result
+        };
+
+        result.transpose()
+    }
+}
+
+impl<T> fmt::Debug for JsonStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonStream")
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "_test"))]
+mod test {
+    use serde::Deserialize;
+
+    use crate::test::init_test_log;
+    use crate::transport::set_handler;
+    use crate::Error;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Line {
+        n: u32,
+        s: String,
+    }
+
+    fn line(n: u32, s: &str) -> Line {
+        Line { n, s: s.into() }
+    }
+
+    #[test]
+    fn json_stream_lines() {
+        init_test_log();
+        set_handler(
+            "/ndjson",
+            200,
+            &[("content-type", "application/x-ndjson")],
+            b"{\"n\":1,\"s\":\"a\"}\n\
+            \n\
+            {\"n\":2,\"s\":\"b\"}\r\n\
+            {\"n\":\"bad\"}\n\
+            {\"n\":3,\"s\":\"c\"}",
+        );
+
+        let mut stream = crate::get("https://my.test/ndjson")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_json_stream::<Line>();
+
+        assert_eq!(stream.next().unwrap().unwrap(), line(1, "a"));
+        assert_eq!(stream.next().unwrap().unwrap(), line(2, "b"));
+        // A bad line doesn't stop the stream.
+        assert!(matches!(stream.next().unwrap(), Err(Error::Json(_))));
+        assert_eq!(stream.next().unwrap().unwrap(), line(3, "c"));
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn json_stream_array() {
+        init_test_log();
+        set_handler(
+            "/json-array",
+            200,
+            &[("content-type", "application/json")],
+            b" [ {\"n\":1,\"s\":\"a,]}\\\"\"},\n{\"n\":2,\"s\":\"[{\"} ] ",
+        );
+
+        let stream = crate::get("https://my.test/json-array")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_json_array_stream::<Line>();
+
+        let lines: Vec<_> = stream.collect::<Result<_, _>>().unwrap();
+        assert_eq!(lines, vec![line(1, "a,]}\""), line(2, "[{")]);
+    }
+
+    #[test]
+    fn json_stream_max_item_size() {
+        init_test_log();
+        set_handler(
+            "/ndjson-large",
+            200,
+            &[],
+            b"{\"n\":1,\"s\":\"a\"}\n\
+            {\"n\":2,\"s\":\"0123456789\"}\n\
+            {\"n\":3,\"s\":\"c\"}\n",
+        );
+        set_handler(
+            "/json-array-large",
+            200,
+            &[],
+            b"[{\"n\":1,\"s\":\"a\"},{\"n\":2,\"s\":\"0123456789\"}]",
+        );
+
+        let mut lines = crate::get("https://my.test/ndjson-large")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_json_stream::<Line>()
+            .max_item_size(20);
+
+        assert_eq!(lines.next().unwrap().unwrap(), line(1, "a"));
+        let err = lines.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::BodyExceedsLimit(20)));
+        assert!(lines.next().is_none());
+
+        let mut elements = crate::get("https://my.test/json-array-large")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_json_array_stream::<Line>()
+            .max_item_size(20);
+
+        assert_eq!(elements.next().unwrap().unwrap(), line(1, "a"));
+        let err = elements.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::BodyExceedsLimit(20)));
+        assert!(elements.next().is_none());
+    }
+
+    #[test]
+    fn json_stream_array_nested_and_empty() {
+        init_test_log();
+        set_handler("/json-nested", 200, &[], b"[[1,2],[],[3]]");
+        set_handler("/json-empty", 200, &[], b"[ ]");
+        set_handler("/json-object", 200, &[], b"{}");
+
+        let nested: Vec<Vec<u32>> = crate::get("https://my.test/json-nested")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_json_array_stream()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(nested, vec![vec![1, 2], vec![], vec![3]]);
+
+        let mut empty = crate::get("https://my.test/json-empty")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_json_array_stream::<u32>();
+        assert!(empty.next().is_none());
+
+        let mut object = crate::get("https://my.test/json-object")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_json_array_stream::<u32>();
+        assert!(matches!(object.next().unwrap(), Err(Error::Json(_))));
+        assert!(object.next().is_none());
+    }
+}
@@ -5,7 +5,10 @@ use std::sync::Arc;
 use hoot::BodyMode;
 
 use crate::pool::Connection;
+use crate::progress::DownloadProgress;
+use crate::sse::Events;
 use crate::transport::time::Instant;
+use crate::transport::TransportAdapter;
 use crate::unit::Unit;
 use crate::Error;
 
@@ -26,6 +29,11 @@ mod gzip;
 #[cfg(feature = "brotli")]
 mod brotli;
 
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "json")]
+pub use self::json::JsonStream;
+
 /// Default max body size for read_to_string() and read_to_vec().
 const MAX_BODY_SIZE: u64 = 10 * 1024 * 1024;
 
@@ -61,6 +69,8 @@ pub(crate) struct ResponseInfo {
     mime_type: Option<String>,
     charset: Option<String>,
     body_mode: BodyMode,
+    progress: Option<Arc<DownloadProgress>>,
+    upgrade: bool,
 }
 
 impl Body {
@@ -70,9 +80,11 @@ impl Body {
         info: ResponseInfo,
         current_time: impl Fn() -> Instant + Send + Sync + 'static,
     ) -> Self {
+        let progress = info.progress.clone();
+        let upgrade = info.upgrade;
         Body {
             info: Arc::new(info),
-            unit_handler: UnitHandler::new(unit, connection, current_time),
+            unit_handler: UnitHandler::new(unit, connection, progress, upgrade, current_time),
         }
     }
 
@@ -293,6 +305,143 @@ impl Body {
         let handler = UnitHandlerRef::Owned(self.unit_handler);
         BodyWithConfig::new(handler, self.info.clone())
     }
+
+    /// Read the body as a stream of newline-delimited JSON values.
+    ///
+    /// Requires the **json** feature.
+    ///
+    /// Each line of the body (NDJSON or JSON lines) is deserialized as it arrives,
+    /// without a limit on the body size. A line that fails to deserialize results
+    /// in an error for that line, and the stream continues with the next line.
+    /// Blank lines are skipped. Each line is limited to 10MB, see
+    /// [`JsonStream::max_item_size()`].
+    ///
+    /// ```no_run
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct LogLine {
+    ///     level: String,
+    ///     message: String,
+    /// }
+    ///
+    /// let lines = ureq::get("https://example.com/logs")
+    ///     .call()?
+    ///     .into_body()
+    ///     .into_json_stream::<LogLine>();
+    ///
+    /// for line in lines {
+    ///     let line = line?;
+    ///     println!("{}: {}", line.level, line.message);
+    /// }
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn into_json_stream<T: serde::de::DeserializeOwned>(self) -> JsonStream<T> {
+        JsonStream::lines(self.into_reader())
+    }
+
+    /// Read the body as a stream of the elements of a JSON array.
+    ///
+    /// Requires the **json** feature.
+    ///
+    /// The body must be a JSON array, like `[{...}, {...}]`. Each element is
+    /// deserialized as it arrives, without a limit on the body size. The stream
+    /// ends at the first error. Each element is limited to 10MB, see
+    /// [`JsonStream::max_item_size()`].
+    ///
+    /// ```no_run
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Repo {
+    ///     name: String,
+    /// }
+    ///
+    /// let repos = ureq::get("https://example.com/repos")
+    ///     .call()?
+    ///     .into_body()
+    ///     .into_json_array_stream::<Repo>();
+    ///
+    /// for repo in repos {
+    ///     println!("{}", repo?.name);
+    /// }
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn into_json_array_stream<T: serde::de::DeserializeOwned>(self) -> JsonStream<T> {
+        JsonStream::array(self.into_reader())
+    }
+
+    /// Read the body as a stream of server-sent events.
+    ///
+    /// The events are parsed as they arrive. To reconnect when the stream ends,
+    /// use [`Agent::event_source()`](crate::Agent::event_source) instead.
+    ///
+    /// ```no_run
+    /// let events = ureq::get("https://example.com/feed")
+    ///     .call()?
+    ///     .into_body()
+    ///     .into_sse();
+    ///
+    /// for event in events {
+    ///     println!("{:?}", event?);
+    /// }
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    pub fn into_sse(self) -> Events {
+        Events::new(self.into_reader(), None)
+    }
+
+    /// Take over the connection of an upgraded response.
+    ///
+    /// This is for responses with status `101 Switching Protocols`, and `2xx` responses
+    /// to a `CONNECT` request. The returned stream reads and writes directly on the
+    /// connection, using whatever protocol the request upgraded to. Any data the server
+    /// sent right after the response header is not lost, it is the first data read.
+    ///
+    /// The connection is never returned to the connection pool. Reads and writes have
+    /// no timeout, unless set with [`TransportAdapter::set_timeout()`].
+    ///
+    /// Errors with [`Error::NotUpgraded`] for any other response, or if the connection
+    /// is already gone because the body was read.
+    ///
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    ///
+    /// let res = ureq::get("http://example.com/attach")
+    ///     .header("connection", "upgrade")
+    ///     .header("upgrade", "tcp")
+    ///     .call()?;
+    ///
+    /// let mut stream = res.into_body().into_upgraded()?;
+    ///
+    /// stream.write_all(b"ping")?;
+    ///
+    /// let mut buf = [0; 4];
+    /// stream.read_exact(&mut buf)?;
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    pub fn into_upgraded(mut self) -> Result<TransportAdapter, Error> {
+        if !self.info.upgrade {
+            return Err(Error::NotUpgraded);
+        }
+
+        let connection = self
+            .unit_handler
+            .take_connection()
+            .ok_or(Error::NotUpgraded)?;
+
+        Ok(TransportAdapter::new(connection.into_transport()))
+    }
+
+    /// Reader of the body bytes exactly as sent by the server.
+    ///
+    /// No content decoding or charset conversion. Used where the byte offsets
+    /// must match the server's representation, like for range requests.
+    pub(crate) fn as_raw_reader(&mut self) -> impl Read + '_ {
+        UnitHandlerRef::Shared(&mut self.unit_handler)
+    }
 }
 
 /// Configuration of how to read the body.
@@ -390,7 +539,12 @@ enum ContentEncoding {
 }
 
 impl ResponseInfo {
-    pub fn new(headers: &http::HeaderMap, body_mode: BodyMode) -> Self {
+    pub fn new(
+        headers: &http::HeaderMap,
+        body_mode: BodyMode,
+        progress: Option<Arc<DownloadProgress>>,
+        upgrade: bool,
+    ) -> Self {
         let content_encoding = headers
             .get("content-encoding")
             .and_then(|v| v.to_str().ok())
@@ -408,6 +562,8 @@ impl ResponseInfo {
             mime_type,
             charset,
             body_mode,
+            progress,
+            upgrade,
         }
     }
 
@@ -485,6 +641,7 @@ pub struct BodyReader<'a> {
     // body mode can indiciate the content-length. Gzip, charset etc
     // would mean input is not same as output.
     outgoing_body_mode: BodyMode,
+    progress: Option<Arc<DownloadProgress>>,
 }
 
 impl<'a> BodyReader<'a> {
@@ -538,6 +695,7 @@ impl<'a> BodyReader<'a> {
         BodyReader {
             outgoing_body_mode,
             reader,
+            progress: info.progress.clone(),
         }
     }
 
@@ -593,7 +751,11 @@ impl<R: io::Read> io::Read for MaybeLossyDecoder<R> {
 
 impl<'a> Read for BodyReader<'a> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.reader.read(buf)
+        let n = self.reader.read(buf)?;
+        if let Some(p) = &self.progress {
+            p.add_received(n);
+        }
+        Ok(n)
     }
 }
 
@@ -654,10 +816,11 @@ impl From<&str> for ContentEncoding {
 
 #[cfg(all(test, feature = "_test"))]
 mod test {
+    use std::io::Read;
     use std::iter;
 
     use crate::test::init_test_log;
-    use crate::transport::set_handler;
+    use crate::transport::{set_handler, set_handler_fn};
     use crate::Error;
 
     #[test]
@@ -724,4 +887,102 @@ mod test {
         let err = crate::get("https://my.test/get").call().unwrap_err();
         assert!(matches!(err, Error::LargeResponseHeader(_, _)));
     }
+
+    #[test]
+    fn upgraded_keeps_buffered_input() {
+        init_test_log();
+        set_handler_fn("/upgrade", |_uri, _req, w| {
+            w.write_all(
+                b"HTTP/1.1 101 Switching Protocols\r\n\
+                connection: upgrade\r\n\
+                upgrade: tcp\r\n\
+                \r\n\
+                hello",
+            )
+        });
+
+        let res = crate::get("https://my.test/upgrade")
+            .header("connection", "upgrade")
+            .header("upgrade", "tcp")
+            .call()
+            .unwrap();
+        assert_eq!(res.status(), 101);
+
+        let mut stream = res.into_body().into_upgraded().unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn upgraded_connect() {
+        init_test_log();
+        set_handler_fn("/connect", |_uri, req, w| {
+            assert_eq!(req.method(), "CONNECT");
+            w.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\ntunnel")
+        });
+
+        let res = crate::connect("https://my.test/connect").call().unwrap();
+
+        let mut stream = res.into_body().into_upgraded().unwrap();
+        let mut buf = [0; 6];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"tunnel");
+    }
+
+    #[test]
+    fn upgraded_timeout() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::time::Duration;
+
+        use crate::test::FixedResolver;
+        use crate::transport::{ChainedConnector, Connector, TcpConnector};
+
+        init_test_log();
+
+        // Upgrades, then never sends anything.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(sock.try_clone().unwrap());
+            let mut line = String::new();
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            sock.write_all(b"HTTP/1.1 101 Switching Protocols\r\nupgrade: tcp\r\n\r\n")
+                .unwrap();
+            // Keep the connection open until the client is done.
+            let _ = reader.read_line(&mut line);
+        });
+
+        let connector = ChainedConnector::new([TcpConnector::default().boxed()]);
+        let agent = crate::Agent::with_parts(Default::default(), connector, FixedResolver(addr));
+
+        let res = agent
+            .get(format!("http://{}/silent", addr))
+            .header("connection", "upgrade")
+            .header("upgrade", "tcp")
+            .call()
+            .unwrap();
+
+        let mut stream = res.into_body().into_upgraded().unwrap();
+        stream.set_timeout(Some(Duration::from_millis(100)));
+
+        let err = stream.read(&mut [0; 1]).unwrap_err();
+        assert!(matches!(Error::from(err), Error::Timeout(_)));
+    }
+
+    #[test]
+    fn not_upgraded() {
+        init_test_log();
+        set_handler("/not-upgrade", 200, &[], b"");
+
+        let res = crate::get("https://my.test/not-upgrade").call().unwrap();
+        let err = res.into_body().into_upgraded().unwrap_err();
+        assert!(matches!(err, Error::NotUpgraded));
+    }
 }
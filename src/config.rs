@@ -8,6 +8,9 @@ use crate::middleware::MiddlewareChain;
 use crate::resolver::IpFamily;
 use crate::Proxy;
 
+#[cfg(feature = "pac")]
+use crate::ProxyAutoConfig;
+
 #[cfg(feature = "_tls")]
 use crate::tls::TlsConfig;
 
@@ -63,6 +66,25 @@ pub struct AgentConfig {
     /// [`Agent::new_with_defaults()`][crate::Agent::new_with_defaults].
     pub proxy: Option<Proxy>,
 
+    /// Config for TLS to an `https` proxy.
+    ///
+    /// The connection to the proxy and the tunneled connection to the server are
+    /// separate TLS sessions, each with their own certificates and server name.
+    ///
+    /// Defaults to `None`, which means [`AgentConfig::tls_config`] is used, but
+    /// without any ALPN protocols.
+    #[cfg(feature = "_tls")]
+    pub proxy_tls_config: Option<TlsConfig>,
+
+    /// Proxy auto-config script, choosing the proxy per request.
+    ///
+    /// When set, the proxy chosen by the script is used instead of [`AgentConfig::proxy`],
+    /// also for redirects to other hosts.
+    ///
+    /// Defaults to `None`.
+    #[cfg(feature = "pac")]
+    pub proxy_auto_config: Option<ProxyAutoConfig>,
+
     /// Disable Nagle's algorithm
     ///
     /// Set TCP_NODELAY. It's up to the transport whether this flag is honored.
@@ -134,6 +156,14 @@ pub struct AgentConfig {
     /// Defaults to no middleware.
     pub middleware: MiddlewareChain,
 
+    /// Use HTTP/2 for `http://` without negotiating it first (h2c).
+    ///
+    /// For `https://`, HTTP/2 is used when the server selects it via ALPN.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "http2")]
+    pub http2_prior_knowledge: bool,
+
     // This is here to force users of ureq to use the ..Default::default() pattern
     // as part of creating `AgentConfig`. That way we can introduce new settings without
     // it becoming a breaking changes.
@@ -240,6 +270,10 @@ impl Default for AgentConfig {
             #[cfg(feature = "_tls")]
             tls_config: TlsConfig::default(),
             proxy: Proxy::try_from_env(),
+            #[cfg(feature = "_tls")]
+            proxy_tls_config: None,
+            #[cfg(feature = "pac")]
+            proxy_auto_config: None,
             no_delay: true,
             max_redirects: 10,
             redirect_auth_headers: RedirectAuthHeaders::Never,
@@ -252,6 +286,8 @@ impl Default for AgentConfig {
             max_idle_connections_per_host: 3,
             max_idle_age: Duration::from_secs(15),
             middleware: MiddlewareChain::default(),
+            #[cfg(feature = "http2")]
+            http2_prior_knowledge: false,
 
             _must_use_default: private::Private,
         }
@@ -298,7 +334,18 @@ impl fmt::Debug for AgentConfig {
 
         #[cfg(feature = "_tls")]
         {
-            dbg.field("tls_config", &self.tls_config);
+            dbg.field("tls_config", &self.tls_config)
+                .field("proxy_tls_config", &self.proxy_tls_config);
+        }
+
+        #[cfg(feature = "pac")]
+        {
+            dbg.field("proxy_auto_config", &self.proxy_auto_config);
+        }
+
+        #[cfg(feature = "http2")]
+        {
+            dbg.field("http2_prior_knowledge", &self.http2_prior_knowledge);
         }
 
         dbg.finish()
@@ -0,0 +1,843 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use http::{HeaderMap, HeaderValue, StatusCode, Uri};
+
+use crate::{Agent, Error};
+
+const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Download of a resource to a file, resuming the transfer on failures.
+///
+/// Obtained via [`Agent::download()`].
+///
+/// If the transfer fails with [`Error::Io`] or [`Error::Timeout`], the download
+/// continues from where it left off using a `Range: bytes=N-` request. The
+/// `ETag` (or `Last-Modified`) of the first response is sent as `If-Range`
+/// to make sure the remaining bytes belong to the same version of the resource.
+///
+/// If the server ignores the range and sends the entire resource, the download
+/// restarts from the beginning. Without an `ETag` or `Last-Modified` there is
+/// no safe way to resume, and every retry is a full download.
+///
+/// The bytes are written exactly as sent by the server, which means the request
+/// is made with `Accept-Encoding: identity`.
+///
+/// # Example
+///
+/// ```no_run
+/// let agent = ureq::agent();
+///
+/// let size = agent
+///     .download("https://example.com/big.iso")
+///     .max_retries(10)
+///     .to_file("big.iso")?;
+///
+/// println!("Downloaded {} bytes", size);
+/// # Ok::<_, ureq::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Download {
+    agent: Agent,
+    uri: Result<Uri, http::Error>,
+    max_retries: u32,
+    segments: u32,
+}
+
+impl Download {
+    pub(crate) fn new<T>(agent: Agent, uri: T) -> Self
+    where
+        Uri: TryFrom<T>,
+        <Uri as TryFrom<T>>::Error: Into<http::Error>,
+    {
+        Download {
+            agent,
+            uri: Uri::try_from(uri).map_err(Into::into),
+            max_retries: DEFAULT_MAX_RETRIES,
+            segments: 1,
+        }
+    }
+
+    /// Max number of times to resume a failed transfer.
+    ///
+    /// With [`segments()`](Download::segments) this is per segment.
+    ///
+    /// Defaults to 5.
+    pub fn max_retries(mut self, value: u32) -> Self {
+        self.max_retries = value;
+        self
+    }
+
+    /// Download the resource in parallel segments.
+    ///
+    /// A `HEAD` request finds the length of the resource, which is split into
+    /// this many byte ranges. Each range is fetched on its own thread using a
+    /// clone of the [`Agent`], and written at its offset in the file.
+    ///
+    /// If the server does not announce `Accept-Ranges: bytes` and a
+    /// `Content-Length`, the download falls back to a single transfer. So it
+    /// does without a strong `ETag` or `Last-Modified`, since the segments could
+    /// otherwise be of different versions of the resource.
+    ///
+    /// Defaults to 1, i.e. no parallel segments.
+    ///
+    /// ```no_run
+    /// ureq::agent()
+    ///     .download("https://example.com/big.iso")
+    ///     .segments(4)
+    ///     .to_file("big.iso")?;
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    pub fn segments(mut self, value: u32) -> Self {
+        self.segments = value.max(1);
+        self
+    }
+
+    /// Download to a file at the given path.
+    ///
+    /// The file is created, or truncated if it exists. Returns the number of
+    /// bytes written.
+    pub fn to_file(self, path: impl AsRef<Path>) -> Result<u64, Error> {
+        let path = path.as_ref();
+        let uri = self.uri?;
+        let mut file = File::create(path)?;
+
+        if self.segments > 1 {
+            if let Some((len, validator)) = probe_ranges(&self.agent, &uri, self.segments)? {
+                return download_segments(
+                    self.agent,
+                    uri,
+                    path,
+                    file,
+                    self.segments,
+                    self.max_retries,
+                    len,
+                    validator,
+                );
+            }
+            debug!("Server does not support safe ranges, download in one segment");
+        }
+
+        let mut state = State::new(0, None, None);
+        let abort = AtomicBool::new(false);
+        state.run(&self.agent, &uri, &mut file, self.max_retries, &abort)?;
+
+        Ok(state.written)
+    }
+}
+
+/// HEAD request to find out whether we can split the download.
+///
+/// Returns the length and validator of the resource.
+fn probe_ranges(
+    agent: &Agent,
+    uri: &Uri,
+    segments: u32,
+) -> Result<Option<(u64, HeaderValue)>, Error> {
+    let res = agent
+        .head(uri.clone())
+        .header("accept-encoding", "identity")
+        .call()?;
+
+    let headers = res.headers();
+
+    let accepts_ranges = headers
+        .get("accept-ranges")
+        .map(|v| v.as_bytes().eq_ignore_ascii_case(b"bytes"))
+        .unwrap_or(false);
+
+    let len = headers
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    let Some(validator) = validator(headers) else {
+        return Ok(None);
+    };
+
+    match len {
+        Some(len) if accepts_ranges && len >= segments as u64 => Ok(Some((len, validator))),
+        _ => Ok(None),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn download_segments(
+    agent: Agent,
+    uri: Uri,
+    path: &Path,
+    file: File,
+    segments: u32,
+    max_retries: u32,
+    len: u64,
+    validator: HeaderValue,
+) -> Result<u64, Error> {
+    file.set_len(len)?;
+
+    let ranges = split_ranges(len, segments);
+
+    // Each thread has its own file handle, to not share the file cursor. They are
+    // all opened up front so that a failure doesn't leave threads running.
+    let files = ranges
+        .iter()
+        .map(|_| OpenOptions::new().write(true).open(path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Set by the first segment that fails, to stop the others.
+    let abort = Arc::new(AtomicBool::new(false));
+
+    let handles: Vec<_> = ranges
+        .into_iter()
+        .zip(files)
+        .map(|((start, end), mut file)| {
+            let agent = agent.clone();
+            let uri = uri.clone();
+            let abort = abort.clone();
+            let mut state = State::new(start, Some(end), Some(validator.clone()));
+            state.total = Some(len);
+
+            thread::spawn(move || -> Result<u64, Error> {
+                let result = state.run(&agent, &uri, &mut file, max_retries, &abort);
+                if result.is_err() {
+                    abort.store(true, Ordering::Relaxed);
+                }
+                result?;
+                Ok(state.written)
+            })
+        })
+        .collect();
+
+    let mut total = 0;
+    let mut first_err = None;
+
+    for handle in handles {
+        let result = handle
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("segment panicked").into()));
+
+        match result {
+            Ok(written) => total += written,
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+
+    if let Some(e) = first_err {
+        return Err(e);
+    }
+
+    let file_len = file.metadata()?.len();
+    if total != len || file_len != len {
+        return Err(Error::BadContentRange(format!(
+            "downloaded {} bytes, expected {}",
+            total, len
+        )));
+    }
+
+    Ok(total)
+}
+
+/// Split `len` bytes into `n` inclusive ranges.
+fn split_ranges(len: u64, n: u32) -> Vec<(u64, u64)> {
+    let n = n as u64;
+    let size = len / n;
+    let rest = len % n;
+
+    let mut start = 0;
+    (0..n)
+        .map(|i| {
+            // Spread the remainder over the first segments.
+            let this = size + if i < rest { 1 } else { 0 };
+            let range = (start, start + this - 1);
+            start += this;
+            range
+        })
+        .collect()
+}
+
+/// Transfer of one byte range of the resource.
+struct State {
+    /// Where the range starts in the file.
+    offset: u64,
+    /// Inclusive end of the range, or `None` for the rest of the resource.
+    end: Option<u64>,
+    /// Number of bytes written from offset.
+    written: u64,
+    /// The ETag or Last-Modified to send as If-Range.
+    validator: Option<HeaderValue>,
+    /// Length of the resource, which each Content-Range must have.
+    total: Option<u64>,
+}
+
+/// Why a download attempt failed.
+enum Failed {
+    /// The transfer failed in a way we can resume from.
+    Transfer(Error),
+    /// Any other error, which ends the download.
+    Fatal(Error),
+}
+
+impl From<Error> for Failed {
+    fn from(e: Error) -> Self {
+        if matches!(e, Error::Io(_) | Error::Timeout(_)) {
+            Failed::Transfer(e)
+        } else {
+            Failed::Fatal(e)
+        }
+    }
+}
+
+fn local(e: io::Error) -> Failed {
+    Failed::Fatal(Error::Io(e))
+}
+
+impl State {
+    fn new(offset: u64, end: Option<u64>, validator: Option<HeaderValue>) -> Self {
+        State {
+            offset,
+            end,
+            written: 0,
+            validator,
+            total: None,
+        }
+    }
+
+    fn run(
+        &mut self,
+        agent: &Agent,
+        uri: &Uri,
+        file: &mut File,
+        max_retries: u32,
+        abort: &AtomicBool,
+    ) -> Result<(), Error> {
+        let mut retries = 0;
+
+        loop {
+            // Another segment failed. It reports the error, this one just stops.
+            if abort.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            match self.attempt(agent, uri, file, abort) {
+                Ok(()) => return Ok(()),
+                Err(Failed::Transfer(e)) if retries < max_retries => {
+                    retries += 1;
+                    debug!(
+                        "Download failed after {} bytes, retry {}/{}: {}",
+                        self.offset + self.written,
+                        retries,
+                        max_retries,
+                        e
+                    );
+                }
+                Err(Failed::Transfer(e)) | Err(Failed::Fatal(e)) => return Err(e),
+            }
+        }
+    }
+
+    fn attempt(
+        &mut self,
+        agent: &Agent,
+        uri: &Uri,
+        file: &mut File,
+        abort: &AtomicBool,
+    ) -> Result<(), Failed> {
+        let pos = self.offset + self.written;
+        let ranged = self.end.is_some() || (self.written > 0 && self.validator.is_some());
+
+        let mut builder = agent.get(uri.clone()).header("accept-encoding", "identity");
+
+        if ranged {
+            let end = self.end.map(|e| e.to_string()).unwrap_or_default();
+            builder = builder.header("range", format!("bytes={}-{}", pos, end));
+
+            if let Some(validator) = &self.validator {
+                builder = builder.header("if-range", validator.clone());
+            }
+        }
+
+        let mut res = builder.call()?;
+        let status = res.status();
+
+        let expected = if ranged && status == StatusCode::PARTIAL_CONTENT {
+            let range = res
+                .headers()
+                .get("content-range")
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| Error::BadContentRange("missing header".into()))?;
+
+            let (start, end, total) = parse_content_range(range)
+                .ok_or_else(|| Error::BadContentRange(range.to_string()))?;
+
+            if let Some(expected) = self.total.filter(|t| total != Some(*t)) {
+                return Err(Error::BadContentRange(format!(
+                    "expected length {}: {}",
+                    expected, range
+                ))
+                .into());
+            }
+
+            if start != pos || self.end.map(|e| e != end).unwrap_or(false) {
+                return Err(Error::BadContentRange(format!(
+                    "expected bytes {}-{}: {}",
+                    pos,
+                    self.end.map(|e| e.to_string()).unwrap_or_default(),
+                    range
+                ))
+                .into());
+            }
+
+            debug!("Download bytes {}-{}", start, end);
+            Some(end + 1)
+        } else if status == StatusCode::OK {
+            if self.end.is_some() {
+                // A segment can't use the entire resource.
+                return Err(Error::BadContentRange("server ignored range".into()).into());
+            }
+            if self.written > 0 {
+                debug!("Server sent entire resource, restart download");
+                file.set_len(0).map_err(local)?;
+                self.written = 0;
+            }
+            self.validator = validator(res.headers());
+            res.headers()
+                .get("content-length")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+        } else {
+            return Err(Error::StatusCode(status.as_u16()).into());
+        };
+
+        file.seek(SeekFrom::Start(self.offset + self.written))
+            .map_err(local)?;
+
+        let mut reader = res.body_mut().as_raw_reader();
+        let mut buf = vec![0; 16 * 1024];
+
+        loop {
+            if abort.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let n = reader.read(&mut buf).map_err(Error::from)?;
+            if n == 0 {
+                break;
+            }
+
+            // Never write past the end of the range, it belongs to another segment.
+            let pos = self.offset + self.written;
+            let take = match expected {
+                Some(expected) => (n as u64).min(expected.saturating_sub(pos)) as usize,
+                None => n,
+            };
+
+            file.write_all(&buf[..take]).map_err(local)?;
+            self.written += take as u64;
+
+            if take < n {
+                return Err(Error::BadContentRange("body longer than range".into()).into());
+            }
+        }
+
+        if let Some(expected) = expected {
+            if self.offset + self.written < expected {
+                return Err(Error::disconnected().into());
+            }
+        }
+
+        file.flush().map_err(local)?;
+
+        Ok(())
+    }
+}
+
+/// Strong ETag, or Last-Modified, to use as If-Range.
+fn validator(headers: &HeaderMap) -> Option<HeaderValue> {
+    let etag = headers
+        .get("etag")
+        // Weak ETags are not allowed in If-Range.
+        .filter(|v| !v.as_bytes().starts_with(b"W/"));
+
+    etag.or_else(|| headers.get("last-modified")).cloned()
+}
+
+/// Parse `bytes <start>-<end>/<total>` where total might be `*`.
+fn parse_content_range(v: &str) -> Option<(u64, u64, Option<u64>)> {
+    let rest = v.trim().strip_prefix("bytes ")?;
+    let (range, total) = rest.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+
+    let total = match total.trim() {
+        "*" => None,
+        t => Some(t.parse().ok()?),
+    };
+
+    if end < start || total.map(|t| end >= t).unwrap_or(false) {
+        return None;
+    }
+
+    Some((start, end, total))
+}
+
+#[cfg(all(test, feature = "_test"))]
+mod test {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::test::init_test_log;
+    use crate::transport::set_handler_fn;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ureq-{}-{}", name, std::process::id()))
+    }
+
+    fn range_header(req: &http::Request<()>) -> Option<&str> {
+        req.headers().get("range").and_then(|v| v.to_str().ok())
+    }
+
+    // Answers the first request with half the body before disconnecting.
+    fn truncated(w: &mut dyn Write) -> io::Result<()> {
+        write!(
+            w,
+            "HTTP/1.1 200 OK\r\n\
+            Content-Length: 10\r\n\
+            ETag: \"abc\"\r\n\
+            \r\n\
+            01234"
+        )
+    }
+
+    #[test]
+    fn download_resumes_with_range() {
+        init_test_log();
+        set_handler_fn("/resume", |_uri, req, w| {
+            let if_range = req.headers().get("if-range").and_then(|v| v.to_str().ok());
+            match range_header(&req) {
+                None => truncated(w),
+                Some("bytes=5-") if if_range == Some("\"abc\"") => write!(
+                    w,
+                    "HTTP/1.1 206 Partial Content\r\n\
+                    Content-Length: 5\r\n\
+                    Content-Range: bytes 5-9/10\r\n\
+                    \r\n\
+                    56789"
+                ),
+                r => panic!("unexpected range: {:?}", r),
+            }
+        });
+
+        let path = temp_path("resume");
+        let size = crate::agent()
+            .download("https://my.test/resume")
+            .to_file(&path)
+            .unwrap();
+
+        assert_eq!(size, 10);
+        assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_restarts_when_range_ignored() {
+        init_test_log();
+        set_handler_fn("/ignored", |_uri, req, w| match range_header(&req) {
+            None => truncated(w),
+            Some(_) => write!(
+                w,
+                "HTTP/1.1 200 OK\r\n\
+                Content-Length: 10\r\n\
+                \r\n\
+                abcdefghij"
+            ),
+        });
+
+        let path = temp_path("ignored");
+        let size = crate::agent()
+            .download("https://my.test/ignored")
+            .to_file(&path)
+            .unwrap();
+
+        assert_eq!(size, 10);
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcdefghij");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_bad_content_range() {
+        init_test_log();
+        set_handler_fn("/badrange", |_uri, req, w| match range_header(&req) {
+            None => truncated(w),
+            Some(_) => write!(
+                w,
+                "HTTP/1.1 206 Partial Content\r\n\
+                Content-Length: 5\r\n\
+                Content-Range: bytes 4-8/10\r\n\
+                \r\n\
+                45678"
+            ),
+        });
+
+        let path = temp_path("badrange");
+        let err = crate::agent()
+            .download("https://my.test/badrange")
+            .to_file(&path)
+            .unwrap_err();
+
+        assert!(matches!(err, Error::BadContentRange(_)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_body_longer_than_range() {
+        init_test_log();
+        set_handler_fn("/longrange", |_uri, req, w| match range_header(&req) {
+            None => truncated(w),
+            Some(_) => write!(
+                w,
+                "HTTP/1.1 206 Partial Content\r\n\
+                Content-Length: 8\r\n\
+                Content-Range: bytes 5-9/10\r\n\
+                \r\n\
+                fghijxyz"
+            ),
+        });
+
+        let path = temp_path("longrange");
+        let err = crate::agent()
+            .download("https://my.test/longrange")
+            .to_file(&path)
+            .unwrap_err();
+
+        assert!(matches!(err, Error::BadContentRange(_)));
+        // Nothing beyond the requested range is written.
+        assert_eq!(std::fs::read(&path).unwrap(), b"01234fghij");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_gives_up_after_max_retries() {
+        init_test_log();
+        set_handler_fn("/flaky", |_uri, _req, w| truncated(w));
+
+        let path = temp_path("flaky");
+        let err = crate::agent()
+            .download("https://my.test/flaky")
+            .max_retries(2)
+            .to_file(&path)
+            .unwrap_err();
+
+        assert!(matches!(err, Error::Io(_)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_segments_without_ranges() {
+        init_test_log();
+        set_handler_fn("/noranges", |_uri, req, w| {
+            write!(
+                w,
+                "HTTP/1.1 200 OK\r\n\
+                Content-Length: 10\r\n\
+                \r\n"
+            )?;
+            if req.method() != http::Method::HEAD {
+                w.write_all(b"0123456789")?;
+            }
+            Ok(())
+        });
+
+        // No Accept-Ranges, so falls back to a single transfer.
+        let path = temp_path("noranges");
+        let size = crate::agent()
+            .download("https://my.test/noranges")
+            .segments(4)
+            .to_file(&path)
+            .unwrap();
+
+        assert_eq!(size, 10);
+        assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_segments_without_validator() {
+        init_test_log();
+        set_handler_fn("/novalidator", |_uri, req, w| {
+            assert_eq!(range_header(&req), None);
+            write!(
+                w,
+                "HTTP/1.1 200 OK\r\n\
+                Accept-Ranges: bytes\r\n\
+                Content-Length: 10\r\n\
+                ETag: W/\"weak\"\r\n\
+                \r\n"
+            )?;
+            if req.method() != http::Method::HEAD {
+                w.write_all(b"0123456789")?;
+            }
+            Ok(())
+        });
+
+        // Only a weak ETag, so falls back to a single transfer.
+        let path = temp_path("novalidator");
+        let size = crate::agent()
+            .download("https://my.test/novalidator")
+            .segments(2)
+            .to_file(&path)
+            .unwrap();
+
+        assert_eq!(size, 10);
+        assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Agent for a server answering each request head with `respond(head)`.
+    ///
+    /// The test handlers are thread local, and the segments run on their own threads.
+    fn segment_server(respond: fn(&str) -> &'static [u8]) -> (Agent, std::net::SocketAddr) {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        use crate::test::FixedResolver;
+        use crate::transport::{ChainedConnector, Connector, TcpConnector};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for sock in listener.incoming() {
+                let mut sock = sock.unwrap();
+                let mut reader = BufReader::new(sock.try_clone().unwrap());
+
+                thread::spawn(move || loop {
+                    let mut head = String::new();
+                    while !head.ends_with("\r\n\r\n") {
+                        if reader.read_line(&mut head).unwrap_or(0) == 0 {
+                            return;
+                        }
+                    }
+                    sock.write_all(respond(&head)).unwrap();
+                });
+            }
+        });
+
+        let connector = ChainedConnector::new([TcpConnector::default().boxed()]);
+        let config = crate::AgentConfig {
+            proxy: None,
+            ..Default::default()
+        };
+        let agent = Agent::with_parts(config, connector, FixedResolver(addr));
+
+        (agent, addr)
+    }
+
+    const SEGMENT_HEAD: &[u8] = b"HTTP/1.1 200 OK\r\naccept-ranges: bytes\r\n\
+        content-length: 10\r\netag: \"abc\"\r\n\r\n";
+
+    #[test]
+    fn download_segments_ranges() {
+        init_test_log();
+
+        let (agent, addr) = segment_server(|head| {
+            if head.starts_with("HEAD") {
+                SEGMENT_HEAD
+            } else if head.contains("range: bytes=0-4") && head.contains("if-range: \"abc\"") {
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\n\
+                content-range: bytes 0-4/10\r\n\r\n01234"
+            } else {
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\n\
+                content-range: bytes 5-9/10\r\n\r\n56789"
+            }
+        });
+
+        let path = temp_path("segments");
+        let size = agent
+            .download(format!("http://{}/segments", addr))
+            .segments(2)
+            .to_file(&path)
+            .unwrap();
+
+        assert_eq!(size, 10);
+        assert_eq!(std::fs::read(&path).unwrap(), b"0123456789");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_segments_stops_on_failure() {
+        init_test_log();
+
+        let (agent, addr) = segment_server(|head| {
+            if head.starts_with("HEAD") {
+                SEGMENT_HEAD
+            } else if head.contains("range: bytes=0-4") {
+                b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n"
+            } else {
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\n\
+                content-range: bytes 5-9/10\r\n\r\n56789"
+            }
+        });
+
+        let path = temp_path("segfail");
+        let err = agent
+            .download(format!("http://{}/segfail", addr))
+            .segments(2)
+            .to_file(&path)
+            .unwrap_err();
+
+        assert!(matches!(err, Error::StatusCode(500)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn download_segments_check_length() {
+        init_test_log();
+
+        // The resource grew since the HEAD request.
+        let (agent, addr) = segment_server(|head| {
+            if head.starts_with("HEAD") {
+                SEGMENT_HEAD
+            } else if head.contains("range: bytes=0-4") {
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\n\
+                content-range: bytes 0-4/12\r\n\r\n01234"
+            } else {
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\n\
+                content-range: bytes 5-9/10\r\n\r\n56789"
+            }
+        });
+
+        let path = temp_path("seglen");
+        let err = agent
+            .download(format!("http://{}/seglen", addr))
+            .segments(2)
+            .to_file(&path)
+            .unwrap_err();
+
+        assert!(matches!(err, Error::BadContentRange(_)), "{:?}", err);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn split_ranges_spreads_remainder() {
+        assert_eq!(split_ranges(10, 1), vec![(0, 9)]);
+        assert_eq!(split_ranges(10, 2), vec![(0, 4), (5, 9)]);
+        assert_eq!(split_ranges(10, 3), vec![(0, 3), (4, 6), (7, 9)]);
+        assert_eq!(split_ranges(4, 4), vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn parse_content_range_values() {
+        assert_eq!(parse_content_range("bytes 5-9/10"), Some((5, 9, Some(10))));
+        assert_eq!(parse_content_range("bytes 5-9/*"), Some((5, 9, None)));
+        assert_eq!(parse_content_range("bytes 5-10/10"), None);
+        assert_eq!(parse_content_range("bytes 9-5/10"), None);
+        assert_eq!(parse_content_range("items 5-9/10"), None);
+    }
+}
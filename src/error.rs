@@ -69,6 +69,32 @@ pub enum Error {
     #[error("{0}")]
     Tls(&'static str),
 
+    /// The [`TlsConfig`](crate::tls::TlsConfig) can't be honored by the TLS provider.
+    #[cfg(feature = "_tls")]
+    #[error("unsupported TLS config: {0}")]
+    UnsupportedTlsConfig(String),
+
+    /// No certificate presented by the server matches the public key pins for the host.
+    ///
+    /// See [`TlsConfig::spki_pins`](crate::tls::TlsConfig::spki_pins).
+    #[cfg(feature = "_tls")]
+    #[error("certificate pin mismatch for host: {0}")]
+    PinMismatch(String),
+
+    /// The server certificate was rejected by the
+    /// [`TlsConfig::cert_verifier`](crate::tls::TlsConfig::cert_verifier) callback.
+    #[cfg(feature = "_tls")]
+    #[error("certificate rejected by verifier for host: {0}")]
+    CertificateRejected(String),
+
+    /// The server certificate is revoked, or the revocation status couldn't be checked.
+    ///
+    /// See [`TlsConfig::crls`](crate::tls::TlsConfig::crls) and
+    /// [`TlsConfig::ocsp_stapling`](crate::tls::TlsConfig::ocsp_stapling).
+    #[cfg(feature = "_tls")]
+    #[error("certificate revocation check failed: {0}")]
+    Revocation(String),
+
     /// Error in reading PEM certificates/private keys.
     ///
     /// *Note:* The wrapped error struct is not considered part of ureq API.
@@ -83,7 +109,7 @@ pub enum Error {
     /// *Note:* The wrapped error struct is not considered part of ureq API.
     /// Breaking changes in that struct will not be reflected in ureq
     /// major versions.
-    #[cfg(feature = "rustls")]
+    #[cfg(feature = "_rustls")]
     #[error("rustls: {0}")]
     Rustls(#[from] rustls::Error),
 
@@ -157,6 +183,41 @@ pub enum Error {
     #[error("CONNECT proxy failed: {0}")]
     ConnectProxyFailed(String),
 
+    /// Attempt to connect to a SOCKS proxy failed.
+    #[error("SOCKS proxy failed: {0}")]
+    SocksProxyFailed(String),
+
+    /// The [`ProxyAutoConfig`](crate::ProxyAutoConfig) script failed to parse or run.
+    #[cfg(feature = "pac")]
+    #[error("proxy auto-config: {0}")]
+    ProxyAutoConfig(String),
+
+    /// A `206 Partial Content` response did not match the requested range.
+    ///
+    /// Used by [`Download`](crate::Download) when resuming a transfer.
+    #[error("bad Content-Range: {0}")]
+    BadContentRange(String),
+
+    /// The response of an [`EventSource`](crate::sse::EventSource) is not `text/event-stream`.
+    #[error("not an event stream: {0}")]
+    NotEventStream(String),
+
+    /// The response is not an upgrade of the connection.
+    ///
+    /// Returned by [`Body::into_upgraded()`](crate::Body::into_upgraded).
+    #[error("response is not an upgrade")]
+    NotUpgraded,
+
+    /// WebSocket handshake or protocol failure.
+    #[cfg(feature = "websocket")]
+    #[error("websocket: {0}")]
+    WebSocket(String),
+
+    /// HTTP/2 protocol failure, or a request that can't be made over HTTP/2.
+    #[cfg(feature = "http2")]
+    #[error("http2: {0}")]
+    Http2(String),
+
     /// hoot made no progress and there is no more input to read.
     ///
     /// We should never see this value.
@@ -0,0 +1,326 @@
+//! HTTP/2 frame codec (RFC 9113 section 4 and 6).
+
+use crate::Error;
+
+/// Size of the frame header.
+pub(crate) const HEADER_LEN: usize = 9;
+
+/// Max frame size until the peer tells us otherwise.
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+
+pub(crate) const END_STREAM: u8 = 0x1;
+pub(crate) const ACK: u8 = 0x1;
+pub(crate) const END_HEADERS: u8 = 0x4;
+pub(crate) const PADDED: u8 = 0x8;
+pub(crate) const PRIORITY: u8 = 0x20;
+
+pub(crate) const NO_ERROR: u32 = 0x0;
+pub(crate) const PROTOCOL_ERROR: u32 = 0x1;
+pub(crate) const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub(crate) const CANCEL: u32 = 0x8;
+
+pub(crate) const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub(crate) const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub(crate) const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub(crate) const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub(crate) const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub(crate) const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameType {
+    Data,
+    Headers,
+    Priority,
+    RstStream,
+    Settings,
+    PushPromise,
+    Ping,
+    GoAway,
+    WindowUpdate,
+    Continuation,
+    Unknown(u8),
+}
+
+impl FrameType {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0x0 => FrameType::Data,
+            0x1 => FrameType::Headers,
+            0x2 => FrameType::Priority,
+            0x3 => FrameType::RstStream,
+            0x4 => FrameType::Settings,
+            0x5 => FrameType::PushPromise,
+            0x6 => FrameType::Ping,
+            0x7 => FrameType::GoAway,
+            0x8 => FrameType::WindowUpdate,
+            0x9 => FrameType::Continuation,
+            v => FrameType::Unknown(v),
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            FrameType::Data => 0x0,
+            FrameType::Headers => 0x1,
+            FrameType::Priority => 0x2,
+            FrameType::RstStream => 0x3,
+            FrameType::Settings => 0x4,
+            FrameType::PushPromise => 0x5,
+            FrameType::Ping => 0x6,
+            FrameType::GoAway => 0x7,
+            FrameType::WindowUpdate => 0x8,
+            FrameType::Continuation => 0x9,
+            FrameType::Unknown(v) => *v,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Frame<'a> {
+    pub typ: FrameType,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag > 0
+    }
+
+    /// The payload without padding and priority fields.
+    ///
+    /// Only DATA, HEADERS and PUSH_PROMISE can have these.
+    pub fn data(&self) -> Result<&'a [u8], Error> {
+        let mut payload = self.payload;
+
+        let pad_len = if self.has(PADDED) {
+            let Some((pad, rest)) = payload.split_first() else {
+                return Err(h2_error("missing pad length"));
+            };
+            payload = rest;
+            *pad as usize
+        } else {
+            0
+        };
+
+        if self.typ == FrameType::Headers && self.has(PRIORITY) {
+            // Stream dependency (4) and weight (1), which we ignore.
+            payload = payload
+                .get(5..)
+                .ok_or_else(|| h2_error("short priority fields"))?;
+        }
+
+        if pad_len > payload.len() {
+            return Err(h2_error("padding exceeds payload"));
+        }
+
+        Ok(&payload[..payload.len() - pad_len])
+    }
+}
+
+/// Parse one frame from the input.
+///
+/// Returns `None` if the input doesn't hold the entire frame.
+pub(crate) fn parse_frame(
+    input: &[u8],
+    max_frame_size: usize,
+) -> Result<Option<(usize, Frame<'_>)>, Error> {
+    if input.len() < HEADER_LEN {
+        return Ok(None);
+    }
+
+    let len = u32::from_be_bytes([0, input[0], input[1], input[2]]) as usize;
+
+    if len > max_frame_size {
+        return Err(h2_error(format!(
+            "frame of {} bytes exceeds max size of {}",
+            len, max_frame_size
+        )));
+    }
+
+    if input.len() < HEADER_LEN + len {
+        return Ok(None);
+    }
+
+    let typ = FrameType::from_u8(input[3]);
+    let flags = input[4];
+    let stream_id = u32::from_be_bytes([input[5], input[6], input[7], input[8]]) & 0x7fff_ffff;
+    let payload = &input[HEADER_LEN..HEADER_LEN + len];
+
+    let frame = Frame {
+        typ,
+        flags,
+        stream_id,
+        payload,
+    };
+
+    Ok(Some((HEADER_LEN + len, frame)))
+}
+
+/// Append one frame to the output.
+pub(crate) fn write_frame(
+    out: &mut Vec<u8>,
+    typ: FrameType,
+    flags: u8,
+    stream_id: u32,
+    payload: &[u8],
+) {
+    let len = payload.len() as u32;
+    out.extend_from_slice(&len.to_be_bytes()[1..]);
+    out.push(typ.as_u8());
+    out.push(flags);
+    out.extend_from_slice(&stream_id.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Append a header block, split in HEADERS and CONTINUATION frames.
+pub(crate) fn write_headers(
+    out: &mut Vec<u8>,
+    stream_id: u32,
+    block: &[u8],
+    end_stream: bool,
+    max_frame_size: usize,
+) {
+    let mut chunks = block.chunks(max_frame_size).peekable();
+    let mut typ = FrameType::Headers;
+    let mut flags = if end_stream { END_STREAM } else { 0 };
+
+    // An empty block is still one HEADERS frame.
+    if chunks.peek().is_none() {
+        write_frame(out, typ, flags | END_HEADERS, stream_id, &[]);
+        return;
+    }
+
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() {
+            flags |= END_HEADERS;
+        }
+        write_frame(out, typ, flags, stream_id, chunk);
+        typ = FrameType::Continuation;
+        flags = 0;
+    }
+}
+
+pub(crate) fn write_settings(out: &mut Vec<u8>, settings: &[(u16, u32)]) {
+    let mut payload = Vec::with_capacity(settings.len() * 6);
+    for (id, value) in settings {
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+    write_frame(out, FrameType::Settings, 0, 0, &payload);
+}
+
+pub(crate) fn write_window_update(out: &mut Vec<u8>, stream_id: u32, increment: u32) {
+    write_frame(
+        out,
+        FrameType::WindowUpdate,
+        0,
+        stream_id,
+        &increment.to_be_bytes(),
+    );
+}
+
+pub(crate) fn write_rst_stream(out: &mut Vec<u8>, stream_id: u32, code: u32) {
+    write_frame(out, FrameType::RstStream, 0, stream_id, &code.to_be_bytes());
+}
+
+pub(crate) fn write_goaway(out: &mut Vec<u8>, last_stream_id: u32, code: u32) {
+    let mut payload = [0; 8];
+    payload[..4].copy_from_slice(&last_stream_id.to_be_bytes());
+    payload[4..].copy_from_slice(&code.to_be_bytes());
+    write_frame(out, FrameType::GoAway, 0, 0, &payload);
+}
+
+/// Parse the payload of a SETTINGS frame.
+pub(crate) fn parse_settings(payload: &[u8]) -> Result<Vec<(u16, u32)>, Error> {
+    if payload.len() % 6 != 0 {
+        return Err(h2_error("bad settings length"));
+    }
+
+    let settings = payload
+        .chunks_exact(6)
+        .map(|c| {
+            (
+                u16::from_be_bytes([c[0], c[1]]),
+                u32::from_be_bytes([c[2], c[3], c[4], c[5]]),
+            )
+        })
+        .collect();
+
+    Ok(settings)
+}
+
+/// Read a big endian u32 from a payload of exactly 4 bytes, like
+/// RST_STREAM and WINDOW_UPDATE.
+pub(crate) fn parse_u32(payload: &[u8]) -> Result<u32, Error> {
+    let b: [u8; 4] = payload
+        .try_into()
+        .map_err(|_| h2_error("bad frame length"))?;
+    Ok(u32::from_be_bytes(b))
+}
+
+pub(crate) fn h2_error(msg: impl Into<String>) -> Error {
+    Error::Http2(msg.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frame_roundtrip() {
+        let mut out = vec![];
+        write_frame(&mut out, FrameType::Data, END_STREAM, 3, b"hello");
+        assert_eq!(&out[..9], &[0, 0, 5, 0, 1, 0, 0, 0, 3]);
+
+        // Partial input
+        assert!(parse_frame(&out[..10], 100).unwrap().is_none());
+
+        let (used, frame) = parse_frame(&out, 100).unwrap().unwrap();
+        assert_eq!(used, out.len());
+        assert_eq!(frame.typ, FrameType::Data);
+        assert!(frame.has(END_STREAM));
+        assert_eq!(frame.stream_id, 3);
+        assert_eq!(frame.data().unwrap(), b"hello");
+
+        // Too large
+        assert!(parse_frame(&out, 4).is_err());
+    }
+
+    #[test]
+    fn frame_padding_and_priority() {
+        let mut out = vec![];
+        let payload = [2, 0, 0, 0, 1, 16, b'h', b'i', 0, 0];
+        write_frame(&mut out, FrameType::Headers, PADDED | PRIORITY, 1, &payload);
+        let (_, frame) = parse_frame(&out, 100).unwrap().unwrap();
+        assert_eq!(frame.data().unwrap(), b"hi");
+
+        let mut out = vec![];
+        write_frame(&mut out, FrameType::Data, PADDED, 1, &[9, 1, 2]);
+        let (_, frame) = parse_frame(&out, 100).unwrap().unwrap();
+        assert!(frame.data().is_err());
+    }
+
+    #[test]
+    fn frame_headers_continuation() {
+        let mut out = vec![];
+        write_headers(&mut out, 1, &[1, 2, 3, 4, 5], true, 2);
+
+        let mut input = &out[..];
+        let mut frames = vec![];
+        while let Some((used, frame)) = parse_frame(input, 100).unwrap() {
+            frames.push((frame.typ, frame.flags, frame.payload.to_vec()));
+            input = &input[used..];
+        }
+
+        assert_eq!(
+            frames,
+            vec![
+                (FrameType::Headers, END_STREAM, vec![1, 2]),
+                (FrameType::Continuation, 0, vec![3, 4]),
+                (FrameType::Continuation, END_HEADERS, vec![5]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,548 @@
+//! HPACK header compression (RFC 7541).
+//!
+//! The encoder never adds to the dynamic table and doesn't use Huffman coding,
+//! which keeps it stateless. The decoder supports everything a server can send.
+
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+use super::frame::h2_error;
+use crate::Error;
+
+/// Max dynamic table size until changed by SETTINGS_HEADER_TABLE_SIZE.
+pub(crate) const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Decoded header fields as (name, value).
+pub(crate) type HeaderFields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Overhead per entry in the dynamic table (RFC 7541 section 4.1).
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Append one header field to a header block.
+pub(crate) fn encode(out: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    let mut name_index = 0;
+
+    for (i, (n, v)) in STATIC_TABLE.iter().enumerate() {
+        if n.as_bytes() == name {
+            if v.as_bytes() == value {
+                // Indexed header field
+                encode_int(out, 0x80, 7, i + 1);
+                return;
+            }
+            if name_index == 0 {
+                name_index = i + 1;
+            }
+        }
+    }
+
+    // Credentials should never be compressed by intermediaries.
+    let never_indexed = matches!(name, b"authorization" | b"proxy-authorization" | b"cookie");
+    let flags = if never_indexed { 0x10 } else { 0x00 };
+
+    // Literal header field without indexing
+    encode_int(out, flags, 4, name_index);
+    if name_index == 0 {
+        encode_str(out, name);
+    }
+    encode_str(out, value);
+}
+
+fn encode_int(out: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+    let max = (1 << prefix) - 1;
+
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 128 {
+        out.push((rest % 128) as u8 | 0x80);
+        rest /= 128;
+    }
+    out.push(rest as u8);
+}
+
+fn encode_str(out: &mut Vec<u8>, s: &[u8]) {
+    encode_int(out, 0, 7, s.len());
+    out.extend_from_slice(s);
+}
+
+/// Decoder of header blocks.
+///
+/// There is one per connection, and header blocks must be decoded in the order they
+/// are received since they update the shared dynamic table.
+pub(crate) struct Decoder {
+    table: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+    max_size_limit: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+            max_size_limit: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// Decode a header block into a list of fields.
+    ///
+    /// Fails if the total size of the fields, counted as in HPACK, is larger than `max_size`.
+    pub fn decode(&mut self, mut block: &[u8], max_size: usize) -> Result<HeaderFields, Error> {
+        let mut fields = Vec::new();
+        let mut total = 0;
+
+        while let Some(first) = block.first().copied() {
+            let (name, value) = if first & 0x80 > 0 {
+                // Indexed header field
+                let index = decode_int(&mut block, 7)?;
+                let (name, value) = self.get(index)?;
+                (name.to_vec(), value.to_vec())
+            } else if first & 0x40 > 0 {
+                // Literal header field with incremental indexing
+                let (name, value) = self.decode_literal(&mut block, 6)?;
+                self.insert(name.clone(), value.clone());
+                (name, value)
+            } else if first & 0x20 > 0 {
+                // Dynamic table size update
+                let size = decode_int(&mut block, 5)?;
+                if size > self.max_size_limit {
+                    return Err(h2_error("hpack table size exceeds limit"));
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // Literal header field without indexing or never indexed
+                self.decode_literal(&mut block, 4)?
+            };
+
+            total += name.len() + value.len() + ENTRY_OVERHEAD;
+            if total > max_size {
+                return Err(h2_error(format!(
+                    "header list exceeds max size of {}",
+                    max_size
+                )));
+            }
+
+            fields.push((name, value));
+        }
+
+        Ok(fields)
+    }
+
+    fn decode_literal(&self, block: &mut &[u8], prefix: u8) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let index = decode_int(block, prefix)?;
+
+        let name = if index == 0 {
+            decode_str(block)?
+        } else {
+            self.get(index)?.0.to_vec()
+        };
+
+        let value = decode_str(block)?;
+
+        Ok((name, value))
+    }
+
+    fn get(&self, index: usize) -> Result<(&[u8], &[u8]), Error> {
+        if index == 0 {
+            return Err(h2_error("hpack index 0"));
+        }
+
+        if let Some((name, value)) = STATIC_TABLE.get(index - 1) {
+            return Ok((name.as_bytes(), value.as_bytes()));
+        }
+
+        let (name, value) = self
+            .table
+            .get(index - 1 - STATIC_TABLE.len())
+            .ok_or_else(|| h2_error(format!("hpack index out of range: {}", index)))?;
+
+        Ok((name, value))
+    }
+
+    fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+
+        self.evict(size);
+
+        // An entry larger than the table empties the table and is not added.
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    /// Evict entries until there is room for `size` more bytes.
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+fn decode_int(block: &mut &[u8], prefix: u8) -> Result<usize, Error> {
+    let max = (1_usize << prefix) - 1;
+
+    let (first, rest) = block
+        .split_first()
+        .ok_or_else(|| h2_error("hpack truncated integer"))?;
+    *block = rest;
+
+    let mut value = *first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (b, rest) = block
+            .split_first()
+            .ok_or_else(|| h2_error("hpack truncated integer"))?;
+        *block = rest;
+
+        if shift > 28 {
+            return Err(h2_error("hpack integer overflow"));
+        }
+
+        value += (*b as usize & 0x7f) << shift;
+        shift += 7;
+
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_str(block: &mut &[u8]) -> Result<Vec<u8>, Error> {
+    let huffman = block.first().map(|b| b & 0x80 > 0).unwrap_or(false);
+    let len = decode_int(block, 7)?;
+
+    if len > block.len() {
+        return Err(h2_error("hpack truncated string"));
+    }
+
+    let (s, rest) = block.split_at(len);
+    *block = rest;
+
+    if huffman {
+        huffman_decode(s)
+    } else {
+        Ok(s.to_vec())
+    }
+}
+
+/// Code lengths of the Huffman code for each symbol 0-256, where 256 is EOS (RFC 7541
+/// appendix B). The code is canonical, which means the lengths are enough to rebuild it.
+#[rustfmt::skip]
+const HUFFMAN_CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+const EOS: u16 = 256;
+const MAX_CODE_LENGTH: usize = 30;
+
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+fn huffman() -> &'static Huffman {
+    static HUFFMAN: OnceLock<Huffman> = OnceLock::new();
+
+    HUFFMAN.get_or_init(|| {
+        let mut counts = [0; MAX_CODE_LENGTH + 1];
+        for len in HUFFMAN_CODE_LENGTHS {
+            counts[len as usize] += 1;
+        }
+
+        // Canonical codes are assigned by length, then by symbol.
+        let mut symbols: Vec<u16> = (0..=EOS).collect();
+        symbols.sort_by_key(|s| HUFFMAN_CODE_LENGTHS[*s as usize]);
+
+        Huffman { counts, symbols }
+    })
+}
+
+fn huffman_decode(src: &[u8]) -> Result<Vec<u8>, Error> {
+    let h = huffman();
+
+    let mut out = Vec::with_capacity(src.len() * 8 / 5);
+
+    let mut code = 0_u32;
+    let mut first = 0_u32;
+    let mut index = 0_usize;
+    let mut len = 0;
+    let mut all_ones = true;
+
+    for byte in src {
+        for shift in (0..8).rev() {
+            let bit = (*byte >> shift) as u32 & 1;
+            code |= bit;
+            len += 1;
+            all_ones &= bit == 1;
+
+            let count = h.counts[len] as u32;
+
+            if code < first + count {
+                let symbol = h.symbols[index + (code - first) as usize];
+                if symbol == EOS {
+                    return Err(h2_error("hpack huffman EOS"));
+                }
+                out.push(symbol as u8);
+
+                code = 0;
+                first = 0;
+                index = 0;
+                len = 0;
+                all_ones = true;
+            } else {
+                if len == MAX_CODE_LENGTH {
+                    return Err(h2_error("hpack bad huffman code"));
+                }
+                index += count as usize;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+        }
+    }
+
+    // Padding is the most significant bits of EOS, which are all ones.
+    if len > 7 || !all_ones {
+        return Err(h2_error("hpack bad huffman padding"));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(v: &[(&str, &str)]) -> HeaderFields {
+        v.iter()
+            .map(|(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn hpack_int() {
+        // RFC 7541 C.1
+        let mut out = vec![];
+        encode_int(&mut out, 0, 5, 10);
+        encode_int(&mut out, 0, 5, 1337);
+        encode_int(&mut out, 0, 8, 42);
+        assert_eq!(out, [0x0a, 0x1f, 0x9a, 0x0a, 0x2a]);
+
+        let mut block = &out[..];
+        assert_eq!(decode_int(&mut block, 5).unwrap(), 10);
+        assert_eq!(decode_int(&mut block, 5).unwrap(), 1337);
+        assert_eq!(decode_int(&mut block, 8).unwrap(), 42);
+    }
+
+    #[test]
+    fn hpack_requests_with_huffman() {
+        // RFC 7541 C.4
+        let mut d = Decoder::new();
+
+        let r = d
+            .decode(&unhex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"), 1000)
+            .unwrap();
+        assert_eq!(
+            r,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+
+        let r = d
+            .decode(&unhex("8286 84be 5886 a8eb 1064 9cbf"), 1000)
+            .unwrap();
+        assert_eq!(
+            r,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+
+        let r = d
+            .decode(
+                &unhex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"),
+                1000,
+            )
+            .unwrap();
+        assert_eq!(
+            r,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])
+        );
+        assert_eq!(d.size, 164);
+    }
+
+    #[test]
+    fn hpack_eviction() {
+        let mut d = Decoder::new();
+        // Table size update to 60, then two literals with indexing of
+        // 4 + 4 + 32 = 40 bytes each. The second evicts the first.
+        let block = unhex("3f1d 4004 6e61 6d65 0476 616c 3140 046e 616d 6504 7661 6c32 be");
+        let r = d.decode(&block, 1000).unwrap();
+        assert_eq!(
+            r,
+            fields(&[("name", "val1"), ("name", "val2"), ("name", "val2")])
+        );
+        assert_eq!(d.table.len(), 1);
+
+        // Index 63 is out of range.
+        assert!(d.decode(&[0xbf], 1000).is_err());
+
+        // Larger than the limit from our settings.
+        assert!(d.decode(&unhex("3fe2 1f"), 1000).is_err());
+    }
+
+    #[test]
+    fn hpack_max_list_size() {
+        // :method GET and :scheme http, each 32 bytes overhead plus name and value.
+        let mut d = Decoder::new();
+        let block = unhex("8286");
+        assert!(d.decode(&block, 2 * 32 + 20).is_err());
+        assert!(d.decode(&block, 2 * 32 + 21).is_ok());
+    }
+
+    #[test]
+    fn hpack_encode_roundtrip() {
+        let mut out = vec![];
+        encode(&mut out, b":method", b"GET");
+        encode(&mut out, b":path", b"/some/path");
+        encode(&mut out, b"authorization", b"secret");
+        encode(&mut out, b"x-custom", b"value");
+
+        assert_eq!(out[0], 0x82);
+        // authorization is never indexed
+        assert_eq!(out[13], 0x1f);
+
+        let r = Decoder::new().decode(&out, 1000).unwrap();
+        assert_eq!(
+            r,
+            fields(&[
+                (":method", "GET"),
+                (":path", "/some/path"),
+                ("authorization", "secret"),
+                ("x-custom", "value"),
+            ])
+        );
+    }
+
+    #[test]
+    fn hpack_huffman_padding() {
+        // "a" is 00011, padded with ones.
+        assert_eq!(huffman_decode(&[0b0001_1111]).unwrap(), b"a");
+        // Padding with a zero.
+        assert!(huffman_decode(&[0b0001_1110]).is_err());
+        // Padding longer than 7 bits.
+        assert!(huffman_decode(&[0b0001_1111, 0xff]).is_err());
+    }
+}
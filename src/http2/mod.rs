@@ -0,0 +1,1243 @@
+//! HTTP/2 (RFC 9113).
+//!
+//! An HTTP/2 connection is shared by many requests. The [`Transport`] of the
+//! connection is split in two, owned by a thread reading frames and a thread
+//! writing them, while each request talks to them via an [`H2Transport`] of its own.
+//!
+//! `H2Transport` is a [`Transport`] that speaks HTTP/1.1 to the rest of ureq.
+//! The request written by hoot is translated to HEADERS and DATA frames, and the
+//! response is translated back to HTTP/1.1 (using chunked transfer encoding when
+//! the length is unknown). That way redirects, body decoding, timeouts and
+//! connection pooling work the same for both HTTP versions.
+//!
+//! This is experimental. The reading thread is always waiting for input, and the
+//! writing thread waits until there is something to send. That needs a transport
+//! that can be split, see [`Transport::split()`].
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::{fmt, thread};
+
+use http::uri::Scheme;
+
+use crate::transport::time::{Duration, NextTimeout};
+use crate::transport::{ConnectionDetails, Transport};
+use crate::{AgentConfig, Error, TimeoutReason};
+
+use self::frame::*;
+use self::hpack::{Decoder, HeaderFields};
+
+mod frame;
+mod hpack;
+mod transport;
+
+pub(crate) use self::transport::H2Transport;
+
+/// The first bytes sent by the client on an HTTP/2 connection.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Flow control window until changed by SETTINGS_INITIAL_WINDOW_SIZE.
+const DEFAULT_WINDOW: u32 = 65_535;
+
+/// Receive window per stream that we announce.
+const STREAM_WINDOW: u32 = 1024 * 1024;
+
+/// Receive window for the entire connection.
+const CONNECTION_WINDOW: u32 = 16 * 1024 * 1024;
+
+/// Whether the request may use HTTP/2, on a pooled or new connection.
+///
+/// Upgrades and CONNECT requests don't offer `h2`, and must not use a pooled
+/// HTTP/2 connection either.
+pub(crate) fn allows_http2(details: &ConnectionDetails) -> bool {
+    if details.needs_tls() {
+        #[cfg(feature = "_tls")]
+        {
+            // native-tls doesn't offer h2.
+            let tls_config = &details.config.tls_config;
+            tls_config.provider == crate::tls::TlsProvider::Rustls
+                && tls_config.alpn_protocols.iter().any(|p| p == "h2")
+        }
+        #[cfg(not(feature = "_tls"))]
+        false
+    } else {
+        details.config.http2_prior_knowledge && details.uri.scheme() == Some(&Scheme::HTTP)
+    }
+}
+
+/// Whether to use HTTP/2 for a newly connected transport.
+///
+/// That is when TLS negotiated `h2` via ALPN, or for `http` with prior knowledge.
+pub(crate) fn use_http2(transport: &dyn Transport, details: &ConnectionDetails) -> bool {
+    if transport.is_tls() {
+        transport.alpn_protocol() == Some(b"h2")
+    } else {
+        details.config.http2_prior_knowledge && details.uri.scheme() == Some(&Scheme::HTTP)
+    }
+}
+
+/// Handle to a shared HTTP/2 connection.
+#[derive(Clone)]
+pub(crate) struct H2Connection {
+    inner: Arc<Inner>,
+    handle: Arc<Handle>,
+}
+
+/// Handle that doesn't keep the connection alive.
+pub(crate) struct WeakH2Connection {
+    handle: Weak<Handle>,
+}
+
+/// Closes the connection when the last [`H2Connection`] is dropped.
+struct Handle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    cond: Condvar,
+    scheme: &'static str,
+    max_header_size: usize,
+    #[cfg(feature = "_tls")]
+    peer_certificates: Option<Vec<crate::tls::Certificate<'static>>>,
+    #[cfg(feature = "_tls")]
+    tls_handshake: Option<crate::tls::TlsHandshake>,
+}
+
+struct State {
+    streams: HashMap<u32, Stream>,
+    next_stream_id: u32,
+
+    /// Frames to send, before DATA of the streams.
+    out: Vec<u8>,
+
+    decoder: Decoder,
+    /// Header block being received in HEADERS and CONTINUATION frames.
+    continuation: Option<(u32, Vec<u8>, bool)>,
+
+    send_window: i64,
+    /// Bytes the peer may send before the next WINDOW_UPDATE.
+    recv_window: i64,
+    /// Received bytes not yet given back with WINDOW_UPDATE.
+    recv_unacked: u32,
+
+    peer_initial_window: u32,
+    peer_max_frame_size: usize,
+    peer_max_concurrent_streams: u32,
+
+    /// The peer sent GOAWAY, no new streams can be opened.
+    goaway: bool,
+    /// The last handle is dropped.
+    closed: bool,
+    /// The connection failed, or was closed.
+    error: Option<String>,
+    /// Error code of the GOAWAY sent when the connection fails.
+    goaway_code: u32,
+}
+
+struct Stream {
+    /// Request body waiting to be sent.
+    data: VecDeque<u8>,
+    /// Send END_STREAM once `data` is sent.
+    end_after_data: bool,
+    send_closed: bool,
+    send_window: i64,
+
+    events: VecDeque<Recv>,
+    recv_closed: bool,
+    recv_window: i64,
+    recv_unacked: u32,
+
+    reset: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Recv {
+    Headers(HeaderFields),
+    Data(Vec<u8>),
+}
+
+impl H2Connection {
+    /// Start using HTTP/2 on the transport.
+    ///
+    /// This spawns the threads reading and writing the connection, which live until
+    /// all handles to the connection are dropped or the connection fails.
+    pub fn new(transport: Box<dyn Transport>, config: &AgentConfig) -> Result<Self, Error> {
+        let mut out = PREFACE.to_vec();
+
+        write_settings(
+            &mut out,
+            &[
+                (SETTINGS_ENABLE_PUSH, 0),
+                (SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW),
+                (
+                    SETTINGS_MAX_HEADER_LIST_SIZE,
+                    config.max_response_header_size as u32,
+                ),
+            ],
+        );
+        write_window_update(&mut out, 0, CONNECTION_WINDOW - DEFAULT_WINDOW);
+
+        let state = State {
+            streams: HashMap::new(),
+            next_stream_id: 1,
+            out,
+            decoder: Decoder::new(),
+            continuation: None,
+            send_window: DEFAULT_WINDOW as i64,
+            recv_window: CONNECTION_WINDOW as i64,
+            recv_unacked: 0,
+            peer_initial_window: DEFAULT_WINDOW,
+            peer_max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            peer_max_concurrent_streams: u32::MAX,
+            goaway: false,
+            closed: false,
+            error: None,
+            goaway_code: PROTOCOL_ERROR,
+        };
+
+        let inner = Arc::new(Inner {
+            state: Mutex::new(state),
+            cond: Condvar::new(),
+            scheme: if transport.is_tls() { "https" } else { "http" },
+            max_header_size: config.max_response_header_size,
+            #[cfg(feature = "_tls")]
+            peer_certificates: transport.peer_certificates(),
+            #[cfg(feature = "_tls")]
+            tls_handshake: transport.tls_handshake(),
+        });
+
+        let (reader, writer) = transport
+            .split()
+            .ok_or_else(|| h2_error("transport can't be split for reading and writing"))?;
+
+        debug!("Start HTTP/2 connection");
+
+        let inner2 = inner.clone();
+        thread::spawn(move || run_reader(inner2, reader));
+        let inner2 = inner.clone();
+        thread::spawn(move || run_writer(inner2, writer));
+
+        Ok(H2Connection {
+            handle: Arc::new(Handle {
+                inner: inner.clone(),
+            }),
+            inner,
+        })
+    }
+
+    pub fn downgrade(&self) -> WeakH2Connection {
+        WeakH2Connection {
+            handle: Arc::downgrade(&self.handle),
+        }
+    }
+
+    /// Whether new streams can be opened on the connection.
+    ///
+    /// The reading thread notices when the server sends GOAWAY or closes the
+    /// connection, which means this doesn't touch the transport.
+    pub fn is_open(&self) -> bool {
+        let state = self.inner.lock();
+        state.error.is_none() && !state.goaway
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        self.inner.scheme
+    }
+
+    #[cfg(feature = "_tls")]
+    pub fn peer_certificates(&self) -> Option<Vec<crate::tls::Certificate<'static>>> {
+        self.inner.peer_certificates.clone()
+    }
+
+    #[cfg(feature = "_tls")]
+    pub fn tls_handshake(&self) -> Option<crate::tls::TlsHandshake> {
+        self.inner.tls_handshake
+    }
+
+    /// Open a stream by sending the request header block.
+    pub fn open_stream(
+        &self,
+        block: &[u8],
+        end_stream: bool,
+        timeout: NextTimeout,
+    ) -> Result<u32, Error> {
+        let mut state = self.inner.lock();
+
+        loop {
+            state.check_open()?;
+            if (state.streams.len() as u32) < state.peer_max_concurrent_streams {
+                break;
+            }
+            state = self.inner.wait(state, timeout)?;
+        }
+
+        let id = state.next_stream_id;
+        state.next_stream_id += 2;
+
+        if state.next_stream_id > i32::MAX as u32 {
+            // Ids are used up, this connection will not be reused.
+            state.goaway = true;
+        }
+
+        let max_frame_size = state.peer_max_frame_size;
+        write_headers(&mut state.out, id, block, end_stream, max_frame_size);
+
+        let stream = Stream {
+            data: VecDeque::new(),
+            end_after_data: false,
+            send_closed: end_stream,
+            send_window: state.peer_initial_window as i64,
+            events: VecDeque::new(),
+            recv_closed: false,
+            recv_window: STREAM_WINDOW as i64,
+            recv_unacked: 0,
+            reset: None,
+        };
+        state.streams.insert(id, stream);
+
+        trace!("Open stream {}", id);
+        self.inner.cond.notify_all();
+
+        Ok(id)
+    }
+
+    /// Send request body data. Blocks until the data is written.
+    pub fn send_data(
+        &self,
+        id: u32,
+        data: &[u8],
+        end_stream: bool,
+        timeout: NextTimeout,
+    ) -> Result<(), Error> {
+        let mut state = self.inner.lock();
+
+        let stream = state.stream(id)?;
+        if stream.send_closed {
+            // The server might have reset the stream after a complete response.
+            return Ok(());
+        }
+        stream.data.extend(data);
+        stream.end_after_data = end_stream;
+        self.inner.cond.notify_all();
+
+        loop {
+            state.check_error()?;
+
+            let stream = state.stream(id)?;
+            if stream.data.is_empty() && (!end_stream || stream.send_closed) {
+                return Ok(());
+            }
+
+            state = self.inner.wait(state, timeout)?;
+        }
+    }
+
+    /// Receive the next event of the stream.
+    ///
+    /// Returns `None` when the stream has ended.
+    pub fn recv(&self, id: u32, timeout: NextTimeout) -> Result<Option<Recv>, Error> {
+        let mut state = self.inner.lock();
+
+        loop {
+            let stream = state.stream(id)?;
+
+            if let Some(event) = stream.events.pop_front() {
+                if let Recv::Data(data) = &event {
+                    state.ack_data(id, data.len() as u32);
+                    self.inner.cond.notify_all();
+                }
+                return Ok(Some(event));
+            }
+
+            if stream.recv_closed {
+                return Ok(None);
+            }
+
+            state.check_error()?;
+            state = self.inner.wait(state, timeout)?;
+        }
+    }
+
+    /// Forget about a stream, resetting it unless it is complete.
+    pub fn close_stream(&self, id: u32) {
+        let mut state = self.inner.lock();
+
+        let Some(stream) = state.streams.remove(&id) else {
+            return;
+        };
+
+        if stream.reset.is_none() && !(stream.send_closed && stream.recv_closed) {
+            trace!("Reset stream {}", id);
+            write_rst_stream(&mut state.out, id, CANCEL);
+        }
+
+        self.inner.cond.notify_all();
+    }
+}
+
+impl WeakH2Connection {
+    pub fn upgrade(&self) -> Option<H2Connection> {
+        let handle = self.handle.upgrade()?;
+        Some(H2Connection {
+            inner: handle.inner.clone(),
+            handle,
+        })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.handle.strong_count() > 0
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let mut state = self.inner.lock();
+        state.closed = true;
+        self.inner.cond.notify_all();
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn wait<'a>(
+        &self,
+        state: MutexGuard<'a, State>,
+        timeout: NextTimeout,
+    ) -> Result<MutexGuard<'a, State>, Error> {
+        let Some(after) = timeout.not_zero() else {
+            return Ok(self.cond.wait(state).unwrap());
+        };
+
+        let (state, result) = self.cond.wait_timeout(state, *after).unwrap();
+
+        if result.timed_out() {
+            return Err(Error::Timeout(timeout.reason));
+        }
+
+        Ok(state)
+    }
+}
+
+impl State {
+    fn stream(&mut self, id: u32) -> Result<&mut Stream, Error> {
+        let stream = self
+            .streams
+            .get_mut(&id)
+            .ok_or_else(|| h2_error(format!("stream {} is gone", id)))?;
+
+        if let Some(reason) = &stream.reset {
+            return Err(h2_error(reason.clone()));
+        }
+
+        Ok(stream)
+    }
+
+    fn check_error(&self) -> Result<(), Error> {
+        if let Some(e) = &self.error {
+            return Err(h2_error(e.clone()));
+        }
+        Ok(())
+    }
+
+    fn check_open(&self) -> Result<(), Error> {
+        self.check_error()?;
+        if self.goaway {
+            return Err(h2_error("connection is going away"));
+        }
+        Ok(())
+    }
+
+    /// Give back receive window for data that has been taken by a stream.
+    fn ack_data(&mut self, id: u32, len: u32) {
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.recv_unacked += len;
+
+            if !stream.recv_closed && stream.recv_unacked >= STREAM_WINDOW / 2 {
+                write_window_update(&mut self.out, id, stream.recv_unacked);
+                stream.recv_window += stream.recv_unacked as i64;
+                stream.recv_unacked = 0;
+            }
+        }
+
+        self.ack_connection_data(len);
+    }
+
+    fn ack_connection_data(&mut self, len: u32) {
+        self.recv_unacked += len;
+
+        if self.recv_unacked >= CONNECTION_WINDOW / 2 {
+            write_window_update(&mut self.out, 0, self.recv_unacked);
+            self.recv_window += self.recv_unacked as i64;
+            self.recv_unacked = 0;
+        }
+    }
+
+    /// Take all frames that can be sent now.
+    fn take_output(&mut self) -> Vec<u8> {
+        let mut out = std::mem::take(&mut self.out);
+
+        let mut ids: Vec<u32> = self.streams.keys().copied().collect();
+        ids.sort_unstable();
+
+        for id in ids {
+            let stream = self.streams.get_mut(&id).unwrap();
+
+            while !stream.send_closed && stream.reset.is_none() {
+                if stream.data.is_empty() {
+                    if stream.end_after_data {
+                        write_frame(&mut out, FrameType::Data, END_STREAM, id, &[]);
+                        stream.send_closed = true;
+                    }
+                    break;
+                }
+
+                let window = stream.send_window.min(self.send_window).max(0) as usize;
+                let len = stream.data.len().min(window).min(self.peer_max_frame_size);
+
+                if len == 0 {
+                    // Wait for WINDOW_UPDATE.
+                    break;
+                }
+
+                let chunk: Vec<u8> = stream.data.drain(..len).collect();
+                let end = stream.data.is_empty() && stream.end_after_data;
+                let flags = if end { END_STREAM } else { 0 };
+
+                write_frame(&mut out, FrameType::Data, flags, id, &chunk);
+
+                stream.send_window -= len as i64;
+                self.send_window -= len as i64;
+                stream.send_closed = end;
+            }
+        }
+
+        out
+    }
+
+    fn handle_frame(&mut self, frame: Frame, max_header_size: usize) -> Result<(), Error> {
+        if let Some((id, _, _)) = &self.continuation {
+            if frame.typ != FrameType::Continuation || frame.stream_id != *id {
+                return Err(h2_error("expected CONTINUATION"));
+            }
+        }
+
+        match frame.typ {
+            FrameType::Data => {
+                if frame.stream_id == 0 {
+                    return Err(h2_error("DATA on stream 0"));
+                }
+
+                let data = frame.data()?;
+                let end = frame.has(END_STREAM);
+
+                // The entire payload counts towards flow control, including padding.
+                let len = frame.payload.len();
+
+                if len as i64 > self.recv_window {
+                    self.goaway_code = FLOW_CONTROL_ERROR;
+                    return Err(h2_error("DATA exceeds the connection flow control window"));
+                }
+                self.recv_window -= len as i64;
+
+                // Padding is never taken by a stream.
+                let padding = len - data.len();
+
+                match self.streams.get_mut(&frame.stream_id) {
+                    Some(stream) if !stream.recv_closed && stream.reset.is_none() => {
+                        if len as i64 > stream.recv_window {
+                            debug!("Stream {} exceeds flow control window", frame.stream_id);
+                            write_rst_stream(&mut self.out, frame.stream_id, FLOW_CONTROL_ERROR);
+                            stream.reset = Some("stream flow control window exceeded".into());
+                            self.ack_connection_data(len as u32);
+                            return Ok(());
+                        }
+                        stream.recv_window -= len as i64;
+
+                        if !data.is_empty() {
+                            stream.events.push_back(Recv::Data(data.to_vec()));
+                        }
+                        stream.recv_closed = end;
+                        stream.recv_unacked += padding as u32;
+                        self.ack_connection_data(padding as u32);
+                    }
+                    _ => {
+                        // Stream is gone.
+                        self.ack_connection_data(len as u32);
+                    }
+                }
+            }
+
+            FrameType::Headers | FrameType::Continuation => {
+                let (id, mut block, end) = if frame.typ == FrameType::Headers {
+                    if frame.stream_id == 0 {
+                        return Err(h2_error("HEADERS on stream 0"));
+                    }
+                    (frame.stream_id, vec![], frame.has(END_STREAM))
+                } else {
+                    self.continuation
+                        .take()
+                        .ok_or_else(|| h2_error("unexpected CONTINUATION"))?
+                };
+
+                if frame.typ == FrameType::Headers {
+                    block.extend_from_slice(frame.data()?);
+                } else {
+                    block.extend_from_slice(frame.payload);
+                }
+
+                if block.len() > max_header_size {
+                    return Err(h2_error(format!(
+                        "header block exceeds max size of {}",
+                        max_header_size
+                    )));
+                }
+
+                if !frame.has(END_HEADERS) {
+                    self.continuation = Some((id, block, end));
+                    return Ok(());
+                }
+
+                // The block must be decoded even when the stream is gone, since the
+                // decoder state is shared by the connection.
+                let fields = self
+                    .decoder
+                    .decode(&block, max_header_size)
+                    .map_err(|e| h2_error(format!("compression error: {}", e)))?;
+
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    if !stream.recv_closed {
+                        stream.events.push_back(Recv::Headers(fields));
+                        stream.recv_closed = end;
+                    }
+                }
+            }
+
+            FrameType::RstStream => {
+                let code = parse_u32(frame.payload)?;
+                if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                    debug!("Stream {} reset by peer: {}", frame.stream_id, code);
+                    stream.reset = Some(format!("stream reset by peer with code {}", code));
+                }
+            }
+
+            FrameType::Settings => {
+                if frame.has(ACK) {
+                    return Ok(());
+                }
+
+                for (id, value) in parse_settings(frame.payload)? {
+                    self.apply_setting(id, value)?;
+                }
+
+                write_frame(&mut self.out, FrameType::Settings, ACK, 0, &[]);
+            }
+
+            FrameType::PushPromise => {
+                return Err(h2_error("PUSH_PROMISE when push is disabled"));
+            }
+
+            FrameType::Ping if !frame.has(ACK) => {
+                write_frame(&mut self.out, FrameType::Ping, ACK, 0, frame.payload);
+            }
+
+            FrameType::GoAway => {
+                let last_id = parse_u32(frame.payload.get(..4).unwrap_or(&[]))? & 0x7fff_ffff;
+                debug!("GOAWAY with last stream {}", last_id);
+
+                self.goaway = true;
+
+                // Streams the server will not process.
+                for (id, stream) in &mut self.streams {
+                    if *id > last_id && stream.reset.is_none() {
+                        stream.reset = Some("stream refused by GOAWAY".into());
+                    }
+                }
+            }
+
+            FrameType::WindowUpdate => {
+                let increment = parse_u32(frame.payload)? & 0x7fff_ffff;
+
+                if frame.stream_id == 0 {
+                    if increment == 0 {
+                        return Err(h2_error("WINDOW_UPDATE of 0"));
+                    }
+                    self.send_window += increment as i64;
+                    if self.send_window > i32::MAX as i64 {
+                        return Err(h2_error("flow control window overflow"));
+                    }
+                } else if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                    stream.send_window += increment as i64;
+                    if increment == 0 || stream.send_window > i32::MAX as i64 {
+                        let code = if increment == 0 {
+                            PROTOCOL_ERROR
+                        } else {
+                            FLOW_CONTROL_ERROR
+                        };
+                        write_rst_stream(&mut self.out, frame.stream_id, code);
+                        stream.reset = Some("bad WINDOW_UPDATE".into());
+                    }
+                }
+            }
+
+            // PRIORITY and unknown frames are ignored.
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn apply_setting(&mut self, id: u16, value: u32) -> Result<(), Error> {
+        match id {
+            SETTINGS_INITIAL_WINDOW_SIZE => {
+                if value > i32::MAX as u32 {
+                    return Err(h2_error("initial window size too large"));
+                }
+                let delta = value as i64 - self.peer_initial_window as i64;
+                for stream in self.streams.values_mut() {
+                    stream.send_window += delta;
+                }
+                self.peer_initial_window = value;
+            }
+            SETTINGS_MAX_FRAME_SIZE => {
+                if !(DEFAULT_MAX_FRAME_SIZE as u32..=0xff_ffff).contains(&value) {
+                    return Err(h2_error("bad max frame size"));
+                }
+                self.peer_max_frame_size = value as usize;
+            }
+            SETTINGS_MAX_CONCURRENT_STREAMS => {
+                self.peer_max_concurrent_streams = value;
+            }
+            // We never add to the encoder's dynamic table, which means the table size
+            // doesn't matter.
+            SETTINGS_HEADER_TABLE_SIZE => {}
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// The thread reading the connection.
+fn run_reader(inner: Arc<Inner>, mut transport: Box<dyn Transport>) {
+    let e = read_loop(&inner, &mut *transport);
+
+    let mut state = inner.lock();
+
+    // Unless the connection is already closed, which ends the read.
+    if state.error.is_none() {
+        debug!("HTTP/2 connection failed: {}", e);
+        let code = state.goaway_code;
+        write_goaway(&mut state.out, 0, code);
+        state.error = Some(e.to_string());
+    }
+
+    inner.cond.notify_all();
+}
+
+/// Wait for input and handle the frames received, until the connection fails.
+fn read_loop(inner: &Inner, transport: &mut dyn Transport) -> Error {
+    let timeout = NextTimeout {
+        after: Duration::NotHappening,
+        reason: TimeoutReason::Global,
+    };
+
+    loop {
+        match transport.await_input(timeout) {
+            Ok(true) => {}
+            Ok(false) => return Error::disconnected(),
+            Err(e) => return e,
+        }
+
+        let mut state = inner.lock();
+        let mut used = 0;
+
+        let result = loop {
+            let input = &transport.buffers().input()[used..];
+
+            // We never raise SETTINGS_MAX_FRAME_SIZE.
+            let (amount, frame) = match parse_frame(input, DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some(v)) => v,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+
+            if let Err(e) = state.handle_frame(frame, inner.max_header_size) {
+                break Err(e);
+            }
+
+            used += amount;
+        };
+
+        transport.buffers().consume(used);
+        inner.cond.notify_all();
+
+        if let Err(e) = result {
+            return e;
+        }
+    }
+}
+
+/// The thread writing the connection.
+fn run_writer(inner: Arc<Inner>, mut transport: Box<dyn Transport>) {
+    let result = write_loop(&inner, &mut *transport);
+
+    let mut state = inner.lock();
+
+    let reason = match result {
+        Ok(()) => "connection closed".to_string(),
+        Err(e) => {
+            debug!("HTTP/2 connection failed: {}", e);
+            e.to_string()
+        }
+    };
+    state.error.get_or_insert(reason);
+
+    inner.cond.notify_all();
+
+    // Dropping the transport closes the connection, which ends the reading thread.
+}
+
+/// Send frames whenever there are any, until the connection is closed or fails.
+fn write_loop(inner: &Inner, transport: &mut dyn Transport) -> Result<(), Error> {
+    let mut state = inner.lock();
+
+    loop {
+        if state.closed || state.error.is_some() {
+            if state.error.is_none() {
+                write_goaway(&mut state.out, 0, NO_ERROR);
+            }
+            // The GOAWAY, if any, and nothing of the streams.
+            let out = std::mem::take(&mut state.out);
+            drop(state);
+            debug!("Close HTTP/2 connection");
+            // Best effort, the connection is closed anyway.
+            let _ = write_all(transport, &out);
+            return Ok(());
+        }
+
+        let out = state.take_output();
+
+        if out.is_empty() {
+            // Until there is something to send, or the connection is closed.
+            state = inner.cond.wait(state).unwrap();
+            continue;
+        }
+
+        drop(state);
+        write_all(transport, &out)?;
+        state = inner.lock();
+
+        // Tell streams that their data has been sent.
+        inner.cond.notify_all();
+    }
+}
+
+fn write_all(transport: &mut dyn Transport, mut data: &[u8]) -> Result<(), Error> {
+    let timeout = NextTimeout {
+        after: Duration::NotHappening,
+        reason: TimeoutReason::Global,
+    };
+
+    while !data.is_empty() {
+        let output = transport.buffers().output_mut();
+        let max = output.len().min(data.len());
+        output[..max].copy_from_slice(&data[..max]);
+        transport.transmit_output(max, timeout)?;
+        data = &data[max..];
+    }
+
+    Ok(())
+}
+
+impl fmt::Debug for WeakH2Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeakH2Connection").finish()
+    }
+}
+
+impl fmt::Debug for H2Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("H2Connection")
+            .field("scheme", &self.inner.scheme)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time;
+
+    use super::*;
+    use crate::test::{init_test_log, FixedResolver};
+    use crate::transport::TcpConnector;
+    use crate::Agent;
+
+    /// Scripted HTTP/2 server answering each request with "<method> <path> <body length>".
+    ///
+    /// The scheme and authority of each request must start with `base`.
+    pub(crate) fn serve(mut sock: impl Read + Write, base: &str) {
+        let mut preface = [0; 24];
+        sock.read_exact(&mut preface).unwrap();
+        assert_eq!(preface, PREFACE);
+
+        let mut out = vec![];
+        write_settings(&mut out, &[(SETTINGS_MAX_CONCURRENT_STREAMS, 2)]);
+        sock.write_all(&out).unwrap();
+
+        let mut decoder = Decoder::new();
+        let mut requests: HashMap<u32, (HeaderFields, usize)> = HashMap::new();
+        let mut buf = vec![];
+        let mut tmp = vec![0; 16384];
+
+        loop {
+            let n = match sock.read(&mut tmp) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            buf.extend_from_slice(&tmp[..n]);
+
+            let mut out = vec![];
+
+            while let Some((used, frame)) = parse_frame(&buf, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                let id = frame.stream_id;
+                let end = frame.has(END_STREAM);
+
+                match frame.typ {
+                    FrameType::Settings if !frame.has(ACK) => {
+                        write_frame(&mut out, FrameType::Settings, ACK, 0, &[]);
+                    }
+                    FrameType::Headers => {
+                        assert!(frame.has(END_HEADERS));
+                        let fields = decoder.decode(frame.data().unwrap(), 65536).unwrap();
+                        requests.insert(id, (fields, 0));
+                    }
+                    FrameType::Data => {
+                        let len = frame.payload.len();
+                        requests.get_mut(&id).unwrap().1 += len;
+                        if len > 0 {
+                            write_window_update(&mut out, 0, len as u32);
+                            write_window_update(&mut out, id, len as u32);
+                        }
+                    }
+                    _ => {}
+                }
+
+                if end && matches!(frame.typ, FrameType::Headers | FrameType::Data) {
+                    let (fields, len) = requests.remove(&id).unwrap();
+                    respond(&mut out, id, &fields, len, base);
+                }
+
+                buf.drain(..used);
+            }
+
+            sock.write_all(&out).unwrap();
+            sock.flush().unwrap();
+        }
+    }
+
+    fn respond(out: &mut Vec<u8>, id: u32, fields: &HeaderFields, len: usize, base: &str) {
+        let get = |name: &str| {
+            let v = &fields.iter().find(|(n, _)| n == name.as_bytes()).unwrap().1;
+            String::from_utf8(v.clone()).unwrap()
+        };
+
+        // Pseudo-headers first, no HTTP/1.1 connection headers.
+        assert_eq!(fields[0].0, b":method");
+        let origin = format!("{}://{}", get(":scheme"), get(":authority"));
+        assert!(origin.starts_with(base), "{} for {}", origin, base);
+        assert!(!fields
+            .iter()
+            .any(|(n, _)| n == b"host" || n == b"connection"));
+
+        let path = get(":path");
+        let body = format!("{} {} {}", get(":method"), path, len);
+
+        let mut block = vec![];
+        hpack::encode(&mut block, b":status", b"200");
+        // Uppercase is not allowed in HTTP/2, but we normalize it.
+        hpack::encode(&mut block, b"X-Served-By", b"test");
+        if path == "/len" {
+            let l = body.len().to_string();
+            hpack::encode(&mut block, b"Content-Length", l.as_bytes());
+        }
+        write_headers(out, id, &block, false, DEFAULT_MAX_FRAME_SIZE);
+
+        let (a, b) = body.as_bytes().split_at(3);
+        write_frame(out, FrameType::Data, 0, id, a);
+        write_frame(out, FrameType::Data, END_STREAM, id, b);
+    }
+
+    #[test]
+    fn http2_prior_knowledge() {
+        init_test_log();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let base = format!("http://{}", addr);
+
+        let server = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            serve(sock, "http://127.0.0.1:");
+            listener
+        });
+
+        let config = AgentConfig {
+            http2_prior_knowledge: true,
+            proxy: None,
+            ..Default::default()
+        };
+        let agent = Agent::with_parts(config, TcpConnector::default(), FixedResolver(addr));
+
+        let url = |path: &str| format!("{}{}", base, path);
+
+        let get = |agent: &Agent, url: String| {
+            let res = agent.get(&url).call().unwrap();
+            assert_eq!(res.headers().get("x-served-by").unwrap(), "test");
+            res.into_body().read_to_string().unwrap()
+        };
+
+        // Without content-length, i.e. translated to chunked.
+        assert_eq!(get(&agent, url("/a?b=c")), "GET /a?b=c 0");
+        // With content-length.
+        assert_eq!(get(&agent, url("/len")), "GET /len 0");
+
+        // Larger than the default flow control window.
+        let res = agent
+            .post(&url("/upload"))
+            .send(&vec![7; 100_000][..])
+            .unwrap();
+        assert_eq!(
+            res.into_body().read_to_string().unwrap(),
+            "POST /upload 100000"
+        );
+
+        // More than the server's max concurrent streams.
+        let handles: Vec<_> = (0..5)
+            .map(|i| {
+                let agent = agent.clone();
+                let url = url(&format!("/{}", i));
+                thread::spawn(move || get(&agent, url))
+            })
+            .collect();
+        for (i, h) in handles.into_iter().enumerate() {
+            assert_eq!(h.join().unwrap(), format!("GET /{} 0", i));
+        }
+
+        // Closing the last handle closes the connection.
+        drop(agent);
+        let listener = server.join().unwrap();
+
+        // All requests were made on the one connection.
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn http2_flow_control_exceeded() {
+        init_test_log();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Sends more DATA than the stream window, and returns the RST_STREAM error code.
+        let server = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+
+            let mut preface = [0; 24];
+            sock.read_exact(&mut preface).unwrap();
+
+            let mut out = vec![];
+            write_settings(&mut out, &[]);
+            sock.write_all(&out).unwrap();
+
+            let mut buf = vec![];
+            let mut tmp = vec![0; 16384];
+
+            loop {
+                let n = sock.read(&mut tmp).unwrap();
+                assert!(n > 0, "connection closed without RST_STREAM");
+                buf.extend_from_slice(&tmp[..n]);
+
+                let mut out = vec![];
+
+                while let Some((used, frame)) = parse_frame(&buf, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                    match frame.typ {
+                        FrameType::Headers => {
+                            let mut block = vec![];
+                            hpack::encode(&mut block, b":status", b"200");
+                            write_headers(&mut out, frame.stream_id, &block, false, 16384);
+
+                            let chunk = vec![0; DEFAULT_MAX_FRAME_SIZE];
+                            let frames = STREAM_WINDOW as usize / chunk.len() + 1;
+                            for _ in 0..frames {
+                                write_frame(&mut out, FrameType::Data, 0, frame.stream_id, &chunk);
+                            }
+                        }
+                        FrameType::RstStream => {
+                            return parse_u32(frame.payload).unwrap();
+                        }
+                        _ => {}
+                    }
+                    buf.drain(..used);
+                }
+
+                sock.write_all(&out).unwrap();
+            }
+        });
+
+        let config = AgentConfig {
+            http2_prior_knowledge: true,
+            proxy: None,
+            ..Default::default()
+        };
+        let agent = Agent::with_parts(config, TcpConnector::default(), FixedResolver(addr));
+
+        // The body is not read until the server has the RST_STREAM, since reading
+        // it gives back window. The reset might also come before the response.
+        let result = agent.get(&format!("http://{}/", addr)).call();
+        assert_eq!(server.join().unwrap(), FLOW_CONTROL_ERROR);
+
+        let result = result.and_then(|res| res.into_body().read_to_vec());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn http2_idle_ping() {
+        init_test_log();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Answers one request, then pings the idle connection and returns the ack.
+        let server = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            sock.set_read_timeout(Some(time::Duration::from_secs(10)))
+                .unwrap();
+
+            let mut preface = [0; 24];
+            sock.read_exact(&mut preface).unwrap();
+
+            let mut out = vec![];
+            write_settings(&mut out, &[]);
+            sock.write_all(&out).unwrap();
+
+            let mut buf = vec![];
+            let mut tmp = vec![0; 16384];
+            let mut ping = false;
+
+            loop {
+                let n = sock.read(&mut tmp).unwrap();
+                assert!(n > 0, "connection closed without PING ack");
+                buf.extend_from_slice(&tmp[..n]);
+
+                let mut out = vec![];
+
+                while let Some((used, frame)) = parse_frame(&buf, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                    match frame.typ {
+                        FrameType::Headers => {
+                            let mut block = vec![];
+                            hpack::encode(&mut block, b":status", b"200");
+                            hpack::encode(&mut block, b"content-length", b"2");
+                            write_headers(&mut out, frame.stream_id, &block, false, 16384);
+                            write_frame(&mut out, FrameType::Data, END_STREAM, 1, b"ok");
+                            ping = true;
+                        }
+                        FrameType::Ping if frame.has(ACK) => {
+                            return frame.payload.to_vec();
+                        }
+                        _ => {}
+                    }
+                    buf.drain(..used);
+                }
+
+                sock.write_all(&out).unwrap();
+
+                if ping {
+                    ping = false;
+                    // Once the connection is idle.
+                    thread::sleep(time::Duration::from_millis(100));
+                    let mut out = vec![];
+                    write_frame(&mut out, FrameType::Ping, 0, 0, b"12345678");
+                    sock.write_all(&out).unwrap();
+                }
+            }
+        });
+
+        let config = AgentConfig {
+            http2_prior_knowledge: true,
+            proxy: None,
+            ..Default::default()
+        };
+        let agent = Agent::with_parts(config, TcpConnector::default(), FixedResolver(addr));
+
+        let res = agent.get(&format!("http://{}/", addr)).call().unwrap();
+        assert_eq!(res.into_body().read_to_string().unwrap(), "ok");
+
+        assert_eq!(server.join().unwrap(), b"12345678");
+    }
+
+    #[test]
+    fn http2_server_close_noticed() {
+        use crate::transport::{LazyBuffers, TcpTransport};
+
+        init_test_log();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Closes the connection once it has the request.
+        let server = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+
+            let mut preface = [0; 24];
+            sock.read_exact(&mut preface).unwrap();
+
+            let mut out = vec![];
+            write_settings(&mut out, &[]);
+            sock.write_all(&out).unwrap();
+
+            let mut buf = vec![];
+            let mut tmp = vec![0; 16384];
+
+            loop {
+                let n = sock.read(&mut tmp).unwrap();
+                assert!(n > 0, "connection closed without HEADERS");
+                buf.extend_from_slice(&tmp[..n]);
+
+                while let Some((used, frame)) = parse_frame(&buf, DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                    if frame.typ == FrameType::Headers {
+                        return;
+                    }
+                    buf.drain(..used);
+                }
+            }
+        });
+
+        let sock = TcpStream::connect(addr).unwrap();
+        let transport = TcpTransport::new(sock, LazyBuffers::new(16384, 16384));
+        let h2 = H2Connection::new(Box::new(transport), &AgentConfig::default()).unwrap();
+        assert!(h2.is_open());
+
+        let mut block = vec![];
+        hpack::encode(&mut block, b":method", b"GET");
+        hpack::encode(&mut block, b":scheme", b"http");
+        hpack::encode(&mut block, b":authority", b"localhost");
+        hpack::encode(&mut block, b":path", b"/");
+
+        let timeout = NextTimeout {
+            after: Duration::NotHappening,
+            reason: TimeoutReason::Global,
+        };
+        let id = h2.open_stream(&block, true, timeout).unwrap();
+        server.join().unwrap();
+
+        // The reading thread wakes up the stream.
+        assert!(h2.recv(id, timeout).is_err());
+        assert!(!h2.is_open());
+    }
+}
@@ -0,0 +1,501 @@
+use std::fmt;
+
+use http::{header, HeaderName, HeaderValue, StatusCode, Uri};
+
+use crate::transport::time::NextTimeout;
+use crate::transport::{Buffers, LazyBuffers, Transport};
+use crate::Error;
+
+use super::frame::h2_error;
+use super::{hpack, H2Connection, Recv};
+
+/// Headers that are specific to the HTTP/1.1 connection and must not be sent over HTTP/2.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+];
+
+/// A [`Transport`] for one request at a time over a shared [`H2Connection`].
+///
+/// The HTTP/1.1 request written to the output buffers is turned into a stream,
+/// and the stream's response is presented as HTTP/1.1 in the input buffers.
+pub(crate) struct H2Transport {
+    conn: H2Connection,
+    /// Host and port for :authority.
+    authority: String,
+    host: String,
+    buffers: LazyBuffers,
+    stream_id: Option<u32>,
+    send: Send,
+    recv: RecvState,
+}
+
+enum Send {
+    /// Collecting the request head.
+    Head(Vec<u8>),
+    /// Sending a body with content-length.
+    Length(u64),
+    /// Sending a chunked body, which we dechunk.
+    Chunked(Chunked),
+    /// The request is sent.
+    Done,
+}
+
+enum Chunked {
+    /// Reading the chunk size line.
+    Size(Vec<u8>),
+    /// Chunk data left.
+    Data(u64),
+    /// Skipping the \r\n after chunk data.
+    DataEnd(usize),
+    /// Reading trailer lines until an empty line.
+    Trailer(Vec<u8>),
+}
+
+#[derive(Default)]
+struct RecvState {
+    /// Translated response waiting to fit the input buffer.
+    pending: Vec<u8>,
+    /// Whether we got the final (non 1xx) response head.
+    got_head: bool,
+    /// Whether the response body is chunked by us.
+    chunked: bool,
+    /// Whether the request is HEAD, which means the response has no body.
+    is_head: bool,
+    done: bool,
+}
+
+impl H2Transport {
+    pub fn new(
+        conn: H2Connection,
+        uri: &Uri,
+        input_buffer_size: usize,
+        output_buffer_size: usize,
+    ) -> Self {
+        let authority = uri.authority().expect("uri with authority");
+        let host = authority.host().to_string();
+
+        H2Transport {
+            conn,
+            authority: match authority.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.clone(),
+            },
+            host,
+            buffers: LazyBuffers::new(input_buffer_size, output_buffer_size),
+            stream_id: None,
+            send: Send::Done,
+            recv: RecvState::default(),
+        }
+    }
+
+    fn start_request(&mut self) {
+        if let Some(id) = self.stream_id.take() {
+            self.conn.close_stream(id);
+        }
+        self.send = Send::Head(vec![]);
+        self.recv = RecvState::default();
+    }
+
+    fn send_bytes(&mut self, mut input: &[u8], timeout: NextTimeout) -> Result<(), Error> {
+        if matches!(self.send, Send::Done) {
+            // A new request on a reused transport.
+            self.start_request();
+        }
+
+        while !input.is_empty() {
+            match &mut self.send {
+                Send::Head(head) => {
+                    // Look for the end of the head, allowing for it to be split
+                    // over several writes.
+                    let before = head.len();
+                    head.extend_from_slice(input);
+
+                    let Some(pos) = head.windows(4).position(|w| w == b"\r\n\r\n") else {
+                        return Ok(());
+                    };
+
+                    let end = pos + 4;
+                    input = &input[end - before..];
+
+                    let head = std::mem::take(head);
+                    self.send_head(&head[..end], timeout)?;
+                }
+                Send::Length(left) => {
+                    let len = (*left).min(input.len() as u64) as usize;
+                    *left -= len as u64;
+                    let end = *left == 0;
+                    if end {
+                        self.send = Send::Done;
+                    }
+                    self.send_data(&input[..len], end, timeout)?;
+                    input = &input[len..];
+                }
+                Send::Chunked(chunked) => {
+                    let (used, data, end) = chunked.dechunk(input)?;
+                    if end {
+                        self.send = Send::Done;
+                    }
+                    if !data.is_empty() || end {
+                        self.send_data(data, end, timeout)?;
+                    }
+                    input = &input[used..];
+                }
+                Send::Done => {
+                    return Err(h2_error("data after end of request"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_head(&mut self, head: &[u8], timeout: NextTimeout) -> Result<(), Error> {
+        let head = std::str::from_utf8(head).map_err(|_| h2_error("request head is not utf-8"))?;
+        let mut lines = head.split("\r\n").filter(|l| !l.is_empty());
+
+        let request_line = lines.next().unwrap_or_default();
+        let mut parts = request_line.split(' ');
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+
+        if method == "CONNECT" {
+            return Err(h2_error("CONNECT requires HTTP/1.1"));
+        }
+
+        let mut authority = None;
+        let mut content_length = None;
+        let mut chunked = false;
+        let mut fields = vec![];
+
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(h2_error("bad request header"));
+            };
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim();
+
+            match name.as_str() {
+                "host" => authority = Some(value),
+                "content-length" => {
+                    content_length = Some(
+                        value
+                            .parse::<u64>()
+                            .map_err(|_| h2_error("bad content-length"))?,
+                    );
+                }
+                "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                "upgrade" => return Err(h2_error("upgrade requires HTTP/1.1")),
+                "te" if !value.eq_ignore_ascii_case("trailers") => continue,
+                _ => {}
+            }
+
+            if CONNECTION_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+
+            fields.push((name, value));
+        }
+
+        // The host header is without port, unless set by the user.
+        let authority = match authority {
+            Some(v) if v != self.host => v,
+            _ => &self.authority,
+        };
+
+        let mut block = vec![];
+        hpack::encode(&mut block, b":method", method.as_bytes());
+        hpack::encode(&mut block, b":scheme", self.conn.scheme().as_bytes());
+        hpack::encode(&mut block, b":authority", authority.as_bytes());
+        hpack::encode(&mut block, b":path", path.as_bytes());
+        for (name, value) in &fields {
+            hpack::encode(&mut block, name.as_bytes(), value.as_bytes());
+        }
+
+        self.send = if chunked {
+            Send::Chunked(Chunked::Size(vec![]))
+        } else {
+            match content_length {
+                Some(n) if n > 0 => Send::Length(n),
+                _ => Send::Done,
+            }
+        };
+
+        let end_stream = matches!(self.send, Send::Done);
+
+        self.recv.is_head = method == "HEAD";
+        self.stream_id = Some(self.conn.open_stream(&block, end_stream, timeout)?);
+
+        Ok(())
+    }
+
+    fn send_data(&mut self, data: &[u8], end: bool, timeout: NextTimeout) -> Result<(), Error> {
+        let id = self.stream_id.expect("stream for request body");
+        self.conn.send_data(id, data, end, timeout)
+    }
+
+    /// Translate one stream event to HTTP/1.1.
+    fn translate(&mut self, event: Option<Recv>) -> Result<(), Error> {
+        let out = &mut self.recv.pending;
+
+        let Some(event) = event else {
+            if !self.recv.got_head {
+                return Err(h2_error("stream ended without response"));
+            }
+            if self.recv.chunked {
+                out.extend_from_slice(b"0\r\n\r\n");
+            }
+            self.recv.done = true;
+            return Ok(());
+        };
+
+        match event {
+            Recv::Headers(fields) if !self.recv.got_head => {
+                let status = fields
+                    .iter()
+                    .find(|(n, _)| n == b":status")
+                    .and_then(|(_, v)| StatusCode::from_bytes(v).ok())
+                    .ok_or_else(|| h2_error("response without :status"))?;
+
+                if status.is_informational() {
+                    // 100-continue is the only one hoot cares about.
+                    if status == StatusCode::CONTINUE {
+                        out.extend_from_slice(b"HTTP/1.1 100 Continue\r\n\r\n");
+                    }
+                    return Ok(());
+                }
+
+                let reason = status.canonical_reason().unwrap_or("");
+                out.extend_from_slice(
+                    format!("HTTP/1.1 {} {}\r\n", status.as_u16(), reason).as_bytes(),
+                );
+
+                let mut has_length = false;
+
+                for (name, value) in &fields {
+                    if name.starts_with(b":") {
+                        continue;
+                    }
+
+                    // Validated since they are written as HTTP/1.1. This also makes
+                    // the name lowercase, even though HTTP/2 doesn't allow uppercase.
+                    let name = HeaderName::from_bytes(name)
+                        .map_err(|_| h2_error("bad response header name"))?;
+                    let value = HeaderValue::from_bytes(value)
+                        .map_err(|_| h2_error("bad response header value"))?;
+
+                    if name == header::TRANSFER_ENCODING {
+                        continue;
+                    }
+                    has_length |= name == header::CONTENT_LENGTH;
+
+                    out.extend_from_slice(name.as_str().as_bytes());
+                    out.extend_from_slice(b": ");
+                    out.extend_from_slice(value.as_bytes());
+                    out.extend_from_slice(b"\r\n");
+                }
+
+                let no_body = self.recv.is_head
+                    || status == StatusCode::NO_CONTENT
+                    || status == StatusCode::NOT_MODIFIED;
+
+                if !has_length && !no_body {
+                    out.extend_from_slice(b"transfer-encoding: chunked\r\n");
+                    self.recv.chunked = true;
+                }
+
+                out.extend_from_slice(b"\r\n");
+                self.recv.got_head = true;
+            }
+            // Trailers are dropped.
+            Recv::Headers(_) => {}
+            Recv::Data(data) => {
+                if !self.recv.got_head {
+                    return Err(h2_error("DATA before response head"));
+                }
+                if self.recv.chunked {
+                    out.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+                    out.extend_from_slice(&data);
+                    out.extend_from_slice(b"\r\n");
+                } else {
+                    out.extend_from_slice(&data);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Chunked {
+    /// Returns (used input, chunk data, end of body).
+    fn dechunk<'a>(&mut self, input: &'a [u8]) -> Result<(usize, &'a [u8], bool), Error> {
+        match self {
+            Chunked::Size(line) => {
+                let Some(pos) = input.iter().position(|c| *c == b'\n') else {
+                    line.extend_from_slice(input);
+                    return Ok((input.len(), &[], false));
+                };
+                line.extend_from_slice(&input[..pos]);
+
+                let size = std::str::from_utf8(line)
+                    .ok()
+                    .and_then(|l| l.trim().split(';').next())
+                    .and_then(|s| u64::from_str_radix(s.trim(), 16).ok())
+                    .ok_or_else(|| h2_error("bad chunk size"))?;
+
+                *self = if size == 0 {
+                    Chunked::Trailer(vec![])
+                } else {
+                    Chunked::Data(size)
+                };
+
+                Ok((pos + 1, &[], false))
+            }
+            Chunked::Data(left) => {
+                let len = (*left).min(input.len() as u64) as usize;
+                *left -= len as u64;
+                if *left == 0 {
+                    *self = Chunked::DataEnd(2);
+                }
+                Ok((len, &input[..len], false))
+            }
+            Chunked::DataEnd(left) => {
+                let len = (*left).min(input.len());
+                *left -= len;
+                if *left == 0 {
+                    *self = Chunked::Size(vec![]);
+                }
+                Ok((len, &[], false))
+            }
+            Chunked::Trailer(line) => {
+                let Some(pos) = input.iter().position(|c| *c == b'\n') else {
+                    line.extend_from_slice(input);
+                    return Ok((input.len(), &[], false));
+                };
+                line.extend_from_slice(&input[..pos]);
+
+                let end = line.iter().all(|c| *c == b'\r');
+                line.clear();
+
+                Ok((pos + 1, &[], end))
+            }
+        }
+    }
+}
+
+impl Transport for H2Transport {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        &mut self.buffers
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), Error> {
+        let output = self.buffers.output()[..amount].to_vec();
+        self.send_bytes(&output, timeout)
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
+        if self.buffers.can_use_input() {
+            return Ok(true);
+        }
+
+        loop {
+            if !self.recv.pending.is_empty() {
+                let input = self.buffers.input_mut();
+                let len = input.len().min(self.recv.pending.len());
+                input[..len].copy_from_slice(&self.recv.pending[..len]);
+                self.buffers.add_filled(len);
+                self.recv.pending.drain(..len);
+                return Ok(len > 0);
+            }
+
+            if self.recv.done {
+                return Ok(false);
+            }
+
+            let Some(id) = self.stream_id else {
+                return Ok(false);
+            };
+
+            let event = self.conn.recv(id, timeout)?;
+            self.translate(event)?;
+
+            if self.recv.done && matches!(self.send, Send::Done) {
+                // Both directions are complete.
+                self.conn.close_stream(id);
+                self.stream_id = None;
+            }
+        }
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.conn.is_open()
+    }
+
+    fn is_tls(&self) -> bool {
+        self.conn.scheme() == "https"
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        Some(b"h2")
+    }
+
+    #[cfg(feature = "_tls")]
+    fn peer_certificates(&self) -> Option<Vec<crate::tls::Certificate<'static>>> {
+        self.conn.peer_certificates()
+    }
+
+    #[cfg(feature = "_tls")]
+    fn tls_handshake(&self) -> Option<crate::tls::TlsHandshake> {
+        self.conn.tls_handshake()
+    }
+}
+
+impl Drop for H2Transport {
+    fn drop(&mut self) {
+        if let Some(id) = self.stream_id.take() {
+            self.conn.close_stream(id);
+        }
+    }
+}
+
+impl fmt::Debug for H2Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("H2Transport")
+            .field("conn", &self.conn)
+            .field("stream_id", &self.stream_id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dechunk_request_body() {
+        let input = b"5;ext=1\r\nhello\r\n3\r\nabc\r\n0\r\nx-trailer: 1\r\n\r\n";
+
+        let mut chunked = Chunked::Size(vec![]);
+        let mut data = vec![];
+        let mut ended = false;
+
+        // Byte by byte to exercise the partial states.
+        for mut b in input.chunks(1) {
+            while !b.is_empty() {
+                assert!(!ended);
+                let (used, d, end) = chunked.dechunk(b).unwrap();
+                data.extend_from_slice(d);
+                ended = end;
+                b = &b[used..];
+            }
+        }
+
+        assert!(ended);
+        assert_eq!(data, b"helloabc");
+    }
+}
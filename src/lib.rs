@@ -146,12 +146,21 @@
 //!
 //! * **rustls** enabled the rustls TLS implementation. This is the defeault for the the crate level
 //!   convenience calls (`ureq::get` etc).
+//! * **rustls-aws-lc-rs** enables rustls with the aws-lc-rs crypto provider instead of ring, for
+//!   instance for FIPS. Use it with `default-features = false` to not also build ring.
+//! * **rustls-no-provider** enables rustls without a crypto provider. The provider must then be
+//!   set with [`TlsConfig::rustls_crypto_provider`](crate::tls::TlsConfig::rustls_crypto_provider), or installed as
+//!   the process-wide default.
 //! * **native-tls** enables the native tls backend for TLS. Due to the risk of diamond dependencies
 //!   accidentally switching on an unwanted TLS implementation, `native-tls` is never picked up as
 //!   a default or used by the crate level convenience calls (`ureq::get` etc) – it must be configured
 //!   on the agent.
-//! * **socks-proxy** enables proxy config using the `socks4://`, `socks4a://`, `socks5://`
-//!    and `socks://` (equal to `socks5://`) prefix.
+//! * **socks-proxy** enables proxy config using the `socks4://`, `socks4a://`, `socks5://`,
+//!   `socks5h://` and `socks://` (equal to `socks5://`) prefix.
+//! * **proxy-digest** enables answering `Digest` challenges of a CONNECT proxy with the
+//!   username and password of the proxy uri.
+//! * **pkcs12** enables reading client certificates from PKCS#12 bundles, `tls::parse_pkcs12()`,
+//!   and password protected private keys, `PrivateKey::from_encrypted_pem()`.
 //! * **cookies** enables cookies.
 //! * **gzip** enables requests of gzip-compressed responses and decompresses them.
 //! * **brotli** enables requests brotli-compressed responses and decompresses them.
@@ -159,6 +168,11 @@
 //!    (e.g.  `Content-Type: text/plain; charset=iso-8859-1`). Without this, the
 //!    library defaults to Rust's built in `utf-8`.
 //! * **json** enables JSON sending and receiving via serde_json.
+//! * **websocket** enables the WebSocket client, [`Agent::websocket()`].
+//! * **http2** (experimental) enables HTTP/2, negotiated via ALPN for `https` with **rustls**.
+//!   See also [`AgentConfig::http2_prior_knowledge`].
+//! * **pac** enables choosing the proxy per request with a proxy auto-config (PAC) script,
+//!   `ProxyAutoConfig`.
 //!
 //! # JSON
 //!
@@ -273,6 +287,17 @@
 //!
 //! Proxies settings are configured on an [Agent]. All request sent through the agent will be proxied.
 //!
+//! An `https://` CONNECT proxy is connected to using TLS, and `https` requests are tunneled
+//! in a second TLS connection to the server. The TLS to the proxy can be configured separately
+//! using `AgentConfig::proxy_tls_config`.
+//!
+//! The username and password of a CONNECT proxy uri are sent using Basic authentication,
+//! and, with the **proxy-digest** feature, used to answer `Digest` challenges. Other schemes
+//! can be handled with a [`ProxyAuthenticator`].
+//!
+//! Plain `http` requests can be sent to a forward proxy in absolute-form instead of
+//! being tunneled, see [`Proxy::forward_http()`].
+//!
 //! [`HTTP`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Proxy_servers_and_tunneling#http_tunneling
 //! [`CONNECT`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods/CONNECT
 //! [`SOCKS4`]: https://en.wikipedia.org/wiki/SOCKS#SOCKS4
@@ -320,11 +345,18 @@ extern crate log;
 /// Re-exported http-crate.
 pub use http;
 
+#[cfg(feature = "json")]
+pub use body::JsonStream;
 pub use body::{Body, BodyReader, BodyWithConfig};
 pub use config::{AgentConfig, Timeouts};
+pub use download::Download;
 use http::Method;
 use http::{Request, Response, Uri};
-pub use proxy::Proxy;
+pub use progress::Progress;
+pub use proxy::{Proxy, ProxyAuthenticator};
+
+#[cfg(feature = "pac")]
+pub use proxy::ProxyAutoConfig;
 pub use request::RequestBuilder;
 use request::{WithBody, WithoutBody};
 pub use send_body::AsSendBody;
@@ -332,8 +364,12 @@ pub use send_body::AsSendBody;
 mod agent;
 mod body;
 mod config;
+mod download;
 mod error;
+#[cfg(feature = "http2")]
+mod http2;
 mod pool;
+mod progress;
 mod proxy;
 mod request;
 mod send_body;
@@ -342,8 +378,12 @@ mod util;
 
 pub mod middleware;
 pub mod resolver;
+pub mod sse;
 pub mod transport;
 
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
 #[cfg(feature = "_tls")]
 pub mod tls;
 
@@ -405,6 +445,22 @@ pub(crate) mod test {
         *INIT_LOG
     }
 
+    /// Resolver for tests against local servers, since the test build resolves
+    /// every host to a fake address.
+    #[derive(Debug)]
+    pub struct FixedResolver(pub std::net::SocketAddr);
+
+    impl resolver::Resolver for FixedResolver {
+        fn resolve(
+            &self,
+            _uri: &http::Uri,
+            _config: &AgentConfig,
+            _timeout: transport::time::NextTimeout,
+        ) -> Result<resolver::ResolvedSocketAddrs, Error> {
+            Ok([self.0].into_iter().collect())
+        }
+    }
+
     #[test]
     fn connect_http_google() {
         init_test_log();
@@ -424,7 +480,7 @@ pub(crate) mod test {
     }
 
     #[test]
-    #[cfg(feature = "rustls")]
+    #[cfg(feature = "_rustls")]
     fn connect_https_google_rustls() {
         init_test_log();
         use crate::tls::{TlsConfig, TlsProvider};
@@ -482,7 +538,7 @@ pub(crate) mod test {
     }
 
     #[test]
-    #[cfg(feature = "rustls")]
+    #[cfg(feature = "_rustls")]
     fn connect_https_google_rustls_webpki() {
         init_test_log();
 
@@ -11,6 +11,9 @@ use crate::transport::{Buffers, ConnectionDetails, Connector, Transport};
 use crate::util::DebugAuthority;
 use crate::{AgentConfig, Error};
 
+#[cfg(feature = "http2")]
+use crate::http2::{H2Connection, H2Transport, WeakH2Connection};
+
 pub(crate) struct ConnectionPool {
     connector: Box<dyn Connector>,
     pool: Arc<Mutex<Pool>>,
@@ -35,6 +38,25 @@ impl ConnectionPool {
                 debug!("Use pooled: {:?}", key);
                 return Ok(conn);
             }
+
+            #[cfg(feature = "http2")]
+            let h2 = if crate::http2::allows_http2(details) {
+                pool.get_http2(&key)
+            } else {
+                None
+            };
+
+            #[cfg(feature = "http2")]
+            if let Some(h2) = h2 {
+                debug!("Use HTTP/2 connection: {:?}", key);
+                return Ok(Connection {
+                    transport: Box::new(h2_transport(h2, details)),
+                    key,
+                    last_use: details.now,
+                    pool: Arc::downgrade(&self.pool),
+                    position_per_host: None,
+                });
+            }
         }
 
         let transport = self
@@ -42,6 +64,16 @@ impl ConnectionPool {
             .connect(details, None)?
             .ok_or(Error::ConnectionFailed)?;
 
+        #[cfg(feature = "http2")]
+        let transport = if crate::http2::use_http2(&*transport, details) {
+            let h2 = H2Connection::new(transport, details.config)?;
+            let mut pool = self.pool.lock().unwrap();
+            pool.add_http2(key.clone(), &h2);
+            Box::new(h2_transport(h2, details))
+        } else {
+            transport
+        };
+
         let conn = Connection {
             transport,
             key,
@@ -60,6 +92,17 @@ impl ConnectionPool {
     }
 }
 
+#[cfg(feature = "http2")]
+fn h2_transport(h2: H2Connection, details: &ConnectionDetails) -> H2Transport {
+    let config = details.config;
+    H2Transport::new(
+        h2,
+        details.uri,
+        config.input_buffer_size,
+        config.output_buffer_size,
+    )
+}
+
 pub(crate) struct Connection {
     transport: Box<dyn Transport>,
     key: PoolKey,
@@ -109,6 +152,22 @@ impl Connection {
         // Just consume self.
     }
 
+    #[cfg(feature = "_tls")]
+    pub fn peer_certificates(&self) -> Option<Vec<crate::tls::Certificate<'static>>> {
+        self.transport.peer_certificates()
+    }
+
+    #[cfg(feature = "_tls")]
+    pub fn tls_handshake(&self) -> Option<crate::tls::TlsHandshake> {
+        self.transport.tls_handshake()
+    }
+
+    /// Take the transport out of the connection. It will never be pooled.
+    pub fn into_transport(self) -> Box<dyn Transport> {
+        debug!("Take over: {:?}", self.key);
+        self.transport
+    }
+
     pub fn reuse(mut self, now: Instant) {
         if !self.transport.is_open() {
             // The purpose of probing is that is_open() for tcp connector attempts
@@ -155,14 +214,25 @@ impl Connection {
 /// It's correct to include username/password since connections with differing such and
 /// the same host/port must not be mixed up.
 ///
+/// For a forward proxy, the authority is that of the proxy.
+///
 #[derive(Clone, PartialEq, Eq)]
 struct PoolKey(Arc<PoolKeyInner>);
 
 impl PoolKey {
     fn new(uri: &Uri, proxy: &Option<Proxy>) -> Self {
+        // Requests to a forward proxy share the connections regardless of server.
+        let authority_uri = match proxy {
+            Some(p) if p.is_forward(uri) => p.uri(),
+            _ => uri,
+        };
+
         let inner = PoolKeyInner(
             uri.scheme().expect("uri with scheme").clone(),
-            uri.authority().expect("uri with authority").clone(),
+            authority_uri
+                .authority()
+                .expect("uri with authority")
+                .clone(),
             proxy.clone(),
         );
 
@@ -179,6 +249,11 @@ struct Pool {
     max_idle_connections: usize,
     max_idle_connections_per_host: usize,
     max_idle_age: Duration,
+
+    /// Shared HTTP/2 connections. These live as long as some stream uses them,
+    /// in flight or idle in `lru`.
+    #[cfg(feature = "http2")]
+    http2: Vec<(PoolKey, WeakH2Connection)>,
 }
 
 impl Pool {
@@ -188,6 +263,8 @@ impl Pool {
             max_idle_connections: config.max_idle_connections,
             max_idle_connections_per_host: config.max_idle_connections_per_host,
             max_idle_age: config.max_idle_age.into(),
+            #[cfg(feature = "http2")]
+            http2: vec![],
         }
     }
 
@@ -239,6 +316,20 @@ impl Pool {
         }
     }
 
+    #[cfg(feature = "http2")]
+    fn add_http2(&mut self, key: PoolKey, conn: &H2Connection) {
+        self.http2.push((key, conn.downgrade()));
+    }
+
+    #[cfg(feature = "http2")]
+    fn get_http2(&mut self, key: &PoolKey) -> Option<H2Connection> {
+        self.http2.retain(|(_, c)| c.is_alive());
+        self.http2
+            .iter()
+            .filter(|(k, _)| k == key)
+            .find_map(|(_, c)| c.upgrade().filter(|c| c.is_open()))
+    }
+
     fn add(&mut self, conn: Connection) {
         self.lru.push_back(conn)
     }
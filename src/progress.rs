@@ -0,0 +1,162 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Progress of sending a request body or receiving a response body.
+///
+/// Reported to the callback set with [`RequestBuilder::progress()`](crate::RequestBuilder::progress).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Progress {
+    /// Request body data was sent.
+    Sent {
+        /// Total number of body bytes sent so far.
+        amount: u64,
+        /// The length of the entire request body, if known.
+        total: Option<u64>,
+    },
+
+    /// Response body data was received.
+    Received {
+        /// Total number of body bytes delivered to the reader so far.
+        ///
+        /// This is after decompression (**gzip**, **brotli**) and charset
+        /// conversion (**charset**).
+        amount: u64,
+        /// Total number of body bytes received from the connection so far.
+        ///
+        /// This is before any decompression or charset conversion, which makes it
+        /// comparable to `total`.
+        raw: u64,
+        /// The `Content-Length` of the response body, if known.
+        total: Option<u64>,
+    },
+}
+
+/// The progress callback as stored in the request extensions.
+#[derive(Clone)]
+pub(crate) struct ProgressHook(Arc<dyn Fn(Progress) + Send + Sync>);
+
+impl ProgressHook {
+    pub fn new(f: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        ProgressHook(Arc::new(f))
+    }
+
+    fn call(&self, progress: Progress) {
+        (self.0)(progress)
+    }
+}
+
+/// Counter of sent request body bytes.
+pub(crate) struct UploadProgress {
+    hook: ProgressHook,
+    sent: u64,
+    total: Option<u64>,
+}
+
+impl UploadProgress {
+    pub fn new(hook: ProgressHook, total: Option<u64>) -> Self {
+        UploadProgress {
+            hook,
+            sent: 0,
+            total,
+        }
+    }
+
+    pub fn add(&mut self, amount: usize) {
+        if amount == 0 {
+            return;
+        }
+        self.sent += amount as u64;
+        self.hook.call(Progress::Sent {
+            amount: self.sent,
+            total: self.total,
+        });
+    }
+}
+
+/// Counter of received response body bytes.
+///
+/// This is shared between the raw reading (in the unit handler) and the decoded
+/// reading (in the body reader), which is why the counters are atomics.
+pub(crate) struct DownloadProgress {
+    hook: ProgressHook,
+    raw: AtomicU64,
+    received: AtomicU64,
+    total: Option<u64>,
+}
+
+impl DownloadProgress {
+    pub fn new(hook: ProgressHook, total: Option<u64>) -> Self {
+        DownloadProgress {
+            hook,
+            raw: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            total,
+        }
+    }
+
+    pub fn add_raw(&self, amount: usize) {
+        self.raw.fetch_add(amount as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, amount: usize) {
+        if amount == 0 {
+            return;
+        }
+        let received = self.received.fetch_add(amount as u64, Ordering::Relaxed) + amount as u64;
+        self.hook.call(Progress::Received {
+            amount: received,
+            raw: self.raw.load(Ordering::Relaxed),
+            total: self.total,
+        });
+    }
+}
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressHook").finish()
+    }
+}
+
+#[cfg(all(test, feature = "_test"))]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::Progress;
+    use crate::test::init_test_log;
+    use crate::transport::set_handler;
+
+    #[test]
+    fn progress_upload_and_download() {
+        init_test_log();
+        set_handler("/progress", 200, &[("content-length", "10")], b"0123456789");
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen2 = seen.clone();
+
+        let mut res = crate::post("https://my.test/progress")
+            .progress(move |p| seen2.lock().unwrap().push(p))
+            .send(&[0_u8; 5])
+            .unwrap();
+
+        res.body_mut().read_to_vec().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(
+            seen.first(),
+            Some(&Progress::Sent {
+                amount: 5,
+                total: Some(5)
+            })
+        );
+        assert_eq!(
+            seen.last(),
+            Some(&Progress::Received {
+                amount: 10,
+                raw: 10,
+                total: Some(10)
+            })
+        );
+    }
+}
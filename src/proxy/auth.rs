@@ -0,0 +1,65 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use super::Proxy;
+
+/// Authentication with a CONNECT proxy beyond Basic.
+///
+/// When the proxy answers `407 Proxy Authentication Required`, the authenticator
+/// is asked to answer the challenges in the `Proxy-Authenticate` headers. The CONNECT
+/// request is then sent again with the answer as `Proxy-Authorization`.
+///
+/// Without an authenticator, ureq answers `Digest` challenges using the username and
+/// password of the proxy uri, if the feature **proxy-digest** is enabled.
+///
+/// ```
+/// use ureq::{Proxy, ProxyAuthenticator};
+///
+/// #[derive(Debug)]
+/// struct Token;
+///
+/// impl ProxyAuthenticator for Token {
+///     fn authorize(&self, _proxy: &Proxy, _target: &str, challenges: &[&str]) -> Option<String> {
+///         let bearer = challenges.iter().any(|c| c.starts_with("Bearer"));
+///         bearer.then(|| "Bearer secret-token".to_string())
+///     }
+/// }
+///
+/// let proxy = Proxy::new("http://cool.proxy:9090")?.authenticator(Token);
+/// # Ok::<_, ureq::Error>(())
+/// ```
+pub trait ProxyAuthenticator: fmt::Debug + Send + Sync + 'static {
+    /// Answer the challenges of a `407 Proxy Authentication Required` response.
+    ///
+    /// * `proxy` is the proxy being connected to, with the credentials of its uri.
+    /// * `target` is the `host:port` of the CONNECT request.
+    /// * `challenges` are the values of the `Proxy-Authenticate` headers.
+    ///
+    /// Returns the value of the `Proxy-Authorization` header, or `None` to give up.
+    fn authorize(&self, proxy: &Proxy, target: &str, challenges: &[&str]) -> Option<String>;
+}
+
+/// Authenticator of a [`Proxy`], compared by identity.
+#[derive(Clone)]
+pub(crate) struct AuthenticatorRef(pub Arc<dyn ProxyAuthenticator>);
+
+impl PartialEq for AuthenticatorRef {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for AuthenticatorRef {}
+
+impl Hash for AuthenticatorRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const () as usize).hash(state);
+    }
+}
+
+impl fmt::Debug for AuthenticatorRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
@@ -0,0 +1,357 @@
+//! Digest authentication with a proxy (RFC 7616).
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// A challenge of a `Proxy-Authenticate` header.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Challenge {
+    pub scheme: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Parse the challenges of a `Proxy-Authenticate` header.
+///
+/// One header can hold several challenges, such as `Digest realm="a", Basic realm="a"`.
+/// The token68 value of challenges such as `Negotiate` is not interpreted.
+pub(crate) fn parse_challenges(value: &str) -> Vec<Challenge> {
+    let mut challenges: Vec<Challenge> = vec![];
+    let mut rest = value;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            break;
+        }
+
+        let (token, after) = split_token(rest);
+        if token.is_empty() {
+            // Not a token, such as the "=" padding of a token68.
+            rest = &rest[1..];
+            continue;
+        }
+
+        let after_ws = after.trim_start();
+
+        match challenges.last_mut() {
+            Some(challenge) if after_ws.starts_with('=') && !after_ws.starts_with("==") => {
+                let (value, after) = split_value(after_ws[1..].trim_start());
+                challenge.params.push((token.to_string(), value));
+                rest = after;
+            }
+            _ if after_ws.starts_with('=') => {
+                // token68 or a param without a challenge.
+                rest = after_ws.trim_start_matches('=');
+            }
+            _ => {
+                challenges.push(Challenge {
+                    scheme: token.to_string(),
+                    params: vec![],
+                });
+                rest = after;
+            }
+        }
+    }
+
+    challenges
+}
+
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn split_token(s: &str) -> (&str, &str) {
+    let end = s.find(|c| !is_tchar(c)).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+fn split_value(s: &str) -> (String, &str) {
+    let Some(quoted) = s.strip_prefix('"') else {
+        let (token, rest) = split_token(s);
+        return (token.to_string(), rest);
+    };
+
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, c)) = chars.next() {
+                    value.push(c);
+                }
+            }
+            '"' => return (value, &quoted[i + 1..]),
+            c => value.push(c),
+        }
+    }
+
+    // Unterminated quoted string.
+    (value, "")
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+fn hex(v: &[u8]) -> String {
+    v.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The hash algorithm of a Digest challenge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    /// Parse the `algorithm` param, which is MD5 when missing. The second value tells
+    /// whether it is the `-sess` variant.
+    fn parse(value: Option<&str>) -> Option<(Algorithm, bool)> {
+        let value = value.unwrap_or("MD5");
+        let (name, sess) = match value.len().checked_sub(5) {
+            Some(i) if value.is_char_boundary(i) && value[i..].eq_ignore_ascii_case("-sess") => {
+                (&value[..i], true)
+            }
+            _ => (value, false),
+        };
+
+        let algorithm = if name.eq_ignore_ascii_case("MD5") {
+            Algorithm::Md5
+        } else if name.eq_ignore_ascii_case("SHA-256") {
+            Algorithm::Sha256
+        } else {
+            return None;
+        };
+
+        Some((algorithm, sess))
+    }
+
+    fn hex(&self, s: &str) -> String {
+        match self {
+            Algorithm::Md5 => hex(&Md5::digest(s.as_bytes())),
+            Algorithm::Sha256 => hex(&Sha256::digest(s.as_bytes())),
+        }
+    }
+}
+
+/// Client nonce for Digest.
+///
+/// Only needs to be unique, which the randomly seeded hasher gives us without
+/// depending on a random number generator.
+fn cnonce() -> String {
+    let mut out = String::new();
+    for _ in 0..2 {
+        let hash = RandomState::new().hash_one(std::time::SystemTime::now());
+        out.push_str(&format!("{:016x}", hash));
+    }
+    out
+}
+
+/// Answer a `Digest` challenge using MD5, SHA-256 or their `-sess` variants.
+///
+/// The first challenge with a supported algorithm is answered, since the proxy sends
+/// them in order of preference.
+pub(crate) fn digest(
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    challenges: &[&str],
+) -> Option<String> {
+    digest_with_cnonce(username, password, method, uri, challenges, &cnonce())
+}
+
+fn digest_with_cnonce(
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    challenges: &[&str],
+    cnonce: &str,
+) -> Option<String> {
+    let challenge = challenges
+        .iter()
+        .flat_map(|c| parse_challenges(c))
+        .filter(|c| c.scheme.eq_ignore_ascii_case("digest"))
+        .find(|c| {
+            let qop_ok = c
+                .param("qop")
+                .map(|q| q.split(',').any(|q| q.trim() == "auth"))
+                .unwrap_or(true);
+            qop_ok && Algorithm::parse(c.param("algorithm")).is_some()
+        })?;
+
+    let realm = challenge.param("realm").unwrap_or_default();
+    let nonce = challenge.param("nonce")?;
+    let algorithm = challenge.param("algorithm");
+    // unwrap is ok because the challenge was found by it.
+    let (hash, sess) = Algorithm::parse(algorithm).unwrap();
+    let qop = challenge.param("qop").is_some();
+    let nc = "00000001";
+
+    let mut ha1 = hash.hex(&format!("{}:{}:{}", username, realm, password));
+    if sess {
+        ha1 = hash.hex(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = hash.hex(&format!("{}:{}", method, uri));
+
+    let response = if qop {
+        hash.hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))
+    } else {
+        hash.hex(&format!("{}:{}:{}", ha1, nonce, ha2))
+    };
+
+    let mut header = format!(
+        "Digest username={}, realm={}, nonce={}, uri={}",
+        quote(username),
+        quote(realm),
+        quote(nonce),
+        quote(uri)
+    );
+    if let Some(algorithm) = algorithm {
+        header.push_str(&format!(", algorithm={}", algorithm));
+    }
+    header.push_str(&format!(", response=\"{}\"", response));
+    if qop {
+        header.push_str(&format!(", qop=auth, nc={}, cnonce={}", nc, quote(cnonce)));
+    }
+    if let Some(opaque) = challenge.param("opaque") {
+        header.push_str(&format!(", opaque={}", quote(opaque)));
+    }
+
+    Some(header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_several_challenges() {
+        let challenges = parse_challenges(
+            r#"Digest realm="proxy, inc", nonce="abc\"d", qop="auth,auth-int", Basic realm=x"#,
+        );
+        assert_eq!(
+            challenges,
+            vec![
+                Challenge {
+                    scheme: "Digest".into(),
+                    params: vec![
+                        ("realm".into(), "proxy, inc".into()),
+                        ("nonce".into(), "abc\"d".into()),
+                        ("qop".into(), "auth,auth-int".into()),
+                    ],
+                },
+                Challenge {
+                    scheme: "Basic".into(),
+                    params: vec![("realm".into(), "x".into())],
+                },
+            ]
+        );
+
+        let challenges = parse_challenges("Negotiate YII=, NTLM");
+        let schemes: Vec<_> = challenges.iter().map(|c| c.scheme.as_str()).collect();
+        assert_eq!(schemes, ["Negotiate", "NTLM"]);
+    }
+
+    #[test]
+    fn digest_rfc2617_example() {
+        let challenge = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+
+        let header = digest_with_cnonce(
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            &[challenge],
+            "0a4f113b",
+        )
+        .unwrap();
+
+        assert_eq!(
+            header,
+            "Digest username=\"Mufasa\", realm=\"testrealm@host.com\", \
+            nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", uri=\"/dir/index.html\", \
+            response=\"6629fae49393a05397450978507c4ef1\", qop=auth, nc=00000001, \
+            cnonce=\"0a4f113b\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""
+        );
+    }
+
+    #[test]
+    fn digest_rfc7616_sha256_example() {
+        let challenge = r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#;
+
+        let header = digest_with_cnonce(
+            "Mufasa",
+            "Circle of Life",
+            "GET",
+            "/dir/index.html",
+            &[challenge],
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            header,
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+            algorithm=SHA-256, \
+            response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\", \
+            qop=auth, nc=00000001, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        );
+    }
+
+    #[test]
+    fn digest_first_supported_challenge() {
+        let header = digest(
+            "u",
+            "p",
+            "CONNECT",
+            "host:443",
+            &[
+                r#"Digest realm="x", nonce="n", algorithm=SHA-512-256"#,
+                r#"Digest realm="x", nonce="n", algorithm=SHA-256-sess"#,
+                r#"Digest realm="x", nonce="n", algorithm=MD5"#,
+            ],
+        )
+        .unwrap();
+        assert!(header.contains("algorithm=SHA-256-sess"));
+    }
+
+    #[test]
+    fn digest_unsupported() {
+        let call = |challenge: &str| digest("u", "p", "CONNECT", "host:443", &[challenge]);
+
+        assert!(call(r#"Basic realm="x""#).is_none());
+        assert!(call(r#"Digest realm="x", nonce="n", algorithm=SHA-512-256"#).is_none());
+        assert!(call(r#"Digest realm="x", nonce="n", qop="auth-int""#).is_none());
+        assert!(call(r#"Digest realm="x", nonce="n", algorithm=MD5-sess"#).is_some());
+        assert!(call(r#"Digest realm="x", nonce="n", algorithm=sha-256"#).is_some());
+        assert!(call(r#"Digest realm="x", nonce="n", algorithm=-sess"#).is_none());
+        assert!(call(r#"Digest realm="x""#).is_none());
+    }
+}
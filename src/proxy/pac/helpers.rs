@@ -0,0 +1,493 @@
+//! The standard functions available to proxy auto-config scripts.
+//!
+//! Time based functions use UTC, also without the `"GMT"` argument, since the
+//! standard library has no notion of the local time zone.
+
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::js::{Interp, Value};
+
+type Helper = for<'a> fn(&[Value<'a>]) -> Result<Value<'a>, String>;
+
+/// A helper looking up host names.
+type DnsHelper =
+    for<'a> fn(&dyn Fn(&str) -> Option<Ipv4Addr>, &[Value<'a>]) -> Result<Value<'a>, String>;
+
+/// Looks up the first IPv4 address of a host name.
+pub(crate) type Lookup<'a> = Rc<dyn Fn(&str) -> Option<Ipv4Addr> + 'a>;
+
+const FUNCTIONS: &[(&str, Helper)] = &[
+    ("isPlainHostName", is_plain_host_name),
+    ("dnsDomainIs", dns_domain_is),
+    ("localHostOrDomainIs", local_host_or_domain_is),
+    ("convert_addr", convert_addr),
+    ("myIpAddress", my_ip_address),
+    ("dnsDomainLevels", dns_domain_levels),
+    ("shExpMatch", sh_exp_match),
+    ("weekdayRange", weekday_range),
+    ("dateRange", date_range),
+    ("timeRange", time_range),
+    ("alert", alert),
+];
+
+const DNS_FUNCTIONS: &[(&str, DnsHelper)] = &[
+    ("isResolvable", is_resolvable),
+    ("isInNet", is_in_net),
+    ("dnsResolve", dns_resolve),
+];
+
+pub(crate) fn define_all<'a>(interp: &mut Interp<'a>, lookup: Lookup<'a>) {
+    for (name, f) in FUNCTIONS {
+        interp.define(name, Value::native(*f));
+    }
+
+    for (name, f) in DNS_FUNCTIONS {
+        let (f, lookup) = (*f, lookup.clone());
+        interp.define(name, Value::native(move |args| f(&*lookup, args)));
+    }
+}
+
+fn arg(args: &[Value], i: usize) -> String {
+    args.get(i).map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn is_plain_host_name<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(Value::Bool(!arg(args, 0).contains('.')))
+}
+
+fn dns_domain_is<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    let host = arg(args, 0).to_ascii_lowercase();
+    let domain = arg(args, 1).to_ascii_lowercase();
+    Ok(Value::Bool(host.ends_with(&domain)))
+}
+
+fn local_host_or_domain_is<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    let host = arg(args, 0).to_ascii_lowercase();
+    let hostdom = arg(args, 1).to_ascii_lowercase();
+    let is = host == hostdom || (!host.contains('.') && hostdom.starts_with(&format!("{}.", host)));
+    Ok(Value::Bool(is))
+}
+
+/// The first IPv4 address of a host name or address.
+fn resolve(lookup: &dyn Fn(&str) -> Option<Ipv4Addr>, host: &str) -> Option<Ipv4Addr> {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Some(ip),
+        Ok(IpAddr::V6(_)) => None,
+        Err(_) => lookup(host),
+    }
+}
+
+fn is_resolvable<'a>(
+    lookup: &dyn Fn(&str) -> Option<Ipv4Addr>,
+    args: &[Value<'a>],
+) -> Result<Value<'a>, String> {
+    Ok(Value::Bool(resolve(lookup, &arg(args, 0)).is_some()))
+}
+
+fn is_in_net<'a>(
+    lookup: &dyn Fn(&str) -> Option<Ipv4Addr>,
+    args: &[Value<'a>],
+) -> Result<Value<'a>, String> {
+    let (Some(ip), Ok(pattern), Ok(mask)) = (
+        resolve(lookup, &arg(args, 0)),
+        arg(args, 1).parse::<Ipv4Addr>(),
+        arg(args, 2).parse::<Ipv4Addr>(),
+    ) else {
+        return Ok(Value::Bool(false));
+    };
+
+    let mask = u32::from(mask);
+    Ok(Value::Bool(
+        u32::from(ip) & mask == u32::from(pattern) & mask,
+    ))
+}
+
+fn dns_resolve<'a>(
+    lookup: &dyn Fn(&str) -> Option<Ipv4Addr>,
+    args: &[Value<'a>],
+) -> Result<Value<'a>, String> {
+    Ok(match resolve(lookup, &arg(args, 0)) {
+        Some(ip) => Value::str(&ip.to_string()),
+        None => Value::Null,
+    })
+}
+
+fn convert_addr<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(match arg(args, 0).parse::<Ipv4Addr>() {
+        Ok(ip) => Value::Number(u32::from(ip) as f64),
+        Err(_) => Value::Number(0.0),
+    })
+}
+
+fn my_ip_address<'a>(_: &[Value<'a>]) -> Result<Value<'a>, String> {
+    // Connecting a UDP socket sends nothing, but picks the interface of the default route.
+    let ip = UdpSocket::bind("0.0.0.0:0")
+        .and_then(|s| s.connect("192.0.2.1:80").map(|_| s))
+        .and_then(|s| s.local_addr())
+        .map(|a| a.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    Ok(Value::str(&ip.to_string()))
+}
+
+fn dns_domain_levels<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(Value::Number(arg(args, 0).matches('.').count() as f64))
+}
+
+fn sh_exp_match<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(Value::Bool(glob(&arg(args, 0), &arg(args, 1))))
+}
+
+/// Shell expression match, with `*` and `?`.
+fn glob(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.chars().collect();
+    let p: Vec<char> = pattern.chars().collect();
+
+    let (mut si, mut pi) = (0, 0);
+    // Position after the last `*`, and where in `s` it matched up to.
+    let mut star = None;
+
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            si += 1;
+            pi += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            pi += 1;
+            star = Some((pi, si));
+        } else if let Some((star_pi, star_si)) = star {
+            // Let the `*` match one more character.
+            pi = star_pi;
+            si = star_si + 1;
+            star = Some((star_pi, si));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// A point in time, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    /// 0 is Sunday.
+    weekday: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl DateTime {
+    fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self::from_unix(secs)
+    }
+
+    fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86400) as i64;
+        let rem = (secs % 86400) as u32;
+
+        // Civil from days, by Howard Hinnant.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + (month <= 2) as i64;
+
+        DateTime {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday.
+            weekday: ((days + 4) % 7) as u32,
+            hour: rem / 3600,
+            minute: rem / 60 % 60,
+            second: rem % 60,
+        }
+    }
+}
+
+/// Arguments without the trailing `"GMT"`.
+fn time_args<'b, 'a>(args: &'b [Value<'a>]) -> &'b [Value<'a>] {
+    match args.split_last() {
+        Some((last, rest)) if last.to_string() == "GMT" => rest,
+        _ => args,
+    }
+}
+
+/// Whether `value` is in the range, which wraps around if `start` is after `end`.
+fn in_range<T: PartialOrd>(start: T, value: T, end: T) -> bool {
+    if start <= end {
+        start <= value && value <= end
+    } else {
+        value >= start || value <= end
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+fn weekday_range<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(Value::Bool(weekdays_match(
+        time_args(args),
+        DateTime::now(),
+    )))
+}
+
+fn weekdays_match(args: &[Value], now: DateTime) -> bool {
+    let day = |i: usize| {
+        WEEKDAYS
+            .iter()
+            .position(|d| *d == arg(args, i))
+            .map(|d| d as u32)
+    };
+
+    match args.len() {
+        1 => day(0) == Some(now.weekday),
+        2 => match (day(0), day(1)) {
+            (Some(start), Some(end)) => in_range(start, now.weekday, end),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn date_range<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(Value::Bool(dates_match(time_args(args), DateTime::now())))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DatePart {
+    Day(u32),
+    Month(u32),
+    Year(i64),
+}
+
+impl DatePart {
+    fn parse(v: &Value) -> Option<Self> {
+        let s = v.to_string();
+        if let Some(m) = MONTHS.iter().position(|m| *m == s) {
+            return Some(DatePart::Month(m as u32 + 1));
+        }
+        let n = v.to_number();
+        if n.fract() != 0.0 || n < 1.0 {
+            return None;
+        }
+        Some(if n <= 31.0 {
+            DatePart::Day(n as u32)
+        } else {
+            DatePart::Year(n as i64)
+        })
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            DatePart::Year(_) => 0,
+            DatePart::Month(_) => 1,
+            DatePart::Day(_) => 2,
+        }
+    }
+
+    fn value(&self) -> i64 {
+        match self {
+            DatePart::Day(d) | DatePart::Month(d) => *d as i64,
+            DatePart::Year(y) => *y,
+        }
+    }
+
+    /// The same part of `now`.
+    fn of(&self, now: DateTime) -> i64 {
+        match self {
+            DatePart::Day(_) => now.day as i64,
+            DatePart::Month(_) => now.month as i64,
+            DatePart::Year(_) => now.year,
+        }
+    }
+}
+
+fn dates_match(args: &[Value], now: DateTime) -> bool {
+    let Some(mut parts) = args.iter().map(DatePart::parse).collect::<Option<Vec<_>>>() else {
+        return false;
+    };
+
+    if parts.len() == 1 {
+        return parts[0].value() == parts[0].of(now);
+    }
+
+    if parts.len() % 2 != 0 || parts.len() > 6 {
+        return false;
+    }
+    let (start, end) = parts.split_at_mut(args.len() / 2);
+
+    // Compare as (year, month, day), with the parts that are given.
+    start.sort_by_key(DatePart::rank);
+    end.sort_by_key(DatePart::rank);
+    let kinds_match = start
+        .iter()
+        .zip(end.iter())
+        .all(|(s, e)| s.rank() == e.rank());
+    let distinct = start.windows(2).all(|w| w[0].rank() != w[1].rank());
+    if !kinds_match || !distinct {
+        return false;
+    }
+
+    let key = |parts: &[DatePart]| parts.iter().map(DatePart::value).collect::<Vec<_>>();
+    let now_key: Vec<_> = start.iter().map(|p| p.of(now)).collect();
+
+    in_range(key(start), now_key, key(end))
+}
+
+fn time_range<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(Value::Bool(times_match(time_args(args), DateTime::now())))
+}
+
+fn times_match(args: &[Value], now: DateTime) -> bool {
+    let n: Vec<u32> = args.iter().map(|v| v.to_number() as u32).collect();
+    let secs = now.hour * 3600 + now.minute * 60 + now.second;
+
+    match n[..] {
+        [h] => now.hour == h,
+        [h1, h2] => h1 <= now.hour && now.hour <= h2,
+        [h1, m1, h2, m2] => h1 * 3600 + m1 * 60 <= secs && secs <= h2 * 3600 + m2 * 60 + 59,
+        [h1, m1, s1, h2, m2, s2] => {
+            h1 * 3600 + m1 * 60 + s1 <= secs && secs <= h2 * 3600 + m2 * 60 + s2
+        }
+        _ => false,
+    }
+}
+
+fn alert<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    debug!("PAC alert: {}", arg(args, 0));
+    Ok(Value::Undefined)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<Value<'static>> {
+        values
+            .iter()
+            .map(|v| match v.parse::<f64>() {
+                Ok(n) => Value::Number(n),
+                Err(_) => Value::str(v),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shell_expressions() {
+        assert!(glob("www.example.com", "*.example.com"));
+        assert!(glob("a.b.example.com", "*.example.*"));
+        assert!(glob("host1", "host?"));
+        assert!(glob("", "*"));
+        assert!(!glob("example.com", "*.example.com"));
+        assert!(!glob("host12", "host?"));
+        assert!(!glob("abc", "a*d"));
+    }
+
+    #[test]
+    fn host_functions() {
+        let call = |f: Helper, values: &[&str]| f(&args(values)).unwrap().to_string();
+        let lookup = |host: &str| (host == "intranet.test").then_some(Ipv4Addr::new(10, 1, 2, 3));
+        let dns = |f: DnsHelper, values: &[&str]| f(&lookup, &args(values)).unwrap().to_string();
+
+        assert_eq!(call(is_plain_host_name, &["intranet"]), "true");
+        assert_eq!(call(dns_domain_is, &["www.Corp.com", ".corp.com"]), "true");
+        assert_eq!(
+            call(dns_domain_is, &["www.corp.com.evil", ".corp.com"]),
+            "false"
+        );
+        assert_eq!(
+            call(local_host_or_domain_is, &["www", "www.corp.com"]),
+            "true"
+        );
+        assert_eq!(
+            call(local_host_or_domain_is, &["www.x.com", "www.corp.com"]),
+            "false"
+        );
+        assert_eq!(
+            dns(is_in_net, &["10.1.2.3", "10.0.0.0", "255.0.0.0"]),
+            "true"
+        );
+        assert_eq!(
+            dns(is_in_net, &["11.1.2.3", "10.0.0.0", "255.0.0.0"]),
+            "false"
+        );
+        assert_eq!(
+            dns(is_in_net, &["intranet.test", "10.0.0.0", "255.0.0.0"]),
+            "true"
+        );
+        assert_eq!(dns(dns_resolve, &["192.168.0.1"]), "192.168.0.1");
+        assert_eq!(dns(dns_resolve, &["intranet.test"]), "10.1.2.3");
+        assert_eq!(dns(dns_resolve, &["other.test"]), "null");
+        assert_eq!(dns(is_resolvable, &["other.test"]), "false");
+        assert_eq!(call(convert_addr, &["1.0.0.2"]), "16777218");
+        assert_eq!(call(dns_domain_levels, &["www.corp.com"]), "2");
+    }
+
+    #[test]
+    fn date_from_unix() {
+        // 2024-02-29 13:14:15, a Thursday.
+        let date = DateTime::from_unix(1709212455);
+        assert_eq!(
+            date,
+            DateTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                weekday: 4,
+                hour: 13,
+                minute: 14,
+                second: 15,
+            }
+        );
+    }
+
+    #[test]
+    fn time_ranges() {
+        let now = DateTime::from_unix(1709212455);
+
+        assert!(weekdays_match(&args(&["THU"]), now));
+        assert!(weekdays_match(&args(&["MON", "FRI"]), now));
+        assert!(weekdays_match(&args(&["FRI", "THU"]), now));
+        assert!(!weekdays_match(&args(&["FRI", "WED"]), now));
+        assert!(weekdays_match(time_args(&args(&["THU", "GMT"])), now));
+
+        assert!(dates_match(&args(&["29"]), now));
+        assert!(dates_match(&args(&["FEB"]), now));
+        assert!(dates_match(&args(&["2024"]), now));
+        assert!(dates_match(&args(&["NOV", "MAR"]), now));
+        assert!(!dates_match(&args(&["MAR", "NOV"]), now));
+        assert!(dates_match(&args(&["1", "FEB", "1", "MAR"]), now));
+        assert!(dates_match(&args(&["FEB", "2024", "MAR", "2024"]), now));
+        assert!(!dates_match(
+            &args(&["1", "JAN", "2023", "31", "DEC", "2023"]),
+            now
+        ));
+        assert!(!dates_match(&args(&["1", "FEB", "2024"]), now));
+
+        assert!(times_match(&args(&["13"]), now));
+        assert!(times_match(&args(&["9", "17"]), now));
+        assert!(times_match(&args(&["13", "0", "13", "14"]), now));
+        assert!(!times_match(&args(&["13", "15", "14", "0"]), now));
+        assert!(times_match(
+            &args(&["13", "14", "15", "13", "14", "15"]),
+            now
+        ));
+    }
+}
@@ -0,0 +1,1982 @@
+//! A small JavaScript interpreter for proxy auto-config scripts.
+//!
+//! PAC scripts are short ES5 functions working on strings. This covers statements,
+//! operators, functions and closures, strings, arrays, plain objects and the common
+//! string methods. Regular expressions, `new`, `this`, prototypes and exceptions are
+//! not supported, and scripts using them fail to parse or evaluate.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Max number of statements and loop iterations in one evaluation.
+const MAX_STEPS: usize = 1_000_000;
+
+/// Max depth of function calls.
+const MAX_CALL_DEPTH: usize = 100;
+
+/// Max nesting of statements and expressions when parsing.
+const MAX_NESTING: usize = 100;
+
+/// Max length of an array grown by assigning to an index.
+const MAX_ARRAY_LEN: usize = 100_000;
+
+/// Max length in bytes of a string built by a script.
+const MAX_STRING_LEN: usize = 1024 * 1024;
+
+// ---------------------------------------------------------------- lexer
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+    Eof,
+}
+
+// Longest first, since the first match wins.
+const PUNCTS: &[&str] = &[
+    ">>>", "===", "!==", "==", "!=", "<=", ">=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=",
+    "%=", "<<", ">>", "=", "<", ">", "+", "-", "*", "/", "%", "!", "~", "?", ":", ",", ";", "(",
+    ")", "{", "}", "[", "]", ".", "&", "|", "^",
+];
+
+const KEYWORDS: &[&str] = &[
+    "break", "case", "const", "continue", "default", "do", "else", "false", "for", "function",
+    "if", "in", "let", "new", "null", "return", "switch", "this", "throw", "true", "try", "typeof",
+    "var", "while",
+];
+
+/// Tokens with the line they start on.
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("line {}: unterminated comment", line)),
+                    Some('*') if chars.get(i + 1) == Some(&'/') => break,
+                    Some('\n') => line += 1,
+                    _ => {}
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) {
+            let start = i;
+            let n = if c == '0' && matches!(next, Some('x' | 'X')) {
+                i += 2;
+                while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+                let hex: String = chars[start + 2..i].iter().collect();
+                u64::from_str_radix(&hex, 16).map(|n| n as f64).ok()
+            } else {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                if matches!(chars.get(i), Some('e' | 'E')) {
+                    i += 1;
+                    if matches!(chars.get(i), Some('+' | '-')) {
+                        i += 1;
+                    }
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                let num: String = chars[start..i].iter().collect();
+                num.parse().ok()
+            };
+            let n = n.ok_or_else(|| format!("line {}: bad number", line))?;
+            tokens.push((Token::Num(n), line));
+        } else if c == '"' || c == '\'' {
+            let (s, end) = string_literal(&chars, i, &mut line)?;
+            tokens.push((Token::Str(s), line));
+            i = end;
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), line));
+        } else {
+            let punct = PUNCTS
+                .iter()
+                .find(|p| {
+                    p.chars()
+                        .enumerate()
+                        .all(|(n, pc)| chars.get(i + n) == Some(&pc))
+                })
+                .ok_or_else(|| format!("line {}: unexpected character '{}'", line, c))?;
+            tokens.push((Token::Punct(punct), line));
+            i += punct.len();
+        }
+    }
+
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+/// Parse the string literal starting with the quote at `start`.
+///
+/// Returns the string and the index after the closing quote.
+fn string_literal(
+    chars: &[char],
+    start: usize,
+    line: &mut usize,
+) -> Result<(String, usize), String> {
+    let quote = chars[start];
+    let mut s = String::new();
+    let mut i = start + 1;
+
+    let hex = |from: usize, len: usize| -> Option<char> {
+        let digits: String = chars.get(from..from + len)?.iter().collect();
+        char::from_u32(u32::from_str_radix(&digits, 16).ok()?)
+    };
+
+    loop {
+        let Some(&c) = chars.get(i) else {
+            return Err(format!("line {}: unterminated string", line));
+        };
+        i += 1;
+
+        match c {
+            '\n' => return Err(format!("line {}: unterminated string", line)),
+            c if c == quote => return Ok((s, i)),
+            '\\' => {
+                let Some(&e) = chars.get(i) else { continue };
+                i += 1;
+                match e {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'v' => s.push('\u{b}'),
+                    '0' => s.push('\0'),
+                    '\n' => *line += 1,
+                    'x' => {
+                        s.push(hex(i, 2).ok_or_else(|| format!("line {}: bad escape", line))?);
+                        i += 2;
+                    }
+                    'u' => {
+                        s.push(hex(i, 4).ok_or_else(|| format!("line {}: bad escape", line))?);
+                        i += 4;
+                    }
+                    e => s.push(e),
+                }
+            }
+            c => s.push(c),
+        }
+    }
+}
+
+// ---------------------------------------------------------------- syntax
+
+#[derive(Debug)]
+pub(crate) struct Func {
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Var(Vec<(String, Option<Expr>)>),
+    Func(String, Func),
+    Expr(Expr),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    DoWhile(Box<Stmt>, Expr),
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    ForIn(bool, String, Expr, Box<Stmt>),
+    Switch(Expr, Vec<(Option<Expr>, Vec<Stmt>)>),
+    Block(Vec<Stmt>),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Empty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    StrictEq,
+    StrictNe,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    UShr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnOp {
+    Not,
+    Neg,
+    Plus,
+    BitNot,
+    TypeOf,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    Null,
+    Ident(String),
+    Array(Vec<Expr>),
+    Object(Vec<(String, Expr)>),
+    Func(Func),
+    Unary(UnOp, Box<Expr>),
+    Update(Box<Expr>, f64, bool),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Logical(bool, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(Option<BinOp>, Box<Expr>, Box<Expr>),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+}
+
+/// Binary operators from lowest to highest precedence.
+const BINARY: &[&[(&str, BinOp)]] = &[
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[
+        ("==", BinOp::Eq),
+        ("!=", BinOp::Ne),
+        ("===", BinOp::StrictEq),
+        ("!==", BinOp::StrictNe),
+    ],
+    &[
+        ("<", BinOp::Lt),
+        (">", BinOp::Gt),
+        ("<=", BinOp::Le),
+        (">=", BinOp::Ge),
+    ],
+    &[("<<", BinOp::Shl), (">>", BinOp::Shr), (">>>", BinOp::UShr)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+];
+
+const ASSIGN: &[(&str, Option<BinOp>)] = &[
+    ("=", None),
+    ("+=", Some(BinOp::Add)),
+    ("-=", Some(BinOp::Sub)),
+    ("*=", Some(BinOp::Mul)),
+    ("/=", Some(BinOp::Div)),
+    ("%=", Some(BinOp::Rem)),
+];
+
+/// A parsed script.
+#[derive(Debug)]
+pub(crate) struct Script {
+    body: Vec<Stmt>,
+}
+
+impl Script {
+    pub fn parse(src: &str) -> Result<Script, String> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+            nesting: 0,
+        };
+
+        let mut body = vec![];
+        while parser.peek() != &Token::Eof {
+            body.push(parser.statement()?);
+        }
+
+        Ok(Script { body })
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    nesting: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn peek_at(&self, n: usize) -> &Token {
+        let i = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[i].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, what: &str) -> Result<T, String> {
+        let found = match self.peek() {
+            Token::Num(n) => n.to_string(),
+            Token::Str(s) => format!("{:?}", s),
+            Token::Ident(s) => s.clone(),
+            Token::Punct(p) => p.to_string(),
+            Token::Eof => "end of script".to_string(),
+        };
+        Err(format!(
+            "line {}: expected {}, found '{}'",
+            self.line(),
+            what,
+            found
+        ))
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Token::Punct(t) if *t == p)
+    }
+
+    fn is_keyword(&self, k: &str) -> bool {
+        matches!(self.peek(), Token::Ident(t) if t == k)
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        let is = self.is_punct(p);
+        if is {
+            self.next();
+        }
+        is
+    }
+
+    fn eat_keyword(&mut self, k: &str) -> bool {
+        let is = self.is_keyword(k);
+        if is {
+            self.next();
+        }
+        is
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<(), String> {
+        if !self.eat_punct(p) {
+            return self.error(&format!("'{}'", p));
+        }
+        Ok(())
+    }
+
+    /// An identifier which isn't a keyword.
+    fn ident(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Token::Ident(s) if !KEYWORDS.contains(&s.as_str()) => {
+                let s = s.clone();
+                self.next();
+                Ok(s)
+            }
+            _ => self.error("identifier"),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.nesting += 1;
+        if self.nesting > MAX_NESTING {
+            return Err(format!("line {}: nesting too deep", self.line()));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.nesting -= 1;
+    }
+
+    /// Semicolon at the end of a statement, which can be left out.
+    fn end_statement(&mut self) {
+        self.eat_punct(";");
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        self.enter()?;
+        let stmt = self.do_statement()?;
+        self.leave();
+        Ok(stmt)
+    }
+
+    fn do_statement(&mut self) -> Result<Stmt, String> {
+        if self.eat_punct("{") {
+            return Ok(Stmt::Block(self.block_rest()?));
+        }
+        if self.eat_punct(";") {
+            return Ok(Stmt::Empty);
+        }
+
+        let Token::Ident(word) = self.peek() else {
+            let expr = self.expr()?;
+            self.end_statement();
+            return Ok(Stmt::Expr(expr));
+        };
+
+        let stmt = match word.as_str() {
+            "var" | "let" | "const" => {
+                self.next();
+                let decl = self.var_decl()?;
+                self.end_statement();
+                decl
+            }
+            "function" => {
+                self.next();
+                let name = self.ident()?;
+                Stmt::Func(name, self.function_rest()?)
+            }
+            "if" => {
+                self.next();
+                self.expect_punct("(")?;
+                let cond = self.expr()?;
+                self.expect_punct(")")?;
+                let then = Box::new(self.statement()?);
+                let other = if self.eat_keyword("else") {
+                    Some(Box::new(self.statement()?))
+                } else {
+                    None
+                };
+                Stmt::If(cond, then, other)
+            }
+            "while" => {
+                self.next();
+                self.expect_punct("(")?;
+                let cond = self.expr()?;
+                self.expect_punct(")")?;
+                Stmt::While(cond, Box::new(self.statement()?))
+            }
+            "do" => {
+                self.next();
+                let body = Box::new(self.statement()?);
+                if !self.eat_keyword("while") {
+                    return self.error("'while'");
+                }
+                self.expect_punct("(")?;
+                let cond = self.expr()?;
+                self.expect_punct(")")?;
+                self.end_statement();
+                Stmt::DoWhile(body, cond)
+            }
+            "for" => {
+                self.next();
+                self.for_rest()?
+            }
+            "switch" => {
+                self.next();
+                self.switch_rest()?
+            }
+            "return" => {
+                let line = self.line();
+                self.next();
+                let value = if self.is_punct(";") || self.is_punct("}") || self.line() > line {
+                    None
+                } else {
+                    Some(self.expr()?)
+                };
+                self.end_statement();
+                Stmt::Return(value)
+            }
+            "break" => {
+                self.next();
+                self.end_statement();
+                Stmt::Break
+            }
+            "continue" => {
+                self.next();
+                self.end_statement();
+                Stmt::Continue
+            }
+            "new" | "this" | "throw" | "try" => {
+                return Err(format!("line {}: '{}' is not supported", self.line(), word));
+            }
+            _ => {
+                let expr = self.expr()?;
+                self.end_statement();
+                Stmt::Expr(expr)
+            }
+        };
+
+        Ok(stmt)
+    }
+
+    /// Statements up to and including the closing `}`.
+    fn block_rest(&mut self) -> Result<Vec<Stmt>, String> {
+        let mut body = vec![];
+        while !self.eat_punct("}") {
+            if self.peek() == &Token::Eof {
+                return self.error("'}'");
+            }
+            body.push(self.statement()?);
+        }
+        Ok(body)
+    }
+
+    fn var_decl(&mut self) -> Result<Stmt, String> {
+        let mut decls = vec![];
+        loop {
+            let name = self.ident()?;
+            let init = if self.eat_punct("=") {
+                Some(self.assignment()?)
+            } else {
+                None
+            };
+            decls.push((name, init));
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        Ok(Stmt::Var(decls))
+    }
+
+    /// Parameters and body of a function, after the name.
+    fn function_rest(&mut self) -> Result<Func, String> {
+        self.expect_punct("(")?;
+        let mut params = vec![];
+        while !self.eat_punct(")") {
+            params.push(self.ident()?);
+            if !self.is_punct(")") {
+                self.expect_punct(",")?;
+            }
+        }
+        self.expect_punct("{")?;
+        let body = self.block_rest()?;
+        Ok(Func { params, body })
+    }
+
+    fn for_rest(&mut self) -> Result<Stmt, String> {
+        self.expect_punct("(")?;
+
+        let is_decl =
+            matches!(self.peek(), Token::Ident(k) if k == "var" || k == "let" || k == "const");
+        let name_at = if is_decl { 1 } else { 0 };
+        let is_for_in = matches!(self.peek_at(name_at), Token::Ident(_))
+            && matches!(self.peek_at(name_at + 1), Token::Ident(k) if k == "in");
+
+        if is_for_in {
+            if is_decl {
+                self.next();
+            }
+            let name = self.ident()?;
+            self.next();
+            let object = self.expr()?;
+            self.expect_punct(")")?;
+            let body = Box::new(self.statement()?);
+            return Ok(Stmt::ForIn(is_decl, name, object, body));
+        }
+
+        let init = if self.is_punct(";") {
+            None
+        } else if is_decl {
+            self.next();
+            Some(Box::new(self.var_decl()?))
+        } else {
+            Some(Box::new(Stmt::Expr(self.expr()?)))
+        };
+        self.expect_punct(";")?;
+
+        let cond = if self.is_punct(";") {
+            None
+        } else {
+            Some(self.expr()?)
+        };
+        self.expect_punct(";")?;
+
+        let update = if self.is_punct(")") {
+            None
+        } else {
+            Some(self.expr()?)
+        };
+        self.expect_punct(")")?;
+
+        let body = Box::new(self.statement()?);
+        Ok(Stmt::For(init, cond, update, body))
+    }
+
+    fn switch_rest(&mut self) -> Result<Stmt, String> {
+        self.expect_punct("(")?;
+        let value = self.expr()?;
+        self.expect_punct(")")?;
+        self.expect_punct("{")?;
+
+        let mut cases = vec![];
+        while !self.eat_punct("}") {
+            let test = if self.eat_keyword("case") {
+                Some(self.expr()?)
+            } else if self.eat_keyword("default") {
+                None
+            } else {
+                return self.error("'case'");
+            };
+            self.expect_punct(":")?;
+
+            let mut body = vec![];
+            while !self.is_keyword("case") && !self.is_keyword("default") && !self.is_punct("}") {
+                if self.peek() == &Token::Eof {
+                    return self.error("'}'");
+                }
+                body.push(self.statement()?);
+            }
+            cases.push((test, body));
+        }
+
+        Ok(Stmt::Switch(value, cases))
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        self.enter()?;
+        let target = self.conditional()?;
+
+        let op = ASSIGN
+            .iter()
+            .find(|(p, _)| self.is_punct(p))
+            .map(|(_, op)| *op);
+
+        let expr = if let Some(op) = op {
+            if !matches!(target, Expr::Ident(_) | Expr::Member(..) | Expr::Index(..)) {
+                return Err(format!("line {}: invalid assignment target", self.line()));
+            }
+            self.next();
+            let value = self.assignment()?;
+            Expr::Assign(op, Box::new(target), Box::new(value))
+        } else {
+            target
+        };
+
+        self.leave();
+        Ok(expr)
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let cond = self.logical_or()?;
+        if !self.eat_punct("?") {
+            return Ok(cond);
+        }
+        let then = self.assignment()?;
+        self.expect_punct(":")?;
+        let other = self.assignment()?;
+        Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(other)))
+    }
+
+    fn logical_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.logical_and()?;
+        while self.eat_punct("||") {
+            let right = self.logical_and()?;
+            left = Expr::Logical(false, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn logical_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.binary(0)?;
+        while self.eat_punct("&&") {
+            let right = self.binary(0)?;
+            left = Expr::Logical(true, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == BINARY.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some((_, op)) = BINARY[level].iter().find(|(p, _)| self.is_punct(p)) {
+            self.next();
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(*op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Token::Punct("!") => Some(UnOp::Not),
+            Token::Punct("-") => Some(UnOp::Neg),
+            Token::Punct("+") => Some(UnOp::Plus),
+            Token::Punct("~") => Some(UnOp::BitNot),
+            Token::Ident(k) if k == "typeof" => Some(UnOp::TypeOf),
+            _ => None,
+        };
+
+        if let Some(op) = op {
+            self.enter()?;
+            self.next();
+            let expr = Expr::Unary(op, Box::new(self.unary()?));
+            self.leave();
+            return Ok(expr);
+        }
+
+        for (p, delta) in [("++", 1.0), ("--", -1.0)] {
+            if self.eat_punct(p) {
+                let target = self.unary()?;
+                return self.update(target, delta, true);
+            }
+        }
+
+        self.postfix()
+    }
+
+    fn update(&self, target: Expr, delta: f64, prefix: bool) -> Result<Expr, String> {
+        if !matches!(target, Expr::Ident(_) | Expr::Member(..) | Expr::Index(..)) {
+            return Err(format!("line {}: invalid increment target", self.line()));
+        }
+        Ok(Expr::Update(Box::new(target), delta, prefix))
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+
+        loop {
+            let line = self.line();
+            if self.eat_punct(".") {
+                // Keywords are fine as property names.
+                let Token::Ident(name) = self.next() else {
+                    return self.error("property name");
+                };
+                expr = Expr::Member(Box::new(expr), name);
+            } else if self.eat_punct("[") {
+                let index = self.expr()?;
+                self.expect_punct("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat_punct("(") {
+                let args = self.list(")")?;
+                expr = Expr::Call(Box::new(expr), args);
+            } else if self.is_punct("++") && self.tokens[self.pos - 1].1 == line {
+                self.next();
+                return self.update(expr, 1.0, false);
+            } else if self.is_punct("--") && self.tokens[self.pos - 1].1 == line {
+                self.next();
+                return self.update(expr, -1.0, false);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Comma separated expressions up to and including `end`.
+    fn list(&mut self, end: &str) -> Result<Vec<Expr>, String> {
+        let mut items = vec![];
+        while !self.eat_punct(end) {
+            items.push(self.assignment()?);
+            if !self.is_punct(end) {
+                self.expect_punct(",")?;
+            }
+        }
+        Ok(items)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let expr = match self.peek().clone() {
+            Token::Num(n) => Expr::Num(n),
+            Token::Str(s) => Expr::Str(s),
+            Token::Punct("(") => {
+                self.next();
+                let expr = self.expr()?;
+                self.expect_punct(")")?;
+                return Ok(expr);
+            }
+            Token::Punct("[") => {
+                self.next();
+                return Ok(Expr::Array(self.list("]")?));
+            }
+            Token::Punct("{") => {
+                self.next();
+                return self.object_rest();
+            }
+            Token::Punct("/") => {
+                return Err(format!(
+                    "line {}: regular expressions are not supported",
+                    self.line()
+                ));
+            }
+            Token::Ident(word) => match word.as_str() {
+                "true" => Expr::Bool(true),
+                "false" => Expr::Bool(false),
+                "null" => Expr::Null,
+                "function" => {
+                    self.next();
+                    if matches!(self.peek(), Token::Ident(_)) {
+                        self.ident()?;
+                    }
+                    return Ok(Expr::Func(self.function_rest()?));
+                }
+                w if KEYWORDS.contains(&w) => {
+                    return Err(format!(
+                        "line {}: '{}' is not supported here",
+                        self.line(),
+                        w
+                    ));
+                }
+                _ => Expr::Ident(word),
+            },
+            _ => return self.error("expression"),
+        };
+
+        self.next();
+        Ok(expr)
+    }
+
+    fn object_rest(&mut self) -> Result<Expr, String> {
+        let mut props = vec![];
+        while !self.eat_punct("}") {
+            let key = match self.next() {
+                Token::Ident(s) | Token::Str(s) => s,
+                Token::Num(n) => number_to_string(n),
+                _ => return self.error("property name"),
+            };
+            self.expect_punct(":")?;
+            props.push((key, self.assignment()?));
+            if !self.is_punct("}") {
+                self.expect_punct(",")?;
+            }
+        }
+        Ok(Expr::Object(props))
+    }
+}
+
+// ---------------------------------------------------------------- values
+
+/// A function implemented in Rust.
+pub(crate) type Native<'a> = Rc<dyn Fn(&[Value<'a>]) -> Result<Value<'a>, String> + 'a>;
+
+#[derive(Clone)]
+pub(crate) enum Value<'a> {
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Array(Rc<RefCell<Vec<Value<'a>>>>),
+    Object(Rc<RefCell<Vec<(String, Value<'a>)>>>),
+    Function(Rc<Closure<'a>>),
+    Native(Native<'a>),
+}
+
+pub(crate) struct Closure<'a> {
+    func: &'a Func,
+    env: Env<'a>,
+}
+
+impl<'a> Value<'a> {
+    pub fn str(s: &str) -> Self {
+        Value::Str(s.into())
+    }
+
+    pub fn native(f: impl Fn(&[Value<'a>]) -> Result<Value<'a>, String> + 'a) -> Self {
+        Value::Native(Rc::new(f))
+    }
+
+    pub fn to_bool(&self) -> bool {
+        match self {
+            Value::Undefined | Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::Str(s) => !s.is_empty(),
+            _ => true,
+        }
+    }
+
+    pub fn to_number(&self) -> f64 {
+        match self {
+            Value::Undefined => f64::NAN,
+            Value::Null => 0.0,
+            Value::Bool(b) => *b as u8 as f64,
+            Value::Number(n) => *n,
+            Value::Str(s) => string_to_number(s),
+            Value::Array(_) => string_to_number(&self.to_string()),
+            _ => f64::NAN,
+        }
+    }
+
+    fn to_int32(&self) -> i32 {
+        let n = self.to_number();
+        if !n.is_finite() {
+            return 0;
+        }
+        (n.trunc() % 4294967296.0) as i64 as u32 as i32
+    }
+
+    fn is_primitive(&self) -> bool {
+        !matches!(
+            self,
+            Value::Array(_) | Value::Object(_) | Value::Function(_) | Value::Native(_)
+        )
+    }
+
+    fn type_of(&self) -> &'static str {
+        match self {
+            Value::Undefined => "undefined",
+            Value::Null | Value::Array(_) | Value::Object(_) => "object",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+        }
+    }
+
+    /// JavaScript ToString, failing for strings longer than [`MAX_STRING_LEN`].
+    pub fn to_js_string(&self) -> Result<String, String> {
+        let mut out = String::new();
+        self.write(&mut out, 0)?;
+        Ok(out)
+    }
+
+    fn write(&self, out: &mut String, depth: usize) -> Result<(), String> {
+        match self {
+            Value::Undefined => out.push_str("undefined"),
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Number(n) => out.push_str(&number_to_string(*n)),
+            Value::Str(s) => out.push_str(s),
+            // Arrays can contain themselves.
+            Value::Array(_) if depth > 10 => {}
+            Value::Array(a) => {
+                for (i, v) in a.borrow().iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    if !matches!(v, Value::Undefined | Value::Null) {
+                        v.write(out, depth + 1)?;
+                    }
+                    check_len(out)?;
+                }
+            }
+            Value::Object(_) => out.push_str("[object Object]"),
+            Value::Function(_) | Value::Native(_) => out.push_str("function"),
+        }
+        check_len(out)
+    }
+}
+
+/// JavaScript ToString, cut short at [`MAX_STRING_LEN`] where failing is not an option.
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        let _ = self.write(&mut out, 0);
+        f.write_str(&out)
+    }
+}
+
+fn check_len(s: &str) -> Result<(), String> {
+    if s.len() > MAX_STRING_LEN {
+        return Err("string too long".to_string());
+    }
+    Ok(())
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == n.trunc() && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        format!("{}", n)
+    }
+}
+
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim();
+    if s.is_empty() {
+        return 0.0;
+    }
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return u64::from_str_radix(hex, 16).map_or(f64::NAN, |n| n as f64);
+    }
+    match s.trim_start_matches(['+', '-']) {
+        "Infinity" if s.starts_with('-') => f64::NEG_INFINITY,
+        "Infinity" => f64::INFINITY,
+        // Rust also accepts "inf" and "NaN", which JavaScript doesn't.
+        n if n.chars().all(|c| c.is_ascii_digit() || "eE.+-".contains(c)) => {
+            s.parse().unwrap_or(f64::NAN)
+        }
+        _ => f64::NAN,
+    }
+}
+
+fn strict_eq<'a>(a: &Value<'a>, b: &Value<'a>) -> bool {
+    match (a, b) {
+        (Value::Undefined, Value::Undefined) | (Value::Null, Value::Null) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+        (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+        (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+        (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+fn loose_eq<'a>(a: &Value<'a>, b: &Value<'a>) -> bool {
+    use Value::*;
+    match (a, b) {
+        (Undefined | Null, Undefined | Null) => true,
+        (Undefined | Null, _) | (_, Undefined | Null) => false,
+        (Bool(_), _) => loose_eq(&Number(a.to_number()), b),
+        (_, Bool(_)) => loose_eq(a, &Number(b.to_number())),
+        (Number(_), Str(_)) | (Str(_), Number(_)) => a.to_number() == b.to_number(),
+        _ if a.is_primitive() != b.is_primitive() => a.to_string() == b.to_string(),
+        _ => strict_eq(a, b),
+    }
+}
+
+fn binary<'a>(op: BinOp, a: &Value<'a>, b: &Value<'a>) -> Result<Value<'a>, String> {
+    use BinOp::*;
+    let num = |f: fn(f64, f64) -> f64| Value::Number(f(a.to_number(), b.to_number()));
+    let int = |f: fn(i32, i32) -> i32| Value::Number(f(a.to_int32(), b.to_int32()) as f64);
+
+    Ok(match op {
+        Add => {
+            // Objects become strings too, so their sum is a string.
+            let is_string = |v: &Value| matches!(v, Value::Str(_)) || !v.is_primitive();
+            if is_string(a) || is_string(b) {
+                let mut s = a.to_js_string()?;
+                s.push_str(&b.to_js_string()?);
+                check_len(&s)?;
+                Value::Str(s.into())
+            } else {
+                num(|a, b| a + b)
+            }
+        }
+        Sub => num(|a, b| a - b),
+        Mul => num(|a, b| a * b),
+        Div => num(|a, b| a / b),
+        Rem => num(|a, b| a % b),
+        Eq => Value::Bool(loose_eq(a, b)),
+        Ne => Value::Bool(!loose_eq(a, b)),
+        StrictEq => Value::Bool(strict_eq(a, b)),
+        StrictNe => Value::Bool(!strict_eq(a, b)),
+        Lt | Gt | Le | Ge => {
+            let ordering = match (a, b) {
+                (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                _ => a.to_number().partial_cmp(&b.to_number()),
+            };
+            let Some(ordering) = ordering else {
+                return Ok(Value::Bool(false));
+            };
+            Value::Bool(match op {
+                Lt => ordering.is_lt(),
+                Gt => ordering.is_gt(),
+                Le => ordering.is_le(),
+                _ => ordering.is_ge(),
+            })
+        }
+        BitAnd => int(|a, b| a & b),
+        BitOr => int(|a, b| a | b),
+        BitXor => int(|a, b| a ^ b),
+        Shl => int(|a, b| a.wrapping_shl(b as u32 & 31)),
+        Shr => int(|a, b| a.wrapping_shr(b as u32 & 31)),
+        UShr => Value::Number(((a.to_int32() as u32) >> (b.to_int32() as u32 & 31)) as f64),
+    })
+}
+
+// ---------------------------------------------------------------- interpreter
+
+type Env<'a> = Rc<Scope<'a>>;
+
+struct Scope<'a> {
+    vars: RefCell<HashMap<String, Value<'a>>>,
+    parent: Option<Env<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    fn lookup(&self, name: &str) -> Option<Value<'a>> {
+        if let Some(v) = self.vars.borrow().get(name) {
+            return Some(v.clone());
+        }
+        self.parent.as_ref()?.lookup(name)
+    }
+
+    /// Set an existing variable, returning false if there is none.
+    fn set(&self, name: &str, value: Value<'a>) -> bool {
+        if let Some(v) = self.vars.borrow_mut().get_mut(name) {
+            *v = value;
+            return true;
+        }
+        self.parent.as_ref().is_some_and(|p| p.set(name, value))
+    }
+
+    fn declare(&self, name: &str, value: Value<'a>) {
+        self.vars.borrow_mut().insert(name.to_string(), value);
+    }
+}
+
+enum Flow<'a> {
+    Normal,
+    Return(Value<'a>),
+    Break,
+    Continue,
+}
+
+/// Evaluation of a [`Script`].
+///
+/// Values borrow the functions of the script, so the interpreter can't
+/// outlive it.
+pub(crate) struct Interp<'a> {
+    global: Env<'a>,
+    // All scopes, to break the reference cycles of closures on drop.
+    scopes: Vec<Env<'a>>,
+    steps: usize,
+    depth: usize,
+}
+
+impl<'a> Interp<'a> {
+    pub fn new() -> Self {
+        let global = Rc::new(Scope {
+            vars: RefCell::new(HashMap::new()),
+            parent: None,
+        });
+
+        let mut interp = Interp {
+            global: global.clone(),
+            scopes: vec![global],
+            steps: 0,
+            depth: 0,
+        };
+
+        interp.define("undefined", Value::Undefined);
+        interp.define("NaN", Value::Number(f64::NAN));
+        interp.define("Infinity", Value::Number(f64::INFINITY));
+        interp.define("parseInt", Value::native(parse_int));
+        interp.define("parseFloat", Value::native(parse_float));
+        interp.define("isNaN", Value::native(is_nan));
+
+        interp
+    }
+
+    pub fn define(&mut self, name: &str, value: Value<'a>) {
+        self.global.declare(name, value);
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value<'a>> {
+        self.global.lookup(name)
+    }
+
+    /// Run the top level statements of the script.
+    pub fn run(&mut self, script: &'a Script) -> Result<(), String> {
+        let global = self.global.clone();
+        self.exec_block(&script.body, &global)?;
+        Ok(())
+    }
+
+    /// Call a global function.
+    pub fn call(&mut self, name: &str, args: Vec<Value<'a>>) -> Result<Value<'a>, String> {
+        let f = self
+            .global(name)
+            .ok_or_else(|| format!("{} is not defined", name))?;
+        self.call_value(&f, args)
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err("script takes too long".to_string());
+        }
+        Ok(())
+    }
+
+    fn new_scope(&mut self, parent: &Env<'a>) -> Env<'a> {
+        let scope = Rc::new(Scope {
+            vars: RefCell::new(HashMap::new()),
+            parent: Some(parent.clone()),
+        });
+        self.scopes.push(scope.clone());
+        scope
+    }
+
+    fn closure(&self, func: &'a Func, env: &Env<'a>) -> Value<'a> {
+        Value::Function(Rc::new(Closure {
+            func,
+            env: env.clone(),
+        }))
+    }
+
+    fn call_value(&mut self, f: &Value<'a>, args: Vec<Value<'a>>) -> Result<Value<'a>, String> {
+        match f {
+            Value::Native(f) => f(&args),
+            Value::Function(closure) => {
+                if self.depth >= MAX_CALL_DEPTH {
+                    return Err("too much recursion".to_string());
+                }
+
+                let scope = self.new_scope(&closure.env);
+                for (i, param) in closure.func.params.iter().enumerate() {
+                    scope.declare(param, args.get(i).cloned().unwrap_or(Value::Undefined));
+                }
+                scope.declare("arguments", Value::Array(Rc::new(RefCell::new(args))));
+
+                self.depth += 1;
+                let flow = self.exec_block(&closure.func.body, &scope);
+                self.depth -= 1;
+
+                match flow? {
+                    Flow::Return(v) => Ok(v),
+                    _ => Ok(Value::Undefined),
+                }
+            }
+            v => Err(format!("{} is not a function", v.type_of())),
+        }
+    }
+
+    fn exec_block(&mut self, stmts: &'a [Stmt], env: &Env<'a>) -> Result<Flow<'a>, String> {
+        // Function declarations can be used before they appear.
+        for stmt in stmts {
+            if let Stmt::Func(name, func) = stmt {
+                env.declare(name, self.closure(func, env));
+            }
+        }
+
+        for stmt in stmts {
+            match self.exec(stmt, env)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Run a loop body, returning Some to leave the loop.
+    fn exec_loop_body(
+        &mut self,
+        body: &'a Stmt,
+        env: &Env<'a>,
+    ) -> Result<Option<Flow<'a>>, String> {
+        self.step()?;
+        Ok(match self.exec(body, env)? {
+            Flow::Break => Some(Flow::Normal),
+            Flow::Return(v) => Some(Flow::Return(v)),
+            Flow::Normal | Flow::Continue => None,
+        })
+    }
+
+    fn exec(&mut self, stmt: &'a Stmt, env: &Env<'a>) -> Result<Flow<'a>, String> {
+        self.step()?;
+
+        match stmt {
+            Stmt::Var(decls) => {
+                for (name, init) in decls {
+                    match init {
+                        Some(init) => {
+                            let value = self.eval(init, env)?;
+                            env.declare(name, value);
+                        }
+                        None if !env.vars.borrow().contains_key(name) => {
+                            env.declare(name, Value::Undefined);
+                        }
+                        None => {}
+                    }
+                }
+            }
+            // Declared by exec_block.
+            Stmt::Func(..) | Stmt::Empty => {}
+            Stmt::Expr(expr) => {
+                self.eval(expr, env)?;
+            }
+            Stmt::If(cond, then, other) => {
+                if self.eval(cond, env)?.to_bool() {
+                    return self.exec(then, env);
+                } else if let Some(other) = other {
+                    return self.exec(other, env);
+                }
+            }
+            Stmt::While(cond, body) => {
+                while self.eval(cond, env)?.to_bool() {
+                    if let Some(flow) = self.exec_loop_body(body, env)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            Stmt::DoWhile(body, cond) => loop {
+                if let Some(flow) = self.exec_loop_body(body, env)? {
+                    return Ok(flow);
+                }
+                if !self.eval(cond, env)?.to_bool() {
+                    break;
+                }
+            },
+            Stmt::For(init, cond, update, body) => {
+                if let Some(init) = init {
+                    self.exec(init, env)?;
+                }
+                loop {
+                    if let Some(cond) = cond {
+                        if !self.eval(cond, env)?.to_bool() {
+                            break;
+                        }
+                    }
+                    if let Some(flow) = self.exec_loop_body(body, env)? {
+                        return Ok(flow);
+                    }
+                    if let Some(update) = update {
+                        self.eval(update, env)?;
+                    }
+                }
+            }
+            Stmt::ForIn(is_decl, name, object, body) => {
+                let keys: Vec<String> = match self.eval(object, env)? {
+                    Value::Array(a) => (0..a.borrow().len()).map(|i| i.to_string()).collect(),
+                    Value::Object(o) => o.borrow().iter().map(|(k, _)| k.clone()).collect(),
+                    Value::Str(s) => (0..s.chars().count()).map(|i| i.to_string()).collect(),
+                    _ => vec![],
+                };
+                for key in keys {
+                    let key = Value::str(&key);
+                    if *is_decl {
+                        env.declare(name, key);
+                    } else {
+                        self.assign_name(name, key, env);
+                    }
+                    if let Some(flow) = self.exec_loop_body(body, env)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            Stmt::Switch(value, cases) => {
+                let value = self.eval(value, env)?;
+
+                let mut start = None;
+                for (i, (test, _)) in cases.iter().enumerate() {
+                    if let Some(test) = test {
+                        if strict_eq(&value, &self.eval(test, env)?) {
+                            start = Some(i);
+                            break;
+                        }
+                    }
+                }
+                let start = start.or_else(|| cases.iter().position(|(test, _)| test.is_none()));
+
+                if let Some(start) = start {
+                    for (_, body) in &cases[start..] {
+                        match self.exec_block(body, env)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+                    }
+                }
+            }
+            Stmt::Block(stmts) => return self.exec_block(stmts, env),
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value, env)?,
+                    None => Value::Undefined,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Break => return Ok(Flow::Break),
+            Stmt::Continue => return Ok(Flow::Continue),
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    /// Assign a variable, which is global if it isn't declared.
+    fn assign_name(&self, name: &str, value: Value<'a>, env: &Env<'a>) {
+        if !env.set(name, value.clone()) {
+            self.global.declare(name, value);
+        }
+    }
+
+    fn eval(&mut self, expr: &'a Expr, env: &Env<'a>) -> Result<Value<'a>, String> {
+        Ok(match expr {
+            Expr::Num(n) => Value::Number(*n),
+            Expr::Str(s) => Value::str(s),
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Null => Value::Null,
+            Expr::Ident(name) => env
+                .lookup(name)
+                .ok_or_else(|| format!("{} is not defined", name))?,
+            Expr::Array(items) => {
+                let items = items
+                    .iter()
+                    .map(|e| self.eval(e, env))
+                    .collect::<Result<_, _>>()?;
+                Value::Array(Rc::new(RefCell::new(items)))
+            }
+            Expr::Object(props) => {
+                let mut values: Vec<(String, Value)> = vec![];
+                for (key, e) in props {
+                    let value = self.eval(e, env)?;
+                    match values.iter_mut().find(|(k, _)| k == key) {
+                        Some((_, v)) => *v = value,
+                        None => values.push((key.clone(), value)),
+                    }
+                }
+                Value::Object(Rc::new(RefCell::new(values)))
+            }
+            Expr::Func(func) => self.closure(func, env),
+            Expr::Unary(UnOp::TypeOf, e) => {
+                // typeof is the one way to use an undeclared variable.
+                let value = match &**e {
+                    Expr::Ident(name) => env.lookup(name).unwrap_or(Value::Undefined),
+                    e => self.eval(e, env)?,
+                };
+                Value::str(value.type_of())
+            }
+            Expr::Unary(op, e) => {
+                let value = self.eval(e, env)?;
+                match op {
+                    UnOp::Not => Value::Bool(!value.to_bool()),
+                    UnOp::Neg => Value::Number(-value.to_number()),
+                    UnOp::Plus => Value::Number(value.to_number()),
+                    UnOp::BitNot => Value::Number(!value.to_int32() as f64),
+                    UnOp::TypeOf => unreachable!(),
+                }
+            }
+            Expr::Update(target, delta, prefix) => {
+                let old = self.eval(target, env)?.to_number();
+                let new = old + delta;
+                self.assign(target, Value::Number(new), env)?;
+                Value::Number(if *prefix { new } else { old })
+            }
+            Expr::Binary(op, a, b) => {
+                let a = self.eval(a, env)?;
+                let b = self.eval(b, env)?;
+                binary(*op, &a, &b)?
+            }
+            Expr::Logical(is_and, a, b) => {
+                let a = self.eval(a, env)?;
+                if a.to_bool() == *is_and {
+                    self.eval(b, env)?
+                } else {
+                    a
+                }
+            }
+            Expr::Cond(cond, then, other) => {
+                if self.eval(cond, env)?.to_bool() {
+                    self.eval(then, env)?
+                } else {
+                    self.eval(other, env)?
+                }
+            }
+            Expr::Assign(op, target, value) => {
+                let mut value = self.eval(value, env)?;
+                if let Some(op) = op {
+                    let current = self.eval(target, env)?;
+                    value = binary(*op, &current, &value)?;
+                }
+                self.assign(target, value.clone(), env)?;
+                value
+            }
+            Expr::Member(object, name) => {
+                let object = self.eval(object, env)?;
+                property(&object, name)?
+            }
+            Expr::Index(object, index) => {
+                let object = self.eval(object, env)?;
+                let index = self.eval(index, env)?.to_string();
+                property(&object, &index)?
+            }
+            Expr::Call(callee, args) => {
+                let (this, name) = match &**callee {
+                    Expr::Member(object, name) => (Some(self.eval(object, env)?), name.clone()),
+                    Expr::Index(object, index) => {
+                        let object = self.eval(object, env)?;
+                        (Some(object), self.eval(index, env)?.to_string())
+                    }
+                    _ => (None, String::new()),
+                };
+
+                let args: Vec<Value> = args
+                    .iter()
+                    .map(|e| self.eval(e, env))
+                    .collect::<Result<_, _>>()?;
+
+                match this {
+                    Some(Value::Str(s)) => string_method(&s, &name, &args)?,
+                    Some(Value::Array(a)) if is_array_method(&name) => {
+                        array_method(&a, &name, &args)?
+                    }
+                    Some(this) => {
+                        let f = property(&this, &name)?;
+                        if matches!(f, Value::Undefined) {
+                            return Err(format!("{} is not a function", name));
+                        }
+                        self.call_value(&f, args)?
+                    }
+                    None => {
+                        let f = self.eval(callee, env)?;
+                        self.call_value(&f, args)?
+                    }
+                }
+            }
+        })
+    }
+
+    fn assign(&mut self, target: &'a Expr, value: Value<'a>, env: &Env<'a>) -> Result<(), String> {
+        let (object, key) = match target {
+            Expr::Ident(name) => {
+                self.assign_name(name, value, env);
+                return Ok(());
+            }
+            Expr::Member(object, name) => (self.eval(object, env)?, name.clone()),
+            Expr::Index(object, index) => {
+                let object = self.eval(object, env)?;
+                (object, self.eval(index, env)?.to_string())
+            }
+            _ => return Err("invalid assignment target".to_string()),
+        };
+
+        match object {
+            Value::Array(a) => {
+                let Ok(i) = key.parse::<usize>() else {
+                    // Such as "length", which we don't support changing.
+                    return Ok(());
+                };
+                if i >= MAX_ARRAY_LEN {
+                    return Err("array too long".to_string());
+                }
+                let mut a = a.borrow_mut();
+                if i >= a.len() {
+                    a.resize(i + 1, Value::Undefined);
+                }
+                a[i] = value;
+            }
+            Value::Object(o) => {
+                let mut o = o.borrow_mut();
+                match o.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, v)) => *v = value,
+                    None => o.push((key, value)),
+                }
+            }
+            Value::Undefined | Value::Null => {
+                return Err(format!("cannot set property '{}' of {}", key, object));
+            }
+            // Properties of primitives are silently dropped.
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Interp<'_> {
+    fn drop(&mut self) {
+        for scope in &self.scopes {
+            scope.vars.borrow_mut().clear();
+        }
+    }
+}
+
+fn property<'a>(object: &Value<'a>, key: &str) -> Result<Value<'a>, String> {
+    Ok(match object {
+        Value::Undefined | Value::Null => {
+            return Err(format!("cannot read property '{}' of {}", key, object));
+        }
+        Value::Str(s) if key == "length" => Value::Number(s.chars().count() as f64),
+        Value::Str(s) => match key.parse::<usize>() {
+            Ok(i) => s
+                .chars()
+                .nth(i)
+                .map(|c| Value::str(c.encode_utf8(&mut [0; 4])))
+                .unwrap_or(Value::Undefined),
+            Err(_) => Value::Undefined,
+        },
+        Value::Array(a) if key == "length" => Value::Number(a.borrow().len() as f64),
+        Value::Array(a) => match key.parse::<usize>() {
+            Ok(i) => a.borrow().get(i).cloned().unwrap_or(Value::Undefined),
+            Err(_) => Value::Undefined,
+        },
+        Value::Object(o) => o
+            .borrow()
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .unwrap_or(Value::Undefined),
+        _ => Value::Undefined,
+    })
+}
+
+fn arg<'a>(args: &[Value<'a>], i: usize) -> Value<'a> {
+    args.get(i).cloned().unwrap_or(Value::Undefined)
+}
+
+/// Integer argument as index into `len` items, where negative counts from the end
+/// if `from_end` is set.
+fn index_arg(args: &[Value], i: usize, len: usize, from_end: bool, default: usize) -> usize {
+    let n = match args.get(i) {
+        None | Some(Value::Undefined) => return default,
+        Some(v) => v.to_number(),
+    };
+    let n = if n.is_nan() { 0.0 } else { n.trunc() };
+    if n < 0.0 {
+        if from_end {
+            (len as f64 + n).max(0.0) as usize
+        } else {
+            0
+        }
+    } else {
+        (n as usize).min(len)
+    }
+}
+
+fn find_chars(haystack: &[char], needle: &[char], from: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(from.min(haystack.len()));
+    }
+    (from..haystack.len().saturating_sub(needle.len() - 1))
+        .find(|&i| haystack[i..].starts_with(needle))
+}
+
+fn string_method<'a>(s: &str, name: &str, args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let len = chars.len();
+    let string = |c: &[char]| Value::Str(c.iter().collect::<String>().into());
+    let arg_chars = |i: usize| -> Vec<char> { arg(args, i).to_string().chars().collect() };
+
+    Ok(match name {
+        "toString" | "valueOf" => Value::str(s),
+        "toLowerCase" => Value::str(&s.to_lowercase()),
+        "toUpperCase" => Value::str(&s.to_uppercase()),
+        "trim" => Value::str(s.trim()),
+        "charAt" => {
+            let i = index_arg(args, 0, len, false, 0);
+            string(chars.get(i..(i + 1).min(len)).unwrap_or_default())
+        }
+        "charCodeAt" => {
+            let i = index_arg(args, 0, len, false, 0);
+            Value::Number(chars.get(i).map_or(f64::NAN, |c| *c as u32 as f64))
+        }
+        "indexOf" => {
+            let from = index_arg(args, 1, len, false, 0);
+            let found = find_chars(&chars, &arg_chars(0), from);
+            Value::Number(found.map_or(-1.0, |i| i as f64))
+        }
+        "lastIndexOf" => {
+            let needle = arg_chars(0);
+            let last = (0..=len.saturating_sub(needle.len()))
+                .rev()
+                .find(|&i| chars[i..].starts_with(&needle));
+            Value::Number(last.map_or(-1.0, |i| i as f64))
+        }
+        "includes" => Value::Bool(find_chars(&chars, &arg_chars(0), 0).is_some()),
+        "startsWith" => Value::Bool(chars.starts_with(&arg_chars(0))),
+        "endsWith" => Value::Bool(chars.ends_with(&arg_chars(0))),
+        "substring" => {
+            let a = index_arg(args, 0, len, false, 0);
+            let b = index_arg(args, 1, len, false, len);
+            string(&chars[a.min(b)..a.max(b)])
+        }
+        "slice" => {
+            let a = index_arg(args, 0, len, true, 0);
+            let b = index_arg(args, 1, len, true, len);
+            string(chars.get(a..b).unwrap_or_default())
+        }
+        "substr" => {
+            let a = index_arg(args, 0, len, true, 0);
+            let n = index_arg(args, 1, len, false, len);
+            string(&chars[a..(a + n).min(len)])
+        }
+        "concat" => {
+            let mut s = s.to_string();
+            for v in args {
+                s.push_str(&v.to_js_string()?);
+                check_len(&s)?;
+            }
+            Value::Str(s.into())
+        }
+        "split" => {
+            let parts: Vec<Value> = match args.first() {
+                None | Some(Value::Undefined) => vec![Value::str(s)],
+                Some(sep) => {
+                    let sep = sep.to_string();
+                    if sep.is_empty() {
+                        chars.iter().map(|c| string(&[*c])).collect()
+                    } else {
+                        s.split(sep.as_str()).map(Value::str).collect()
+                    }
+                }
+            };
+            let limit = index_arg(args, 1, parts.len(), false, parts.len());
+            Value::Array(Rc::new(RefCell::new(parts[..limit].to_vec())))
+        }
+        "replace" => {
+            if let Some(Value::Function(_) | Value::Native(_)) = args.get(1) {
+                return Err("replace with a function is not supported".to_string());
+            }
+            let from = arg(args, 0).to_string();
+            let to = arg(args, 1).to_string();
+            Value::str(&s.replacen(&from, &to, 1))
+        }
+        _ => return Err(format!("{} is not a function", name)),
+    })
+}
+
+fn is_array_method(name: &str) -> bool {
+    matches!(
+        name,
+        "push" | "pop" | "indexOf" | "includes" | "join" | "toString"
+    )
+}
+
+fn array_method<'a>(
+    a: &Rc<RefCell<Vec<Value<'a>>>>,
+    name: &str,
+    args: &[Value<'a>],
+) -> Result<Value<'a>, String> {
+    Ok(match name {
+        "push" => {
+            let mut a = a.borrow_mut();
+            if a.len() + args.len() > MAX_ARRAY_LEN {
+                return Err("array too long".to_string());
+            }
+            a.extend(args.iter().cloned());
+            Value::Number(a.len() as f64)
+        }
+        "pop" => a.borrow_mut().pop().unwrap_or(Value::Undefined),
+        "indexOf" => {
+            let needle = arg(args, 0);
+            let found = a.borrow().iter().position(|v| strict_eq(v, &needle));
+            Value::Number(found.map_or(-1.0, |i| i as f64))
+        }
+        "includes" => {
+            let needle = arg(args, 0);
+            Value::Bool(a.borrow().iter().any(|v| strict_eq(v, &needle)))
+        }
+        "join" | "toString" => {
+            let sep = match args.first() {
+                Some(v) if name == "join" && !matches!(v, Value::Undefined) => v.to_string(),
+                _ => ",".to_string(),
+            };
+            let mut joined = String::new();
+            for (i, v) in a.borrow().iter().enumerate() {
+                if i > 0 {
+                    joined.push_str(&sep);
+                }
+                if !matches!(v, Value::Undefined | Value::Null) {
+                    v.write(&mut joined, 0)?;
+                }
+                check_len(&joined)?;
+            }
+            Value::Str(joined.into())
+        }
+        _ => unreachable!("is_array_method"),
+    })
+}
+
+fn parse_int<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    let s = arg(args, 0).to_string();
+    let s = s.trim();
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let mut radix = match args.get(1) {
+        None | Some(Value::Undefined) => 0,
+        Some(v) => v.to_int32() as u32,
+    };
+    let mut s = s;
+    if radix == 16 || radix == 0 {
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            s = hex;
+            radix = 16;
+        }
+    }
+    if radix == 0 {
+        radix = 10;
+    }
+    if !(2..=36).contains(&radix) {
+        return Ok(Value::Number(f64::NAN));
+    }
+
+    let digits: String = s.chars().take_while(|c| c.is_digit(radix)).collect();
+    if digits.is_empty() {
+        return Ok(Value::Number(f64::NAN));
+    }
+    let n = digits.chars().fold(0.0, |n, c| {
+        n * radix as f64 + c.to_digit(radix).unwrap() as f64
+    });
+
+    Ok(Value::Number(if negative { -n } else { n }))
+}
+
+fn parse_float<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    let s = arg(args, 0).to_string();
+    let s = s.trim_start();
+    // Longest prefix that is a number.
+    let n = (1..=s.len())
+        .rev()
+        .filter(|i| s.is_char_boundary(*i))
+        .find_map(|i| {
+            let prefix = &s[..i];
+            let n = string_to_number(prefix);
+            (!n.is_nan() && !prefix.ends_with(char::is_whitespace)).then_some(n)
+        })
+        .unwrap_or(f64::NAN);
+    Ok(Value::Number(n))
+}
+
+fn is_nan<'a>(args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    Ok(Value::Bool(arg(args, 0).to_number().is_nan()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(src: &str) -> Result<String, String> {
+        let script = Script::parse(&format!("function f() {{ {} }}", src))?;
+        let mut interp = Interp::new();
+        interp.run(&script)?;
+        let result = interp.call("f", vec![])?.to_string();
+        Ok(result)
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(eval("return 1 + 2 * 3;").unwrap(), "7");
+        assert_eq!(eval("return '1' + 2;").unwrap(), "12");
+        assert_eq!(eval("return 7 / 2;").unwrap(), "3.5");
+        assert_eq!(eval("return 0x10 >> 2 | 1;").unwrap(), "5");
+        assert_eq!(eval("return -1 >>> 28;").unwrap(), "15");
+        assert_eq!(eval("return 1 == '1' && 1 !== '1';").unwrap(), "true");
+        assert_eq!(eval("return null == undefined;").unwrap(), "true");
+        assert_eq!(eval("return 'b' > 'a' ? 'yes' : 'no';").unwrap(), "yes");
+        assert_eq!(eval("return '' || null || 'x';").unwrap(), "x");
+        assert_eq!(
+            eval("return typeof nope + typeof f;").unwrap(),
+            "undefinedfunction"
+        );
+        assert_eq!(
+            eval("return parseInt('0x1f') + parseInt('12px');").unwrap(),
+            "43"
+        );
+    }
+
+    #[test]
+    fn statements() {
+        let src = r#"
+            var n = 0, s = "";
+            for (var i = 0; i < 10; i++) {
+                if (i % 2) continue;
+                if (i > 6) break;
+                n += i;
+            }
+            var j = 3;
+            while (j--) s += j;
+            do { s += "!" } while (false)
+            switch (s) {
+                case "210!": s = "a";
+                case "x": s += "b"; break;
+                default: s = "c";
+            }
+            var keys = [];
+            for (var k in { x: 1, y: 2 }) keys.push(k);
+            return n + ":" + s + ":" + keys.join("-");
+        "#;
+        assert_eq!(eval(src).unwrap(), "12:ab:x-y");
+    }
+
+    #[test]
+    fn functions_and_closures() {
+        let src = r#"
+            function counter() {
+                var n = 0;
+                return function () { return ++n; };
+            }
+            var c = counter();
+            c(); c();
+            return c() + later(2) + arguments.length;
+            function later(x) { return x * 10 }
+        "#;
+        assert_eq!(eval(src).unwrap(), "23");
+    }
+
+    #[test]
+    fn strings_and_arrays() {
+        let src = r#"
+            var h = "WWW.Example.COM".toLowerCase();
+            var parts = h.split(".");
+            return [
+                h.substring(4, 11), h.substr(-3), h.slice(0, -4), h.charAt(1),
+                h.indexOf("ex"), h.lastIndexOf("m"), parts.length, parts[1].length,
+                h.replace("www.", ""), h.endsWith(".com"), h[0], parts.indexOf("com"),
+            ].join("|");
+        "#;
+        assert_eq!(
+            eval(src).unwrap(),
+            "example|com|www.example|w|4|14|3|7|example.com|true|w|2"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Script::parse("if (").unwrap_err().contains("line 1"));
+        assert!(Script::parse("var x = /re/;")
+            .unwrap_err()
+            .contains("regular"));
+        assert!(Script::parse("var s = 'open").is_err());
+        assert!(Script::parse(&"(".repeat(1000))
+            .unwrap_err()
+            .contains("nesting"));
+
+        assert_eq!(eval("return nope;").unwrap_err(), "nope is not defined");
+        assert_eq!(
+            eval("while (true) {}").unwrap_err(),
+            "script takes too long"
+        );
+        assert_eq!(eval("return f();").unwrap_err(), "too much recursion");
+        assert!(eval("var u; return u.x;").is_err());
+        assert!(eval("return 'x'.nope();").is_err());
+    }
+
+    #[test]
+    fn string_too_long() {
+        let too_long = |src: &str| eval(src).unwrap_err() == "string too long";
+
+        assert!(too_long("var s = 'x'; for (;;) s += s;"));
+        assert!(too_long("var s = 'x'; for (;;) s = s.concat(s, s);"));
+        assert!(too_long(
+            "var a = ['x', 'x']; for (;;) a = [a.join(a), 'x'];"
+        ));
+
+        // An array of itself is cut short, not written out 10 levels deep.
+        let src = "var a = []; for (var i = 0; i < 1000; i++) a.push(a); return a.join();";
+        assert!(too_long(src));
+    }
+}
@@ -5,6 +5,7 @@ use std::ops::{Deref, DerefMut};
 use http::{HeaderName, HeaderValue, Method, Request, Response, Uri, Version};
 
 use crate::body::Body;
+use crate::progress::{Progress, ProgressHook};
 use crate::send_body::AsSendBody;
 use crate::util::private::Private;
 use crate::{Agent, Error, SendBody, Timeouts};
@@ -133,6 +134,36 @@ impl<Any> RequestBuilder<Any> {
         // unwrap is ok because of above logic
         exts.get_mut().unwrap()
     }
+
+    /// Set a callback to follow the progress of the request and response bodies.
+    ///
+    /// The callback is invoked with [`Progress::Sent`] as the request body is sent,
+    /// and with [`Progress::Received`] as the response body is read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ureq::Progress;
+    ///
+    /// let mut res = ureq::put("http://httpbin.org/put")
+    ///     .progress(|p| match p {
+    ///         Progress::Sent { amount, total } => {
+    ///             println!("Sent {} of {:?}", amount, total);
+    ///         }
+    ///         Progress::Received { amount, total, .. } => {
+    ///             println!("Received {} of {:?}", amount, total);
+    ///         }
+    ///         _ => {}
+    ///     })
+    ///     .send(&[0_u8; 1000])?;
+    ///
+    /// res.body_mut().read_to_vec()?;
+    /// # Ok::<_, ureq::Error>(())
+    /// ```
+    pub fn progress(mut self, f: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.builder = self.builder.extension(ProgressHook::new(f));
+        self
+    }
 }
 
 impl RequestBuilder<WithoutBody> {
@@ -0,0 +1,632 @@
+//! Server-Sent Events (`text/event-stream`).
+//!
+//! There are two ways of reading events:
+//!
+//! * [`Body::into_sse()`](crate::Body::into_sse) parses the events of a single response.
+//! * [`Agent::event_source()`](crate::Agent::event_source) is a client that reconnects
+//!   when the stream ends, sending `Last-Event-ID` and honoring the server's `retry`.
+//!
+//! ```no_run
+//! let events = ureq::get("https://example.com/feed")
+//!     .call()?
+//!     .into_body()
+//!     .into_sse();
+//!
+//! for event in events {
+//!     let event = event?;
+//!     println!("{}: {}", event.event, event.data);
+//! }
+//! # Ok::<_, ureq::Error>(())
+//! ```
+
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+
+use crate::{Agent, BodyReader, Error};
+
+/// Reconnection time until the server sets one with `retry`.
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+
+/// Max size of a line or an event until changed with `max_event_size()`.
+const DEFAULT_MAX_EVENT_SIZE: usize = 10 * 1024 * 1024;
+
+/// An event in an event stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Event {
+    /// The event type.
+    ///
+    /// Defaults to `message` when the event has no `event` field.
+    pub event: String,
+
+    /// The data of the event.
+    ///
+    /// Multiple `data` fields are joined with `\n`.
+    pub data: String,
+
+    /// The last event id, which is the most recent `id` field of the stream.
+    ///
+    /// This carries over to events without an `id` field.
+    pub id: Option<String>,
+
+    /// The reconnection time, if the event has a `retry` field.
+    pub retry: Option<Duration>,
+}
+
+/// Iterator of the events in a response body.
+///
+/// Obtained via [`Body::into_sse()`](crate::Body::into_sse).
+///
+/// An event is only complete when followed by an empty line. If the stream
+/// ends in the middle of an event, that event is dropped.
+///
+/// A line or event larger than [`Events::max_event_size()`] results in
+/// [`Error::BodyExceedsLimit`], which ends the stream.
+pub struct Events {
+    reader: BufReader<BodyReader<'static>>,
+    max_event_size: usize,
+    // Set when a limit is exceeded, since the position in the stream is lost.
+    ended: bool,
+    line: Vec<u8>,
+    // The previous line ended with \r, which means a following \n is
+    // part of the same line ending.
+    skip_lf: bool,
+    first_line: bool,
+    event: String,
+    data: String,
+    event_retry: Option<Duration>,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Events {
+    pub(crate) fn new(reader: BodyReader<'static>, last_event_id: Option<String>) -> Self {
+        Events {
+            reader: BufReader::new(reader),
+            max_event_size: DEFAULT_MAX_EVENT_SIZE,
+            ended: false,
+            line: Vec::new(),
+            skip_lf: false,
+            first_line: true,
+            event: String::new(),
+            data: String::new(),
+            event_retry: None,
+            last_event_id,
+            retry: None,
+        }
+    }
+
+    /// Max size in bytes of a line, and of the data of an event.
+    ///
+    /// This protects against a server sending a line or event that never ends.
+    ///
+    /// Defaults to 10MB.
+    pub fn max_event_size(mut self, value: usize) -> Self {
+        self.max_event_size = value;
+        self
+    }
+
+    /// The most recent `id` field in the stream.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// The most recent `retry` field in the stream.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Read one line into self.line. Returns false at the end of the stream.
+    fn read_line(&mut self) -> Result<bool, Error> {
+        self.line.clear();
+
+        loop {
+            let buf = self.reader.fill_buf()?;
+
+            if buf.is_empty() {
+                // An unterminated line is discarded.
+                return Ok(false);
+            }
+
+            if self.skip_lf {
+                self.skip_lf = false;
+                if buf[0] == b'\n' {
+                    self.reader.consume(1);
+                    continue;
+                }
+            }
+
+            match buf.iter().position(|b| *b == b'\n' || *b == b'\r') {
+                Some(i) => {
+                    self.line.extend_from_slice(&buf[..i]);
+                    self.skip_lf = buf[i] == b'\r';
+                    self.reader.consume(i + 1);
+                    break;
+                }
+                None => {
+                    let len = buf.len();
+                    self.line.extend_from_slice(buf);
+                    self.reader.consume(len);
+                }
+            }
+
+            if self.line.len() > self.max_event_size {
+                return Err(self.exceeded());
+            }
+        }
+
+        if self.line.len() > self.max_event_size {
+            return Err(self.exceeded());
+        }
+
+        if self.first_line {
+            self.first_line = false;
+            if self.line.starts_with(b"\xEF\xBB\xBF") {
+                self.line.drain(..3);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn exceeded(&mut self) -> Error {
+        self.ended = true;
+        Error::BodyExceedsLimit(self.max_event_size as u64)
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            if self.ended || !self.read_line()? {
+                return Ok(None);
+            }
+
+            let line = String::from_utf8_lossy(&self.line).into_owned();
+
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    return Ok(Some(event));
+                }
+                continue;
+            }
+
+            if line.starts_with(':') {
+                // Comment
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (&*line, ""),
+            };
+
+            match field {
+                "event" => self.event = value.to_string(),
+                "data" => {
+                    if self.data.len() + value.len() > self.max_event_size {
+                        return Err(self.exceeded());
+                    }
+                    self.data.push_str(value);
+                    self.data.push('\n');
+                }
+                "id" => {
+                    // Ids with NULL are ignored, and an empty id resets the last id.
+                    if !value.contains('\0') {
+                        self.last_event_id = Some(value.to_string()).filter(|v| !v.is_empty());
+                    }
+                }
+                "retry" => {
+                    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+                        if let Ok(millis) = value.parse() {
+                            let retry = Duration::from_millis(millis);
+                            self.event_retry = Some(retry);
+                            self.retry = Some(retry);
+                        }
+                    }
+                }
+                _ => {
+                    trace!("Ignore unknown SSE field: {}", field);
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = mem::take(&mut self.event);
+        let mut data = mem::take(&mut self.data);
+        let retry = self.event_retry.take();
+
+        if data.is_empty() {
+            return None;
+        }
+
+        // Remove the \n after the last data line.
+        data.pop();
+
+        Some(Event {
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data,
+            id: self.last_event_id.clone(),
+            retry,
+        })
+    }
+}
+
+impl Iterator for Events {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+/// Client of an event stream that reconnects.
+///
+/// Obtained via [`Agent::event_source()`](crate::Agent::event_source).
+///
+/// When the stream ends or fails with [`Error::Io`] or [`Error::Timeout`], the
+/// request is made again after the reconnection time. The time is 3 seconds
+/// unless changed by the server using a `retry` field. The reconnect request
+/// has a `Last-Event-ID` header with the most recent event id.
+///
+/// Failures to reconnect are returned by the iterator, and the next call to
+/// `next()` tries again. The event source stops on an HTTP status other than
+/// `200 OK`, or a response that is not `text/event-stream`.
+///
+/// ```no_run
+/// let agent = ureq::agent();
+///
+/// let source = agent
+///     .event_source("https://example.com/feed")
+///     .header("authorization", "Bearer secret");
+///
+/// for event in source {
+///     match event {
+///         Ok(event) => println!("{}: {}", event.event, event.data),
+///         Err(e) => println!("Failed to connect: {}", e),
+///     }
+/// }
+/// ```
+pub struct EventSource {
+    agent: Agent,
+    uri: Result<Uri, http::Error>,
+    headers: HeaderMap,
+    events: Option<Events>,
+    max_event_size: usize,
+    last_event_id: Option<String>,
+    retry: Duration,
+    reconnect: bool,
+    done: bool,
+}
+
+impl EventSource {
+    pub(crate) fn new<T>(agent: Agent, uri: T) -> Self
+    where
+        Uri: TryFrom<T>,
+        <Uri as TryFrom<T>>::Error: Into<http::Error>,
+    {
+        EventSource {
+            agent,
+            uri: Uri::try_from(uri).map_err(Into::into),
+            headers: HeaderMap::new(),
+            events: None,
+            max_event_size: DEFAULT_MAX_EVENT_SIZE,
+            last_event_id: None,
+            retry: DEFAULT_RETRY,
+            reconnect: false,
+            done: false,
+        }
+    }
+
+    /// Appends a header to send with every request.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        HeaderValue: TryFrom<V>,
+    {
+        let key = HeaderName::try_from(key);
+        let value = HeaderValue::try_from(value);
+        if let (Ok(key), Ok(value)) = (key, value) {
+            self.headers.append(key, value);
+        } else {
+            warn!("Ignore invalid EventSource header");
+        }
+        self
+    }
+
+    /// Max size in bytes of a line, and of the data of an event.
+    ///
+    /// Exceeding it stops the event source with [`Error::BodyExceedsLimit`].
+    ///
+    /// Defaults to 10MB.
+    pub fn max_event_size(mut self, value: usize) -> Self {
+        self.max_event_size = value;
+        self
+    }
+
+    /// The most recent event id.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    fn connect(&mut self) -> Result<Events, Error> {
+        let uri = match &self.uri {
+            Ok(v) => v.clone(),
+            Err(_) => {
+                // The error is returned once, since it stops the event source.
+                let e = mem::replace(&mut self.uri, Ok(Uri::default())).unwrap_err();
+                return Err(e.into());
+            }
+        };
+
+        if self.reconnect {
+            debug!("Reconnect event source in {:?}", self.retry);
+            thread::sleep(self.retry);
+        }
+        self.reconnect = true;
+
+        let mut builder = self
+            .agent
+            .get(uri)
+            .header("accept", "text/event-stream")
+            .header("cache-control", "no-cache");
+
+        for (k, v) in &self.headers {
+            builder = builder.header(k, v);
+        }
+
+        if let Some(id) = &self.last_event_id {
+            builder = builder.header("last-event-id", id);
+        }
+
+        let res = builder.call()?;
+
+        if res.status() != StatusCode::OK {
+            return Err(Error::StatusCode(res.status().as_u16()));
+        }
+
+        let content_type = res.body().mime_type().unwrap_or_default();
+        if content_type != "text/event-stream" {
+            return Err(Error::NotEventStream(content_type.to_string()));
+        }
+
+        let body = res.into_body();
+        Ok(Events::new(body.into_reader(), self.last_event_id.clone())
+            .max_event_size(self.max_event_size))
+    }
+
+    fn disconnected(&mut self) {
+        if let Some(events) = self.events.take() {
+            self.last_event_id = events.last_event_id;
+            if let Some(retry) = events.retry {
+                self.retry = retry;
+            }
+        }
+    }
+}
+
+fn is_reconnectable(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Io(_) | Error::Timeout(_) | Error::ConnectionFailed | Error::HostNotFound
+    )
+}
+
+impl Iterator for EventSource {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+
+            let events = match &mut self.events {
+                Some(v) => v,
+                None => match self.connect() {
+                    Ok(v) => self.events.insert(v),
+                    Err(e) => {
+                        self.done = !is_reconnectable(&e);
+                        return Some(Err(e));
+                    }
+                },
+            };
+
+            match events.next_event() {
+                Ok(Some(event)) => {
+                    self.last_event_id = event.id.clone();
+                    return Some(Ok(event));
+                }
+                Ok(None) => {
+                    debug!("Event stream ended");
+                    self.disconnected();
+                }
+                Err(e) if is_reconnectable(&e) => {
+                    debug!("Event stream failed: {}", e);
+                    self.disconnected();
+                }
+                Err(e) => {
+                    self.disconnected();
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("last_event_id", &self.last_event_id)
+            .finish()
+    }
+}
+
+impl fmt::Debug for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSource")
+            .field("uri", &self.uri)
+            .field("last_event_id", &self.last_event_id)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "_test"))]
+mod test {
+    use super::*;
+    use crate::test::init_test_log;
+    use crate::transport::{set_handler, set_handler_fn};
+
+    fn event(event: &str, data: &str, id: Option<&str>) -> Event {
+        Event {
+            event: event.to_string(),
+            data: data.to_string(),
+            id: id.map(|v| v.to_string()),
+            retry: None,
+        }
+    }
+
+    #[test]
+    fn sse_parse_events() {
+        init_test_log();
+        set_handler(
+            "/sse-parse",
+            200,
+            &[("content-type", "text/event-stream")],
+            b"\xEF\xBB\xBF: comment\n\
+            data: one\n\
+            data:two\n\
+            \n\
+            event: update\r\n\
+            id: 7\r\n\
+            data: three\r\n\
+            \r\n\
+            retry: 1500\rdata\r\r\
+            id\n\
+            data: four\n\
+            \n\
+            data: incomplete\n",
+        );
+
+        let events: Vec<_> = crate::get("https://my.test/sse-parse")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_sse()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let mut with_retry = event("message", "", Some("7"));
+        with_retry.retry = Some(Duration::from_millis(1500));
+
+        assert_eq!(
+            events,
+            vec![
+                event("message", "one\ntwo", None),
+                event("update", "three", Some("7")),
+                with_retry,
+                event("message", "four", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn sse_max_event_size() {
+        init_test_log();
+        set_handler(
+            "/sse-large",
+            200,
+            &[("content-type", "text/event-stream")],
+            b"data: 12345\n\n\
+            data: 1234\n\
+            data: 5678\n\n\
+            data: 123456789012345678901234567890\n\n\
+            data: after\n\n",
+        );
+
+        let mut events = crate::get("https://my.test/sse-large")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_sse()
+            .max_event_size(20);
+
+        assert_eq!(events.next().unwrap().unwrap().data, "12345");
+        assert_eq!(events.next().unwrap().unwrap().data, "1234\n5678");
+        let err = events.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::BodyExceedsLimit(20)));
+        assert!(events.next().is_none());
+
+        set_handler(
+            "/sse-many-lines",
+            200,
+            &[("content-type", "text/event-stream")],
+            b"data: 1234567890\n\
+            data: 1234567890\n\
+            data: 1234567890\n\n",
+        );
+
+        let mut events = crate::get("https://my.test/sse-many-lines")
+            .call()
+            .unwrap()
+            .into_body()
+            .into_sse()
+            .max_event_size(20);
+
+        let err = events.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::BodyExceedsLimit(20)));
+    }
+
+    #[test]
+    fn sse_event_source_reconnects() {
+        init_test_log();
+        set_handler_fn("/sse-source", |_uri, req, w| {
+            let last_id = req
+                .headers()
+                .get("last-event-id")
+                .and_then(|v| v.to_str().ok());
+            match last_id {
+                None => write!(
+                    w,
+                    "HTTP/1.1 200 OK\r\n\
+                    Content-Type: text/event-stream\r\n\
+                    \r\n\
+                    retry: 0\n\
+                    id: 1\n\
+                    data: a\n\n"
+                ),
+                Some("1") => write!(
+                    w,
+                    "HTTP/1.1 200 OK\r\n\
+                    Content-Type: text/event-stream\r\n\
+                    \r\n\
+                    id: 2\n\
+                    data: b\n\n"
+                ),
+                _ => write!(w, "HTTP/1.1 204 No Content\r\n\r\n"),
+            }
+        });
+
+        let mut source = crate::agent().event_source("https://my.test/sse-source");
+
+        let first = source.next().unwrap().unwrap();
+        assert_eq!(first.data, "a");
+        assert_eq!(first.retry, Some(Duration::ZERO));
+
+        let second = source.next().unwrap().unwrap();
+        assert_eq!(second, event("message", "b", Some("2")));
+
+        let err = source.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::StatusCode(204)));
+        assert!(source.next().is_none());
+    }
+}
@@ -1,13 +1,32 @@
 //! TLS for handling `https`.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 mod cert;
-pub use cert::{parse_pem, Certificate, PemItem, PrivateKey};
+#[cfg(feature = "pkcs12")]
+pub use cert::parse_pkcs12;
+pub use cert::{load_root_certs, parse_pem, root_certs_from_env};
+pub use cert::{Certificate, Crl, PeerCertificates, PemItem, PrivateKey};
 
-#[cfg(feature = "rustls")]
+#[cfg(feature = "_rustls")]
+mod ocsp;
+#[cfg(feature = "pkcs12")]
+mod pbe;
+mod pin;
+#[cfg(feature = "pkcs12")]
+mod pkcs12;
+pub use pin::SpkiPin;
+
+mod verifier;
+pub use verifier::{CertVerifier, ServerCertificate};
+
+mod provider;
+pub use provider::{CertFiles, CertProvider};
+
+#[cfg(feature = "_rustls")]
 mod rustls;
-#[cfg(feature = "rustls")]
+#[cfg(feature = "_rustls")]
 pub use self::rustls::RustlsConnector;
 
 #[cfg(feature = "native-tls")]
@@ -26,8 +45,9 @@ pub enum TlsProvider {
     /// [Rustls](https://crates.io/crates/rustls) with the
     /// [process-wide default cryptographic backend](https://docs.rs/rustls/latest/rustls/crypto/struct.CryptoProvider.html#method.install_default),
     /// or [Ring](https://crates.io/crates/ring) if no process-wide default is set.
+    /// See [`TlsConfig::rustls_crypto_provider`] for other choices.
     ///
-    /// Requires the feature flag **rustls**.
+    /// Requires the feature flag **rustls**, **rustls-aws-lc-rs** or **rustls-no-provider**.
     ///
     /// This is the default.
     Rustls,
@@ -46,7 +66,7 @@ impl TlsProvider {
     pub(crate) fn is_feature_enabled(&self) -> bool {
         match self {
             TlsProvider::Rustls => {
-                cfg!(feature = "rustls")
+                cfg!(feature = "_rustls")
             }
             TlsProvider::NativeTls => {
                 cfg!(feature = "native-tls")
@@ -83,6 +103,34 @@ pub struct TlsConfig {
     /// Defaults to `PlatformVerifier` to use the platform default root certs.
     pub root_certs: RootCerts,
 
+    /// Custom verification of the server certificate.
+    ///
+    /// The callback gets the certificate chain and the outcome of the default
+    /// verification against [`root_certs`][Self::root_certs], and decides whether to
+    /// accept the certificate. This makes it possible to accept, for instance, a
+    /// specific self-signed certificate without disabling verification entirely.
+    /// Connecting fails with [`Error::CertificateRejected`](crate::Error::CertificateRejected)
+    /// if the callback returns `false`.
+    ///
+    /// [`spki_pins`][Self::spki_pins] are checked after the callback.
+    ///
+    /// Only supported by **rustls**.
+    ///
+    /// Defaults to `None`.
+    pub cert_verifier: Option<CertVerifier>,
+
+    /// Source of client certificates and root certificates that can change while the
+    /// [`Agent`](crate::Agent) is running.
+    ///
+    /// When set, the TLS config is rebuilt for new connections whenever the provider
+    /// reports a new [`generation()`](CertProvider::generation), using the provided
+    /// certificates in place of [`client_cert`][Self::client_cert] and
+    /// [`root_certs`][Self::root_certs]. See [`CertFiles`] for reloading PEM files from
+    /// disk.
+    ///
+    /// Defaults to `None`.
+    pub cert_provider: Option<Arc<dyn CertProvider>>,
+
     /// Whether to send SNI (Server Name Indication) to the remote server.
     ///
     /// This is used by the server to determine which domain/certificate we are connecting
@@ -91,6 +139,134 @@ pub struct TlsConfig {
     /// Defaults to `true`.
     pub use_sni: bool,
 
+    /// Protocols to offer via ALPN (Application-Layer Protocol Negotiation), in order
+    /// of preference.
+    ///
+    /// The protocol selected by the server is available from
+    /// [`Transport::alpn_protocol()`](crate::transport::Transport::alpn_protocol).
+    /// When set, the TLS handshake is done as part of connecting, since the protocol must
+    /// be known before sending the request.
+    ///
+    /// Defaults to `["h2", "http/1.1"]` with the feature flag **http2**, and no ALPN
+    /// otherwise. **native-tls** never offers `h2`, since HTTP/2 is only available with
+    /// **rustls**.
+    pub alpn_protocols: Vec<String>,
+
+    /// The lowest TLS version to use.
+    ///
+    /// Defaults to `None`, meaning the lowest version the provider supports. For **rustls**
+    /// that is TLS 1.2.
+    pub min_version: Option<TlsVersion>,
+
+    /// The highest TLS version to use.
+    ///
+    /// Defaults to `None`, meaning the highest version the provider supports.
+    pub max_version: Option<TlsVersion>,
+
+    /// Cipher suites to allow, in order of preference.
+    ///
+    /// The names are as registered with IANA, such as `TLS_AES_128_GCM_SHA256` or
+    /// `TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384`. Connecting fails with
+    /// [`Error::UnsupportedTlsConfig`](crate::Error::UnsupportedTlsConfig) if a suite
+    /// isn't available.
+    ///
+    /// Only supported by **rustls**.
+    ///
+    /// Defaults to empty, which means the defaults of the provider.
+    pub cipher_suites: Vec<String>,
+
+    /// The rustls crypto provider to use for this agent.
+    ///
+    /// This makes it possible to use, for instance, a FIPS validated provider for some agents,
+    /// without changing the process-wide default. When not set, the
+    /// [process-wide default](https://docs.rs/rustls/latest/rustls/crypto/struct.CryptoProvider.html#method.install_default)
+    /// is used, and when there is no process-wide default, the provider selected by the feature
+    /// flags: **rustls-aws-lc-rs** for aws-lc-rs, or **rustls** for ring.
+    ///
+    /// [`cipher_suites`][Self::cipher_suites] and [`kx_groups`][Self::kx_groups] select from
+    /// the suites and groups of this provider.
+    ///
+    /// Defaults to `None`.
+    #[cfg(feature = "_rustls")]
+    pub rustls_crypto_provider: Option<Arc<::rustls::crypto::CryptoProvider>>,
+
+    /// Key exchange groups to allow, in order of preference.
+    ///
+    /// The names are as registered with IANA, such as `x25519` or `secp256r1`.
+    ///
+    /// Only supported by **rustls**.
+    ///
+    /// Defaults to empty, which means the defaults of the provider.
+    pub kx_groups: Vec<String>,
+
+    /// Public key pins per host.
+    ///
+    /// For a host in this map, the connection is only accepted if the verified certificate
+    /// path, from the server certificate up to and including the trust anchor, contains a
+    /// certificate with one of the pinned public keys. Certificates the server sends that
+    /// aren't part of the path don't count. This is in addition to, not instead of, the
+    /// normal certificate validation.
+    /// Connecting fails with [`Error::PinMismatch`](crate::Error::PinMismatch)
+    /// otherwise. Hosts not in the map are not pinned.
+    ///
+    /// The host is matched case insensitively against the host of the request URI.
+    /// IPv6 addresses are given without brackets.
+    ///
+    /// With **native-tls** only the server (leaf) certificate can be checked, since that's
+    /// the only certificate native-tls exposes.
+    ///
+    /// Defaults to empty.
+    pub spki_pins: HashMap<String, Vec<SpkiPin>>,
+
+    /// Certificate revocation lists (CRLs) to check the server certificate chain against.
+    ///
+    /// A certificate revoked by one of the lists is rejected with
+    /// [`Error::Revocation`](crate::Error::Revocation). Certificates not covered by any of the
+    /// lists are accepted, which means the CRLs of the CAs to enforce revocation for must
+    /// be included. Expired lists are still used.
+    ///
+    /// Only supported by **rustls**, with [`root_certs`][Self::root_certs] other than
+    /// [`RootCerts::PlatformVerifier`], since the platform verifier does its own revocation
+    /// checking.
+    ///
+    /// Defaults to empty.
+    pub crls: Vec<Crl<'static>>,
+
+    /// Whether to check the OCSP response stapled by the server.
+    ///
+    /// See [`OcspStapling`].
+    ///
+    /// Only supported by **rustls**.
+    ///
+    /// Defaults to [`OcspStapling::Ignore`].
+    pub ocsp_stapling: OcspStapling,
+
+    /// Number of TLS sessions to keep for resumption.
+    ///
+    /// Sessions (and TLS 1.3 session tickets) are shared by all connections of the
+    /// [`Agent`](crate::Agent), which means later connections to the same server can skip the
+    /// full handshake. Whether a connection was resumed is available from the
+    /// [`TlsHandshake`] response extension. Setting this to 0 disables resumption.
+    ///
+    /// TLS 1.3 early data (0-RTT) is never used, since it can be replayed.
+    ///
+    /// Only used by **rustls**. native-tls does its own session handling.
+    ///
+    /// Defaults to 256.
+    pub session_cache_size: usize,
+
+    /// **WARNING** Log TLS secrets to the file named by the `SSLKEYLOGFILE` environment
+    /// variable.
+    ///
+    /// This is for debugging TLS traffic with tools like Wireshark, and must never be enabled
+    /// in production, since anyone reading the file can decrypt the traffic. Nothing is
+    /// logged unless `SSLKEYLOGFILE` is also set.
+    ///
+    /// Only supported by **rustls**.
+    ///
+    /// Defaults to `false`.
+    pub key_log: bool,
+
     /// **WARNING** Disable all server certificate verification.
     ///
     /// This breaks encryption and leaks secrets. Must never be enabled for code where
@@ -98,6 +274,67 @@ pub struct TlsConfig {
     pub disable_verification: bool,
 }
 
+/// TLS protocol versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum TlsVersion {
+    /// TLS 1.2
+    Tls12,
+    /// TLS 1.3
+    Tls13,
+}
+
+/// Checking of OCSP responses stapled by the server.
+///
+/// With OCSP stapling the server sends a recent, signed statement from its CA that its
+/// certificate isn't revoked. The response must be signed by the issuer of the server
+/// certificate, or by an OCSP responder the issuer delegated to. The issuer must be sent
+/// by the server, or be one of the [`RootCerts`] (this means that with
+/// [`RootCerts::PlatformVerifier`], a server certificate issued directly by a root can't
+/// be checked).
+///
+/// Connecting fails with [`Error::Revocation`](crate::Error::Revocation) if the certificate
+/// is revoked, or the response is invalid or expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OcspStapling {
+    /// Don't check stapled responses.
+    ///
+    /// This is the default.
+    Ignore,
+
+    /// Check the response if the server staples one.
+    Verify,
+
+    /// Require the server to staple a response, and check it.
+    Require,
+}
+
+/// How the TLS connection of a response was established.
+///
+/// Available in the extensions of the [`Response`](http::Response) for `https` requests
+/// using **rustls**. For a pooled connection, this is the handshake of when the
+/// connection was first opened.
+///
+/// ```no_run
+/// use ureq::tls::TlsHandshake;
+///
+/// let res = ureq::get("https://example.com").call()?;
+///
+/// let resumed = res.extensions().get::<TlsHandshake>() == Some(&TlsHandshake::Resumed);
+/// # Ok::<_, ureq::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TlsHandshake {
+    /// A full handshake.
+    Full,
+    /// An abbreviated handshake resuming an earlier session.
+    ///
+    /// See [`TlsConfig::session_cache_size`].
+    Resumed,
+}
+
 /// Configuration setting for root certs.
 #[derive(Debug, Clone)]
 #[non_exhaustive]
@@ -116,6 +353,22 @@ pub enum RootCerts {
     /// This is useful when you can't trust the system roots, such as in
     /// environments where TLS is intercepted and decrypted by a proxy (MITM attack).
     WebPki,
+
+    /// Use the platform's verifier, and also trust these certificates.
+    ///
+    /// This is for adding an internal CA without giving up the platform roots. The
+    /// certificates can be loaded with [`load_root_certs()`] or
+    /// [`root_certs_from_env()`].
+    ///
+    /// * For **rustls**, a certificate rejected by the platform verifier is verified
+    ///   again against these certificates.
+    /// * For **native-tls**, these are added to the roots that native-tls loads by default.
+    PlatformVerifierWith(Vec<Certificate<'static>>),
+
+    /// Use Mozilla's root certificates, and also trust these certificates.
+    ///
+    /// See [`PlatformVerifierWith`][Self::PlatformVerifierWith].
+    WebPkiWith(Vec<Certificate<'static>>),
 }
 
 impl Default for TlsConfig {
@@ -125,12 +378,34 @@ impl Default for TlsConfig {
             provider,
             client_cert: None,
             root_certs: RootCerts::PlatformVerifier,
+            cert_verifier: None,
+            cert_provider: None,
             use_sni: true,
+            alpn_protocols: default_alpn_protocols(),
+            min_version: None,
+            max_version: None,
+            cipher_suites: vec![],
+            #[cfg(feature = "_rustls")]
+            rustls_crypto_provider: None,
+            kx_groups: vec![],
+            spki_pins: HashMap::new(),
+            crls: vec![],
+            ocsp_stapling: OcspStapling::Ignore,
+            session_cache_size: 256,
+            key_log: false,
             disable_verification: false,
         }
     }
 }
 
+fn default_alpn_protocols() -> Vec<String> {
+    if cfg!(feature = "http2") {
+        vec!["h2".to_string(), "http/1.1".to_string()]
+    } else {
+        vec![]
+    }
+}
+
 impl Default for TlsProvider {
     fn default() -> Self {
         Self::Rustls
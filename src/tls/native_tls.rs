@@ -1,13 +1,15 @@
 use std::fmt;
 use std::io::{Read, Write};
-use std::sync::{Arc, OnceLock};
+use std::sync::Arc;
 
-use crate::tls::{RootCerts, TlsProvider};
+use crate::tls::pin::{pins_for, verify_pins};
+use crate::tls::provider::ConfigCache;
+use crate::tls::{OcspStapling, RootCerts, SpkiPin, TlsProvider, TlsVersion};
 use crate::transport::time::NextTimeout;
 use crate::{transport::*, Error};
 use der::pem::LineEnding;
 use der::Document;
-use native_tls::{Certificate, HandshakeError, Identity, TlsConnector};
+use native_tls::{Certificate, HandshakeError, Identity, Protocol, TlsConnector};
 use native_tls::{TlsConnectorBuilder, TlsStream};
 
 use super::TlsConfig;
@@ -17,7 +19,7 @@ use super::TlsConfig;
 /// Requires feature flag **native-tls**.
 #[derive(Default)]
 pub struct NativeTlsConnector {
-    connector: OnceLock<Arc<TlsConnector>>,
+    connector: ConfigCache<TlsConnector>,
 }
 
 impl Connector for NativeTlsConnector {
@@ -46,18 +48,8 @@ impl Connector for NativeTlsConnector {
 
         let tls_config = &details.config.tls_config;
 
-        // Initialize the connector on first run.
-        let connector_ref = match self.connector.get() {
-            Some(v) => v,
-            None => {
-                // This is unlikely to be racy, but if it is, doesn't matter much.
-                let c = build_connector(tls_config)?;
-                // Maybe someone else set it first. Weird, but ok.
-                let _ = self.connector.set(c);
-                self.connector.get().unwrap()
-            }
-        };
-        let connector = connector_ref.clone(); // cheap clone due to Arc
+        // Built on first run, and rebuilt if the certificates change.
+        let connector = self.connector.get_or_build(tls_config, build_connector)?;
 
         let domain = details
             .uri
@@ -66,15 +58,36 @@ impl Connector for NativeTlsConnector {
             .host()
             .to_string();
 
+        let pins = pins_for(&tls_config.spki_pins, &domain).map(|p| p.to_vec());
+
         let adapter = TransportAdapter::new(transport);
-        let stream = LazyStream::Unstarted(Some((connector, domain, adapter)));
+        let stream = LazyStream::Unstarted(Some(Unstarted {
+            connector,
+            domain,
+            adapter,
+            pins,
+        }));
 
         let buffers = LazyBuffers::new(
             details.config.input_buffer_size,
             details.config.output_buffer_size,
         );
 
-        let transport = Box::new(NativeTlsTransport { buffers, stream });
+        let mut transport = Box::new(NativeTlsTransport {
+            buffers,
+            stream,
+            alpn: None,
+        });
+
+        // The negotiated protocol must be known before the first request.
+        if !tls_config.alpn_protocols.is_empty() {
+            let stream = transport.stream.handshaken()?;
+            transport.alpn = stream.negotiated_alpn().ok().flatten();
+            debug!(
+                "Negotiated ALPN: {:?}",
+                transport.alpn.as_deref().map(String::from_utf8_lossy)
+            );
+        }
 
         debug!("Wrapped TLS");
 
@@ -85,6 +98,51 @@ impl Connector for NativeTlsConnector {
 fn build_connector(tls_config: &TlsConfig) -> Result<Arc<TlsConnector>, Error> {
     let mut builder = TlsConnector::builder();
 
+    if !tls_config.cipher_suites.is_empty() || !tls_config.kx_groups.is_empty() {
+        return Err(Error::UnsupportedTlsConfig(
+            "native-tls can't select cipher suites or key exchange groups".into(),
+        ));
+    }
+
+    if tls_config.key_log {
+        return Err(Error::UnsupportedTlsConfig(
+            "native-tls can't log TLS secrets".into(),
+        ));
+    }
+
+    if tls_config.cert_verifier.is_some() {
+        return Err(Error::UnsupportedTlsConfig(
+            "native-tls can't use a custom certificate verifier".into(),
+        ));
+    }
+
+    if !tls_config.crls.is_empty() || tls_config.ocsp_stapling != OcspStapling::Ignore {
+        return Err(Error::UnsupportedTlsConfig(
+            "native-tls can't check CRLs or OCSP responses".into(),
+        ));
+    }
+
+    match tls_config.min_version {
+        None => {}
+        Some(TlsVersion::Tls12) => {
+            builder.min_protocol_version(Some(Protocol::Tlsv12));
+        }
+        Some(v) => {
+            return Err(Error::UnsupportedTlsConfig(format!(
+                "native-tls can't set {:?} as min version",
+                v
+            )));
+        }
+    }
+
+    match tls_config.max_version {
+        // No max means the highest version, which might be TLS 1.3.
+        None | Some(TlsVersion::Tls13) => {}
+        Some(TlsVersion::Tls12) => {
+            builder.max_protocol_version(Some(Protocol::Tlsv12));
+        }
+    }
+
     if tls_config.disable_verification {
         debug!("Certificate verification disabled");
         builder.danger_accept_invalid_certs(true);
@@ -108,6 +166,18 @@ fn build_connector(tls_config: &TlsConfig) -> Result<Arc<TlsConnector>, Error> {
                     .map(|c| c.as_ref());
                 add_valid_der(certs, &mut builder);
             }
+            RootCerts::PlatformVerifierWith(certs) => {
+                // The built-in roots and the extra ones.
+                builder.disable_built_in_roots(false);
+                add_valid_der(certs.iter().map(|c| c.der()), &mut builder);
+            }
+            RootCerts::WebPkiWith(certs) => {
+                builder.disable_built_in_roots(true);
+                let webpki = webpki_root_certs::TLS_SERVER_ROOT_CERTS
+                    .iter()
+                    .map(|c| c.as_ref());
+                add_valid_der(webpki.chain(certs.iter().map(|c| c.der())), &mut builder);
+            }
         }
     }
 
@@ -127,6 +197,18 @@ fn build_connector(tls_config: &TlsConfig) -> Result<Arc<TlsConnector>, Error> {
 
     builder.use_sni(tls_config.use_sni);
 
+    // HTTP/2 is not offered, since the native-tls transport can't be split for reading
+    // and writing on different threads.
+    let protocols: Vec<&str> = tls_config
+        .alpn_protocols
+        .iter()
+        .map(|p| p.as_str())
+        .filter(|p| *p != "h2")
+        .collect();
+    if !protocols.is_empty() {
+        builder.request_alpns(&protocols);
+    }
+
     if !tls_config.use_sni {
         debug!("Disable SNI");
     }
@@ -168,6 +250,7 @@ fn pemify(der: &[u8], label: &'static str) -> Result<String, Error> {
 struct NativeTlsTransport {
     buffers: LazyBuffers,
     stream: LazyStream,
+    alpn: Option<Vec<u8>>,
 }
 
 impl Transport for NativeTlsTransport {
@@ -210,24 +293,55 @@ impl Transport for NativeTlsTransport {
     fn is_tls(&self) -> bool {
         true
     }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn.as_deref()
+    }
+
+    fn peer_certificates(&self) -> Option<Vec<crate::tls::Certificate<'static>>> {
+        let LazyStream::Started(stream) = &self.stream else {
+            return None;
+        };
+        // native-tls only gives us the leaf certificate.
+        let der = stream.peer_certificate().ok()??.to_der().ok()?;
+        Some(vec![crate::tls::Certificate::from_der(&der).to_owned()])
+    }
 }
 
 /// Helper to delay the handshake until we are starting IO.
 /// This normalizes native-tls to behave like rustls.
 enum LazyStream {
-    Unstarted(Option<(Arc<TlsConnector>, String, TransportAdapter)>),
+    Unstarted(Option<Unstarted>),
     Started(TlsStream<TransportAdapter>),
 }
 
+struct Unstarted {
+    connector: Arc<TlsConnector>,
+    domain: String,
+    adapter: TransportAdapter,
+    /// Public key pins to check after the handshake.
+    pins: Option<Vec<SpkiPin>>,
+}
+
 impl LazyStream {
     fn handshaken(&mut self) -> Result<&mut TlsStream<TransportAdapter>, Error> {
         match self {
             LazyStream::Unstarted(v) => {
-                let (conn, domain, adapter) = v.take().unwrap();
-                let stream = conn.connect(&domain, adapter).map_err(|e| match e {
+                let Unstarted {
+                    connector,
+                    domain,
+                    adapter,
+                    pins,
+                } = v.take().unwrap();
+                let stream = connector.connect(&domain, adapter).map_err(|e| match e {
                     HandshakeError::Failure(e) => e,
                     HandshakeError::WouldBlock(_) => unreachable!(),
                 })?;
+                if let Some(pins) = pins {
+                    // native-tls only gives us the leaf certificate.
+                    let leaf = stream.peer_certificate()?.map(|c| c.to_der()).transpose()?;
+                    verify_pins(&domain, &pins, leaf.as_deref())?;
+                }
                 *self = LazyStream::Started(stream);
                 // Next time we hit the other match arm
                 return self.handshaken();
@@ -0,0 +1,542 @@
+//! Checking of OCSP responses (RFC 6960) stapled by the server.
+//!
+//! rustls asks the server to staple an OCSP response and hands it to the certificate
+//! verifier, but leaves checking it to the verifier. The response must be signed by the
+//! issuer of the server certificate, or by a responder the issuer delegated to.
+
+use der::asn1::{AnyRef, BitString, GeneralizedTime, Null, ObjectIdentifier, OctetString};
+use der::{Choice, Decode, Encode, Enumerated, Sequence, Tag};
+use rustls_pki_types::{SignatureVerificationAlgorithm, TrustAnchor, UnixTime};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use x509_cert::ext::pkix::{CrlReason, ExtendedKeyUsage};
+use x509_cert::ext::Extensions;
+use x509_cert::name::Name;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+use x509_cert::Certificate;
+
+use crate::Error;
+
+const ID_PKIX_OCSP_BASIC: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.48.1.1");
+const ID_KP_OCSP_SIGNING: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.9");
+const ID_CE_EXT_KEY_USAGE: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.29.37");
+const ID_SHA1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+
+/// Allowed difference between our clock and the clock of the responder.
+const CLOCK_SKEW: u64 = 5 * 60;
+
+/// How long a response without `nextUpdate` is considered fresh.
+const MAX_AGE_WITHOUT_NEXT_UPDATE: u64 = 24 * 60 * 60;
+
+const MALFORMED: &str = "malformed response";
+
+/// Check the stapled OCSP `response` for the `end_entity` certificate.
+///
+/// The issuer of the certificate is looked up among the `intermediates` sent by the server,
+/// and the `anchors`.
+pub(crate) fn check(
+    response: &[u8],
+    end_entity: &[u8],
+    intermediates: &[&[u8]],
+    anchors: &[TrustAnchor<'_>],
+    algs: &[&dyn SignatureVerificationAlgorithm],
+    now: UnixTime,
+) -> Result<(), Error> {
+    let outcome = status(
+        response,
+        end_entity,
+        intermediates,
+        anchors,
+        algs,
+        now.as_secs(),
+    );
+
+    let reason = match outcome {
+        Ok(CertStatus::Good) => return Ok(()),
+        Ok(CertStatus::Revoked) => "certificate is revoked".to_string(),
+        Ok(CertStatus::Unknown) => "certificate is unknown to the OCSP responder".to_string(),
+        Err(reason) => format!("invalid OCSP response: {}", reason),
+    };
+
+    Err(Error::Revocation(reason))
+}
+
+/// The DER of a `SEQUENCE` with the `content`.
+///
+/// Trust anchors and signature algorithms of rustls leave out the outer `SEQUENCE`
+/// of names, public keys and algorithm identifiers.
+pub(super) fn sequence_of(content: &[u8]) -> Vec<u8> {
+    AnyRef::new(Tag::Sequence, content)
+        .and_then(|any| any.to_der())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+fn status(
+    response: &[u8],
+    end_entity: &[u8],
+    intermediates: &[&[u8]],
+    anchors: &[TrustAnchor<'_>],
+    algs: &[&dyn SignatureVerificationAlgorithm],
+    now: u64,
+) -> Result<CertStatus, &'static str> {
+    let leaf = Certificate::from_der(end_entity).map_err(|_| "malformed server certificate")?;
+    let issuer = find_issuer(&leaf, intermediates, anchors, algs).ok_or("issuer not found")?;
+
+    let basic = basic_response(response)?;
+    let basic = BasicOcspResponse::from_der(basic.as_bytes()).map_err(|_| MALFORMED)?;
+    let tbs = basic.tbs_response_data.to_der().map_err(|_| MALFORMED)?;
+    let data = ResponseData::from_der(&tbs).map_err(|_| MALFORMED)?;
+
+    let sig_alg = basic.signature_algorithm.to_der().map_err(|_| MALFORMED)?;
+    let signature = basic.signature.as_bytes().ok_or(MALFORMED)?;
+    let signed_by = |spki| verify_signature(algs, spki, &sig_alg, &tbs, signature);
+
+    let signed_by_issuer = signed_by(&issuer.spki);
+
+    let signed_by_responder = || {
+        basic
+            .certs
+            .iter()
+            .flatten()
+            .filter(|c| is_responder_for(c, &issuer, algs, now))
+            .any(|c| signed_by(&c.tbs_certificate.subject_public_key_info))
+    };
+
+    if !signed_by_issuer && !signed_by_responder() {
+        return Err("bad signature");
+    }
+
+    for single in &data.responses {
+        if !single.is_for(&leaf, &issuer) {
+            continue;
+        }
+
+        let this_update = single.this_update.to_unix_duration().as_secs();
+        if this_update > now + CLOCK_SKEW {
+            return Err("response is not yet valid");
+        }
+        let expires = single
+            .next_update
+            .map(|t| t.to_unix_duration().as_secs())
+            .unwrap_or(this_update + MAX_AGE_WITHOUT_NEXT_UPDATE);
+        if now > expires + CLOCK_SKEW {
+            return Err("response has expired");
+        }
+
+        return Ok(match single.cert_status {
+            SingleStatus::Good(_) => CertStatus::Good,
+            SingleStatus::Revoked(_) => CertStatus::Revoked,
+            SingleStatus::Unknown(_) => CertStatus::Unknown,
+        });
+    }
+
+    Err("no response for the server certificate")
+}
+
+/// The `BasicOCSPResponse` of an `OCSPResponse`.
+fn basic_response(response: &[u8]) -> Result<OctetString, &'static str> {
+    let response = OcspResponse::from_der(response).map_err(|_| MALFORMED)?;
+    if response.response_status != ResponseStatus::Successful {
+        return Err("responder returned an error");
+    }
+
+    let bytes = response.response_bytes.ok_or(MALFORMED)?;
+    if bytes.response_type != ID_PKIX_OCSP_BASIC {
+        return Err("unsupported response type");
+    }
+
+    Ok(bytes.response)
+}
+
+// OCSPResponse ::= SEQUENCE { responseStatus OCSPResponseStatus,
+//   responseBytes [0] EXPLICIT ResponseBytes OPTIONAL }
+#[derive(Sequence)]
+struct OcspResponse {
+    response_status: ResponseStatus,
+    #[asn1(context_specific = "0", optional = "true")]
+    response_bytes: Option<ResponseBytes>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumerated)]
+#[repr(u32)]
+enum ResponseStatus {
+    Successful = 0,
+    MalformedRequest = 1,
+    InternalError = 2,
+    TryLater = 3,
+    SigRequired = 5,
+    Unauthorized = 6,
+}
+
+// ResponseBytes ::= SEQUENCE { responseType OBJECT IDENTIFIER, response OCTET STRING }
+#[derive(Sequence)]
+struct ResponseBytes {
+    response_type: ObjectIdentifier,
+    response: OctetString,
+}
+
+// BasicOCSPResponse ::= SEQUENCE { tbsResponseData ResponseData,
+//   signatureAlgorithm AlgorithmIdentifier, signature BIT STRING,
+//   certs [0] EXPLICIT SEQUENCE OF Certificate OPTIONAL }
+//
+// The signed `ResponseData` is kept as is, to verify the signature over it.
+#[derive(Sequence)]
+struct BasicOcspResponse<'a> {
+    tbs_response_data: AnyRef<'a>,
+    signature_algorithm: AlgorithmIdentifierOwned,
+    signature: BitString,
+    #[asn1(context_specific = "0", optional = "true")]
+    certs: Option<Vec<Certificate>>,
+}
+
+// ResponseData ::= SEQUENCE { version [0] EXPLICIT Version DEFAULT v1,
+//   responderID ResponderID, producedAt GeneralizedTime,
+//   responses SEQUENCE OF SingleResponse, responseExtensions [1] EXPLICIT Extensions OPTIONAL }
+#[derive(Sequence)]
+struct ResponseData<'a> {
+    #[asn1(context_specific = "0", default = "Default::default")]
+    version: u8,
+    responder_id: AnyRef<'a>,
+    produced_at: GeneralizedTime,
+    responses: Vec<SingleResponse>,
+    #[asn1(context_specific = "1", optional = "true")]
+    response_extensions: Option<Extensions>,
+}
+
+// SingleResponse ::= SEQUENCE { certID CertID, certStatus CertStatus,
+//   thisUpdate GeneralizedTime, nextUpdate [0] EXPLICIT GeneralizedTime OPTIONAL,
+//   singleExtensions [1] EXPLICIT Extensions OPTIONAL }
+#[derive(Sequence)]
+struct SingleResponse {
+    cert_id: CertId,
+    cert_status: SingleStatus,
+    this_update: GeneralizedTime,
+    #[asn1(context_specific = "0", optional = "true")]
+    next_update: Option<GeneralizedTime>,
+    #[asn1(context_specific = "1", optional = "true")]
+    single_extensions: Option<Extensions>,
+}
+
+impl SingleResponse {
+    fn is_for(&self, cert: &Certificate, issuer: &Issuer) -> bool {
+        let id = &self.cert_id;
+        if id.serial_number != cert.tbs_certificate.serial_number {
+            return false;
+        }
+        let Ok(name) = issuer.name.to_der() else {
+            return false;
+        };
+        let key = issuer.spki.subject_public_key.raw_bytes();
+        let name_hash = id.issuer_name_hash.as_bytes();
+        let key_hash = id.issuer_key_hash.as_bytes();
+
+        match id.hash_algorithm.oid {
+            ID_SHA1 => {
+                Sha1::digest(&name).as_slice() == name_hash
+                    && Sha1::digest(key).as_slice() == key_hash
+            }
+            ID_SHA256 => {
+                Sha256::digest(&name).as_slice() == name_hash
+                    && Sha256::digest(key).as_slice() == key_hash
+            }
+            _ => false,
+        }
+    }
+}
+
+// CertID ::= SEQUENCE { hashAlgorithm AlgorithmIdentifier,
+//   issuerNameHash OCTET STRING, issuerKeyHash OCTET STRING,
+//   serialNumber CertificateSerialNumber }
+#[derive(Sequence)]
+struct CertId {
+    hash_algorithm: AlgorithmIdentifierOwned,
+    issuer_name_hash: OctetString,
+    issuer_key_hash: OctetString,
+    serial_number: SerialNumber,
+}
+
+// CertStatus ::= CHOICE { good [0] IMPLICIT NULL, revoked [1] IMPLICIT RevokedInfo,
+//   unknown [2] IMPLICIT UnknownInfo }
+#[derive(Choice)]
+enum SingleStatus {
+    #[asn1(context_specific = "0", tag_mode = "IMPLICIT")]
+    Good(Null),
+    #[asn1(context_specific = "1", tag_mode = "IMPLICIT", constructed = "true")]
+    Revoked(RevokedInfo),
+    #[asn1(context_specific = "2", tag_mode = "IMPLICIT")]
+    Unknown(Null),
+}
+
+// RevokedInfo ::= SEQUENCE { revocationTime GeneralizedTime,
+//   revocationReason [0] EXPLICIT CRLReason OPTIONAL }
+#[derive(Sequence)]
+struct RevokedInfo {
+    revocation_time: GeneralizedTime,
+    #[asn1(context_specific = "0", optional = "true")]
+    revocation_reason: Option<CrlReason>,
+}
+
+/// Whether `cert` is valid for a responder delegated by the issuer.
+fn is_responder_for(
+    cert: &Certificate,
+    issuer: &Issuer,
+    algs: &[&dyn SignatureVerificationAlgorithm],
+    now: u64,
+) -> bool {
+    let tbs = &cert.tbs_certificate;
+    let not_before = tbs.validity.not_before.to_unix_duration().as_secs();
+    let not_after = tbs.validity.not_after.to_unix_duration().as_secs();
+
+    let (Ok(message), Ok(sig_alg), Some(signature)) = (
+        tbs.to_der(),
+        cert.signature_algorithm.to_der(),
+        cert.signature.as_bytes(),
+    ) else {
+        return false;
+    };
+
+    tbs.issuer == issuer.name
+        && not_before <= now + CLOCK_SKEW
+        && now <= not_after + CLOCK_SKEW
+        && has_ocsp_signing(cert)
+        && verify_signature(algs, &issuer.spki, &sig_alg, &message, signature)
+}
+
+fn has_ocsp_signing(cert: &Certificate) -> bool {
+    cert.tbs_certificate
+        .extensions
+        .iter()
+        .flatten()
+        .filter(|ext| ext.extn_id == ID_CE_EXT_KEY_USAGE)
+        .filter_map(|ext| ExtendedKeyUsage::from_der(ext.extn_value.as_bytes()).ok())
+        .any(|eku| eku.0.contains(&ID_KP_OCSP_SIGNING))
+}
+
+/// The issuer of the server certificate.
+struct Issuer {
+    name: Name,
+    spki: SubjectPublicKeyInfoOwned,
+}
+
+/// The issuer of `leaf`, the certificate whose key signed it.
+///
+/// A matching name is not enough, since the server can send any certificate with the
+/// name of the issuer.
+fn find_issuer(
+    leaf: &Certificate,
+    intermediates: &[&[u8]],
+    anchors: &[TrustAnchor<'_>],
+    algs: &[&dyn SignatureVerificationAlgorithm],
+) -> Option<Issuer> {
+    let wanted = &leaf.tbs_certificate.issuer;
+
+    let (Ok(message), Ok(sig_alg), Some(signature)) = (
+        leaf.tbs_certificate.to_der(),
+        leaf.signature_algorithm.to_der(),
+        leaf.signature.as_bytes(),
+    ) else {
+        return None;
+    };
+    let signed_leaf = |issuer: &Issuer| {
+        &issuer.name == wanted
+            && verify_signature(algs, &issuer.spki, &sig_alg, &message, signature)
+    };
+
+    let from_chain = intermediates
+        .iter()
+        .filter_map(|c| Certificate::from_der(c).ok())
+        .map(|c| Issuer {
+            name: c.tbs_certificate.subject,
+            spki: c.tbs_certificate.subject_public_key_info,
+        })
+        .find(signed_leaf);
+
+    from_chain.or_else(|| {
+        anchors
+            .iter()
+            .filter_map(|a| {
+                let name = Name::from_der(&sequence_of(a.subject.as_ref())).ok()?;
+                let spki = sequence_of(a.subject_public_key_info.as_ref());
+                let spki = SubjectPublicKeyInfoOwned::from_der(&spki).ok()?;
+                Some(Issuer { name, spki })
+            })
+            .find(signed_leaf)
+    })
+}
+
+/// Verify the `signature` over `message`. `sig_alg` is the DER of the `AlgorithmIdentifier`.
+fn verify_signature(
+    algs: &[&dyn SignatureVerificationAlgorithm],
+    spki: &SubjectPublicKeyInfoOwned,
+    sig_alg: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    let (Ok(key_alg), Some(key)) = (spki.algorithm.to_der(), spki.subject_public_key.as_bytes())
+    else {
+        return false;
+    };
+
+    algs.iter()
+        .filter(|a| sequence_of(a.public_key_alg_id().as_ref()) == key_alg)
+        .filter(|a| sequence_of(a.signature_alg_id().as_ref()) == sig_alg)
+        .any(|a| a.verify_signature(key, message, signature).is_ok())
+}
+
+#[cfg(all(test, feature = "_ring"))]
+pub(super) mod test {
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use rustls::RootCertStore;
+    use rustls_pki_types::CertificateDer;
+
+    use super::*;
+    use crate::tls::pin::test::{cert_der, leaf_cert_der};
+
+    // Response for the leaf certificate with status good, valid 2026-10-18T17:59:22Z
+    // to 2036-10-15T17:59:22Z. Signed by a delegated responder.
+    const GOOD: &str = "\
+MIICqgoBAKCCAqMwggKfBgkrBgEFBQcwAQEEggKQMIICjDCBnaERMA8xDTALBgNV
+BAMMBG9jc3AYDzIwMjYxMDE4MTc1OTIyWjB3MHUwTTAJBgUrDgMCGgUABBQriKPk
+QUFNeKtaJDOGW/6zqJCtoQQURWa4WXJMzRUwBwULz08gCtXP8uYCFBypuu2sQ4rX
+FOcr0HP1UCl61FNqgAAYDzIwMjYxMDE4MTc1OTIyWqARGA8yMDM2MTAxNTE3NTky
+MlowCgYIKoZIzj0EAwIDSAAwRQIgC0u4O49vl/eg45byWTaR4kPPScKK7gAIgRC3
+LO1RriwCIQC7s0w3CGD5ft58KKme+eqsc2ndm6Wt54p26aguQCmsBKCCAZIwggGO
+MIIBijCCATCgAwIBAgIUHKm67axDitcU5yvQc/VQKXrUU2swCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MB4XDTI2MTAxODE3NTkyMloXDTM2MTAxNTE3
+NTkyMlowDzENMAsGA1UEAwwEb2NzcDBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IA
+BBlwkrQVxF+yN8YM5U3g2wtAStgDeK91MTuduli8DhfsRXc2Dx8kmsZUSaMXP/gl
+uFF/S72wSu0pIjCs4tDXmcajZTBjMAwGA1UdEwEB/wQCMAAwEwYDVR0lBAwwCgYI
+KwYBBQUHAwkwHQYDVR0OBBYEFKbqqeaf3jFGmefHOR1QOJ95VZyxMB8GA1UdIwQY
+MBaAFEVmuFlyTM0VMAcFC89PIArVz/LmMAoGCCqGSM49BAMCA0gAMEUCIQD6bmkN
+FnHd+vNPGjuhmKWyj+G+0VjfXIfbMSby8mWZuwIga/5Y3Qbe4/hAuC3onRlA6x58
+sI0Q/hPN88rW0ox/8Gg=";
+
+    // Response for the leaf certificate with status revoked, with the same validity.
+    // Signed by the issuer.
+    const REVOKED: &str = "\
+MIIBLAoBAKCCASUwggEhBgkrBgEFBQcwAQEEggESMIIBDjCBtaEWMBQxEjAQBgNV
+BAMMCWxvY2FsaG9zdBgPMjAyNjEwMTgxNzU5MjJaMIGJMIGGME0wCQYFKw4DAhoF
+AAQUK4ij5EFBTXirWiQzhlv+s6iQraEEFEVmuFlyTM0VMAcFC89PIArVz/LmAhQc
+qbrtrEOK1xTnK9Bz9VApetRTaqERGA8yMDI2MTAxODAwMDAwMFoYDzIwMjYxMDE4
+MTc1OTIyWqARGA8yMDM2MTAxNTE3NTkyMlowCgYIKoZIzj0EAwIDSAAwRQIgAtjm
+MFWlNg6TCraGdSMProGeJDCt7YlkJCOmQYbb/mACIQDByGZ3xz24rVdKJxLHzICH
+7G7tQ9cU7vjgluT0w6Z+ow==";
+
+    // Self-signed certificate with the name of the issuer, localhost, and another key.
+    const DECOY: &str = "\
+MIIBfTCCASOgAwIBAgIUE17NS9xvInm9jnGvIpZWAUwUY3YwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MB4XDTI2MTAxODIxMjQwMFoXDTM2MTAxNTIx
+MjQwMFowFDESMBAGA1UEAwwJbG9jYWxob3N0MFkwEwYHKoZIzj0CAQYIKoZIzj0D
+AQcDQgAE1Rg0tV2EW6Di87ugT7AsRHHD+xBStxZU/gz5TttWdfkVReBhGN1iV8x7
+PLm+EgumFg/MXNVIxO8BzjluRd2fv6NTMFEwHQYDVR0OBBYEFFMvMg7IaNwJ2Neu
+KuUhlax+VucyMB8GA1UdIwQYMBaAFFMvMg7IaNwJ2NeuKuUhlax+VucyMA8GA1Ud
+EwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIhAMhv6er4jXTP3RNM5ZNDXYTN
+nf7bKqu82PVyjdlfRz4FAiBVRSEaL+Ya3Me1r67sFVxiHaG05VBiOgNlz7Y/85jD
+oQ==";
+
+    /// An hour after the responses were produced.
+    const NOW: u64 = 1792349962;
+
+    pub(crate) fn good_response() -> Vec<u8> {
+        BASE64_STANDARD.decode(GOOD.replace('\n', "")).unwrap()
+    }
+
+    pub(crate) fn revoked_response() -> Vec<u8> {
+        BASE64_STANDARD.decode(REVOKED.replace('\n', "")).unwrap()
+    }
+
+    fn status_at(response: &[u8], now: u64) -> Result<CertStatus, &'static str> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(cert_der())).unwrap();
+        let algs = rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .all;
+
+        status(response, &leaf_cert_der(), &[], &roots.roots, algs, now)
+    }
+
+    #[test]
+    fn good_from_delegated_responder() {
+        assert_eq!(status_at(&good_response(), NOW), Ok(CertStatus::Good));
+    }
+
+    #[test]
+    fn revoked_from_issuer() {
+        assert_eq!(status_at(&revoked_response(), NOW), Ok(CertStatus::Revoked));
+    }
+
+    #[test]
+    fn outside_validity() {
+        // Signed by the issuer, since the delegated responder certificate has the same
+        // validity as the response.
+        let produced = NOW - 3600;
+        let next_update = 2107706362;
+        assert_eq!(
+            status_at(&revoked_response(), produced - CLOCK_SKEW - 1),
+            Err("response is not yet valid")
+        );
+        assert_eq!(
+            status_at(&revoked_response(), next_update + CLOCK_SKEW + 1),
+            Err("response has expired")
+        );
+        assert_eq!(
+            status_at(&good_response(), next_update + CLOCK_SKEW + 1),
+            Err("bad signature")
+        );
+    }
+
+    #[test]
+    fn tampered_response() {
+        let mut response = good_response();
+        // Change the producedAt time in the signed data.
+        let pos = response
+            .windows(15)
+            .position(|w| w == b"20261018175922Z")
+            .unwrap();
+        response[pos + 3] = b'7';
+        assert_eq!(status_at(&response, NOW), Err("bad signature"));
+
+        assert_eq!(status_at(&response[..100], NOW), Err(MALFORMED));
+    }
+
+    #[test]
+    fn issuer_not_found() {
+        let algs = rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .all;
+        let leaf = leaf_cert_der();
+        let outcome = status(&good_response(), &leaf, &[], &[], algs, NOW);
+        assert_eq!(outcome, Err("issuer not found"));
+
+        // The issuer can also be sent by the server.
+        let issuer = cert_der();
+        let outcome = status(&good_response(), &leaf, &[&issuer], &[], algs, NOW);
+        assert_eq!(outcome, Ok(CertStatus::Good));
+    }
+
+    #[test]
+    fn decoy_issuer() {
+        let algs = rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .all;
+        let leaf = leaf_cert_der();
+        let issuer = cert_der();
+        let decoy = BASE64_STANDARD.decode(DECOY.replace('\n', "")).unwrap();
+
+        let outcome = status(&good_response(), &leaf, &[&decoy], &[], algs, NOW);
+        assert_eq!(outcome, Err("issuer not found"));
+
+        let outcome = status(&good_response(), &leaf, &[&decoy, &issuer], &[], algs, NOW);
+        assert_eq!(outcome, Ok(CertStatus::Good));
+    }
+}
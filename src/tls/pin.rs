@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+
+use der::{Decode, Encode};
+use sha2::{Digest, Sha256};
+use x509_cert::Certificate as X509Certificate;
+
+use super::Certificate;
+use crate::Error;
+
+/// SHA-256 hash of a certificate's public key (SubjectPublicKeyInfo), used for pinning.
+///
+/// See [`TlsConfig::spki_pins`](crate::tls::TlsConfig::spki_pins).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    /// Pin from the raw SHA-256 hash of the DER encoded SubjectPublicKeyInfo.
+    pub fn from_sha256(hash: [u8; 32]) -> Self {
+        SpkiPin(hash)
+    }
+
+    /// Pin from the base64 encoded hash, with or without a `sha256/` prefix.
+    ///
+    /// This is the format used by HPKP and most tooling, which can be produced with:
+    ///
+    /// ```text
+    /// openssl x509 -in cert.pem -pubkey -noout \
+    ///   | openssl pkey -pubin -outform der \
+    ///   | openssl dgst -sha256 -binary | base64
+    /// ```
+    pub fn from_base64(pin: &str) -> Result<Self, Error> {
+        let pin = pin.strip_prefix("sha256/").unwrap_or(pin);
+        let hash = BASE64_STANDARD
+            .decode(pin)
+            .ok()
+            .and_then(|v| <[u8; 32]>::try_from(v).ok())
+            .ok_or(Error::Tls("Invalid SPKI pin"))?;
+        Ok(SpkiPin(hash))
+    }
+
+    /// Pin the public key of the given certificate.
+    pub fn from_certificate(cert: &Certificate<'_>) -> Result<Self, Error> {
+        let spki = spki_of(cert.der()).ok_or(Error::Tls("No public key in certificate"))?;
+        Ok(SpkiPin::of(&spki))
+    }
+
+    /// The SHA-256 hash.
+    pub fn sha256(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    fn of(spki: &[u8]) -> Self {
+        SpkiPin(Sha256::digest(spki).into())
+    }
+}
+
+/// The pins for a host, if it is pinned.
+pub(crate) fn pins_for<'a>(
+    spki_pins: &'a HashMap<String, Vec<SpkiPin>>,
+    host: &str,
+) -> Option<&'a [SpkiPin]> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    spki_pins
+        .iter()
+        .find(|(h, _)| h.eq_ignore_ascii_case(host))
+        .map(|(_, pins)| pins.as_slice())
+}
+
+/// Check that at least one of the certificates (DER) has a pinned public key.
+#[cfg(any(feature = "native-tls", test))]
+pub(crate) fn verify_pins<'a>(
+    host: &str,
+    pins: &[SpkiPin],
+    certs: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<(), Error> {
+    let spkis: Vec<_> = certs.into_iter().filter_map(spki_of).collect();
+    verify_spki_pins(host, pins, spkis.iter().map(|s| s.as_slice()))
+}
+
+/// Check that at least one of the public keys (DER SubjectPublicKeyInfo) is pinned.
+pub(crate) fn verify_spki_pins<'a>(
+    host: &str,
+    pins: &[SpkiPin],
+    spkis: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<(), Error> {
+    let matched = spkis
+        .into_iter()
+        .any(|spki| pins.contains(&SpkiPin::of(spki)));
+
+    if matched {
+        trace!("SPKI pin matched for: {}", host);
+        Ok(())
+    } else {
+        debug!("No SPKI pin matched for: {}", host);
+        Err(Error::PinMismatch(host.to_string()))
+    }
+}
+
+/// The DER encoded SubjectPublicKeyInfo of a DER encoded X509 certificate.
+fn spki_of(cert: &[u8]) -> Option<Vec<u8>> {
+    let cert = X509Certificate::from_der(cert).ok()?;
+    cert.tbs_certificate.subject_public_key_info.to_der().ok()
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SpkiPin(sha256/{})", BASE64_STANDARD.encode(self.0))
+    }
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use super::*;
+
+    // Self-signed EC P-256 certificate for "localhost".
+    const CERT: &str = "\
+MIIBkzCCATmgAwIBAgIUb2ciQT77edVQaxO2q97+V0C1w0UwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MB4XDTI2MTAxODE3MjkzOVoXDTM2MTAxNTE3
+MjkzOVowFDESMBAGA1UEAwwJbG9jYWxob3N0MFkwEwYHKoZIzj0CAQYIKoZIzj0D
+AQcDQgAECniTu0t9SKU7jc5lS8NFUjxOWIKSbHg2M9z1EjDNJ2QBsost94tqtAKe
+/hYhX8gRCtCGquLS9xF8C9EZUVeqG6NpMGcwHQYDVR0OBBYEFEVmuFlyTM0VMAcF
+C89PIArVz/LmMB8GA1UdIwQYMBaAFEVmuFlyTM0VMAcFC89PIArVz/LmMA8GA1Ud
+EwEB/wQFMAMBAf8wFAYDVR0RBA0wC4IJbG9jYWxob3N0MAoGCCqGSM49BAMCA0gA
+MEUCICUhsTp6THFmv16p7VjoLl1EVTMjzluXZFmeBsua/tMeAiEAv1oIZsUOCU1s
+Svn5grlE8/LYFZ/gY5tnC9g+nucTXxs=";
+
+    // openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256
+    const PIN: &str = "sha256/BwHnveJiZA10wctnnveDUJ1748qpSvVTvD26XF/Lhoo=";
+
+    // PKCS#8 key of CERT.
+    const KEY: &str = "\
+MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQgQoJmuC+/5KQ8ouxU
+CUKxk9sappYhVeI/cpgx8dCwkBGhRANCAAQKeJO7S31IpTuNzmVLw0VSPE5YgpJs
+eDYz3PUSMM0nZAGyiy33i2q0Ap7+FiFfyBEK0Iaq4tL3EXwL0RlRV6ob";
+
+    // Certificate for "localhost" issued by CERT, which is a CA.
+    const LEAF_CERT: &str = "\
+MIIBpDCCAUugAwIBAgIUHKm67axDitcU5yvQc/VQKXrUU2owCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJbG9jYWxob3N0MB4XDTI2MTAxODE3NTQ0NFoXDTM2MTAxNTE3
+NTQ0NFowFDESMBAGA1UEAwwJbG9jYWxob3N0MFkwEwYHKoZIzj0CAQYIKoZIzj0D
+AQcDQgAEeh9c6JsvmY7Wtu9qvd27+WgVhzkGPa3pie6f2tkwCf+JmEFIkKcOVHDC
+qnPPB45NgToNToiI/bkGcHKscKmmSqN7MHkwFAYDVR0RBA0wC4IJbG9jYWxob3N0
+MAwGA1UdEwEB/wQCMAAwEwYDVR0lBAwwCgYIKwYBBQUHAwEwHQYDVR0OBBYEFMPq
+UO75PRaUmyURPemu8daglQYwMB8GA1UdIwQYMBaAFEVmuFlyTM0VMAcFC89PIArV
+z/LmMAoGCCqGSM49BAMCA0cAMEQCIFyF69V+QkAAHBdB8YZKenmidyAM0HwS0Pi0
+lf3WsTt6AiAriy3bp+IcwLHms2/I2Ertks5Kzt9DjZWnIUFwxiRcQw==";
+
+    // PKCS#8 key of LEAF_CERT.
+    #[cfg(feature = "_rustls")]
+    const LEAF_KEY: &str = "\
+MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQglMFxEq2qT/dy5vbY
+K9xc/L3drrBdI6Rz3J5YZAtnA9ShRANCAAR6H1zomy+Zjta272q93bv5aBWHOQY9
+remJ7p/a2TAJ/4mYQUiQpw5UcMKqc88Hjk2BOg1OiIj9uQZwcqxwqaZK";
+
+    // Self-signed EC P-256 certificate for "other", unrelated to the others.
+    #[cfg(feature = "_rustls")]
+    const OTHER_CERT: &str = "\
+MIIBdjCCARugAwIBAgIULLNryHbjx+ycY8tf8nw3hxrtrAEwCgYIKoZIzj0EAwIw
+EDEOMAwGA1UEAwwFb3RoZXIwHhcNMjYxMDE4MTkxMzE3WhcNMzYxMDE1MTkxMzE3
+WjAQMQ4wDAYDVQQDDAVvdGhlcjBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABKEw
+5TQs3kpld75X5jQ9eXzllMaZW2m3YGWulUowqEbgJNDf7qdXWYNRRZjk8Y+br+TJ
+RZlbL8gaWXeoLHTd0BGjUzBRMB0GA1UdDgQWBBR/RBAyVJPYZ5W80mwcOScVhrFp
+cjAfBgNVHSMEGDAWgBR/RBAyVJPYZ5W80mwcOScVhrFpcjAPBgNVHRMBAf8EBTAD
+AQH/MAoGCCqGSM49BAMCA0kAMEYCIQDJ74ve7pfEzoA/GVAagvENdPVt05VkKfOE
+pinvgFlB6QIhAIB2guu4LgN5wzoYPESrq58nqCJuFlEiVbTpfd8F4JIA";
+
+    pub(crate) fn cert_der() -> Vec<u8> {
+        BASE64_STANDARD.decode(CERT.replace('\n', "")).unwrap()
+    }
+
+    pub(crate) fn key_der() -> Vec<u8> {
+        BASE64_STANDARD.decode(KEY.replace('\n', "")).unwrap()
+    }
+
+    pub(crate) fn leaf_cert_der() -> Vec<u8> {
+        BASE64_STANDARD.decode(LEAF_CERT.replace('\n', "")).unwrap()
+    }
+
+    #[cfg(feature = "_rustls")]
+    pub(crate) fn leaf_key_der() -> Vec<u8> {
+        BASE64_STANDARD.decode(LEAF_KEY.replace('\n', "")).unwrap()
+    }
+
+    #[cfg(feature = "_rustls")]
+    pub(crate) fn other_cert_der() -> Vec<u8> {
+        BASE64_STANDARD
+            .decode(OTHER_CERT.replace('\n', ""))
+            .unwrap()
+    }
+
+    #[test]
+    fn pin_of_certificate() {
+        let der = cert_der();
+        let pin = SpkiPin::from_certificate(&Certificate::from_der(&der)).unwrap();
+        assert_eq!(pin, SpkiPin::from_base64(PIN).unwrap());
+        assert_eq!(pin, SpkiPin::from_base64(&PIN[7..]).unwrap());
+    }
+
+    #[test]
+    fn invalid_pins() {
+        assert!(SpkiPin::from_base64("sha256/AAAA").is_err());
+        assert!(SpkiPin::from_base64("not base64").is_err());
+
+        let der = cert_der();
+        let truncated = Certificate::from_der(&der[..100]);
+        assert!(SpkiPin::from_certificate(&truncated).is_err());
+    }
+
+    #[test]
+    fn pins_for_host() {
+        let pin = SpkiPin::from_sha256([1; 32]);
+        let mut spki_pins = HashMap::new();
+        spki_pins.insert("Example.COM".to_string(), vec![pin]);
+        spki_pins.insert("::1".to_string(), vec![pin]);
+
+        assert_eq!(pins_for(&spki_pins, "example.com"), Some(&[pin][..]));
+        assert_eq!(pins_for(&spki_pins, "[::1]"), Some(&[pin][..]));
+        assert_eq!(pins_for(&spki_pins, "www.example.com"), None);
+    }
+
+    #[test]
+    fn verify_any_cert_in_chain() {
+        let der = cert_der();
+        let pin = SpkiPin::from_base64(PIN).unwrap();
+        let other = SpkiPin::from_sha256([0; 32]);
+
+        let chain = [&b"garbage"[..], &der];
+        assert!(verify_pins("localhost", &[other, pin], chain).is_ok());
+
+        let err = verify_pins("localhost", &[other], chain).unwrap_err();
+        assert!(matches!(err, Error::PinMismatch(h) if h == "localhost"));
+    }
+}
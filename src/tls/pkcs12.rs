@@ -0,0 +1,286 @@
+//! Reading of PKCS#12 (`.p12` / `.pfx`) bundles, RFC 7292.
+//!
+//! Supports the password integrity mode with an HMAC-SHA256 MAC, and bags encrypted
+//! with PBES2 (see [`pbe`](super::pbe)). The public-key integrity and privacy modes
+//! are not supported.
+
+use cms::content_info::ContentInfo;
+use cms::encrypted_data::EncryptedData;
+use der::asn1::{ContextSpecific, ObjectIdentifier, OctetString};
+use der::{Any, Decode, Encode};
+use hmac::{Hmac, Mac};
+use pkcs12::cert_type::CertBag;
+use pkcs12::kdf::{derive_key_utf8, Pkcs12KeyType};
+use pkcs12::mac_data::MacData;
+use pkcs12::pbe_params::EncryptedPrivateKeyInfo;
+use pkcs12::pfx::Pfx;
+use pkcs12::safe_bag::{SafeBag, SafeContents};
+use pkcs12::{PKCS_12_CERT_BAG_OID, PKCS_12_KEY_BAG_OID, PKCS_12_PKCS8_KEY_BAG_OID};
+use sha2::Sha256;
+use x509_cert::attr::Attributes;
+
+use super::pbe::{decrypt, decrypt_key};
+use crate::Error;
+
+/// 1.2.840.113549.1.7.1
+const ID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+/// 1.2.840.113549.1.7.6
+const ID_ENCRYPTED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.6");
+/// 1.2.840.113549.1.9.22.1
+const ID_X509_CERT: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.22.1");
+/// 1.2.840.113549.1.9.21
+const ID_LOCAL_KEY_ID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.21");
+/// 2.16.840.1.101.3.4.2.1
+const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+
+const MALFORMED: Error = Error::Tls("Malformed PKCS#12");
+
+/// Certificates and private key found in a PKCS#12 bundle.
+pub(crate) struct Contents {
+    /// DER certificates, starting with the one for the private key.
+    pub certs: Vec<Vec<u8>>,
+    /// DER PKCS#8 PrivateKeyInfo.
+    pub key: Vec<u8>,
+}
+
+pub(crate) fn parse(der: &[u8], password: &str) -> Result<Contents, Error> {
+    let pfx = Pfx::from_der(der).map_err(|_| MALFORMED)?;
+
+    let auth_safe = data_content(&pfx.auth_safe)?
+        .ok_or(Error::Tls("Unsupported PKCS#12, only password integrity"))?;
+
+    // Without a MAC, neither the password nor the integrity of the bundle is checked.
+    let mac_data = pfx.mac_data.ok_or(Error::Tls("PKCS#12 without MAC"))?;
+    verify_mac(&mac_data, &auth_safe, password)?;
+
+    let mut bags = Bags::default();
+
+    // AuthenticatedSafe ::= SEQUENCE OF ContentInfo
+    let infos = Vec::<ContentInfo>::from_der(&auth_safe).map_err(|_| MALFORMED)?;
+    for info in &infos {
+        if let Some(safe_contents) = data_content(info)? {
+            bags.read(&safe_contents, password)?;
+        } else if let Some(safe_contents) = encrypted_data_content(info, password)? {
+            bags.read(&safe_contents, password)?;
+        } else {
+            debug!("Skip unsupported PKCS#12 content");
+        }
+    }
+
+    let (key, key_id) = bags.key.ok_or(Error::Tls("No private key in PKCS#12"))?;
+
+    // The certificate of the key goes first.
+    let mut certs = bags.certs;
+    if let Some(pos) = certs
+        .iter()
+        .position(|(_, id)| id.is_some() && *id == key_id)
+    {
+        let cert = certs.remove(pos);
+        certs.insert(0, cert);
+    }
+
+    Ok(Contents {
+        certs: certs.into_iter().map(|(c, _)| c).collect(),
+        key,
+    })
+}
+
+/// The content of a ContentInfo of type data, or `None` if it's another type.
+fn data_content(info: &ContentInfo) -> Result<Option<Vec<u8>>, Error> {
+    if info.content_type != ID_DATA {
+        return Ok(None);
+    }
+    let data: OctetString = info.content.decode_as().map_err(|_| MALFORMED)?;
+    Ok(Some(data.into_bytes()))
+}
+
+/// The decrypted content of a ContentInfo of type encrypted data, or `None` if it's
+/// another type.
+fn encrypted_data_content(info: &ContentInfo, password: &str) -> Result<Option<Vec<u8>>, Error> {
+    if info.content_type != ID_ENCRYPTED_DATA {
+        return Ok(None);
+    }
+    let encrypted: EncryptedData = info.content.decode_as().map_err(|_| MALFORMED)?;
+    let content_info = encrypted.enc_content_info;
+    let data = content_info.encrypted_content.ok_or(MALFORMED)?;
+
+    let decrypted = decrypt(&content_info.content_enc_alg, data.as_bytes(), password)?;
+    Ok(Some(decrypted))
+}
+
+fn verify_mac(mac_data: &MacData, data: &[u8], password: &str) -> Result<(), Error> {
+    if mac_data.mac.algorithm.oid != ID_SHA256 {
+        return Err(Error::Tls(
+            "Unsupported PKCS#12 MAC, only HMAC-SHA256 is supported (not legacy SHA-1)",
+        ));
+    }
+
+    let salt = mac_data.mac_salt.as_bytes();
+    let key =
+        derive_key_utf8::<Sha256>(password, salt, Pkcs12KeyType::Mac, mac_data.iterations, 32)
+            .map_err(|_| Error::Tls("Invalid PKCS#12 password"))?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes any key length");
+    mac.update(data);
+
+    // Compares in constant time.
+    mac.verify_slice(mac_data.mac.digest.as_bytes())
+        .map_err(|_| Error::Tls("PKCS#12 MAC mismatch, wrong password?"))
+}
+
+type LocalKeyId = Option<Vec<u8>>;
+
+#[derive(Default)]
+struct Bags {
+    certs: Vec<(Vec<u8>, LocalKeyId)>,
+    key: Option<(Vec<u8>, LocalKeyId)>,
+}
+
+impl Bags {
+    fn read(&mut self, safe_contents: &[u8], password: &str) -> Result<(), Error> {
+        let bags = SafeContents::from_der(safe_contents).map_err(|_| MALFORMED)?;
+
+        for bag in &bags {
+            let key_id = bag.bag_attributes.as_ref().and_then(local_key_id);
+
+            if bag.bag_id == PKCS_12_KEY_BAG_OID {
+                let key = bag_value::<Any>(bag)?.to_der().map_err(|_| MALFORMED)?;
+                self.set_key(key, key_id);
+            } else if bag.bag_id == PKCS_12_PKCS8_KEY_BAG_OID {
+                let info = bag_value::<EncryptedPrivateKeyInfo>(bag)?;
+                self.set_key(decrypt_key(&info, password)?, key_id);
+            } else if bag.bag_id == PKCS_12_CERT_BAG_OID {
+                let cert_bag = bag_value::<CertBag>(bag)?;
+                if cert_bag.cert_id != ID_X509_CERT {
+                    debug!("Skip non-X509 PKCS#12 certificate");
+                    continue;
+                }
+                self.certs.push((cert_bag.cert_value.into_bytes(), key_id));
+            } else {
+                debug!("Skip unsupported PKCS#12 bag");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: Vec<u8>, key_id: LocalKeyId) {
+        if self.key.is_some() {
+            debug!("Multiple private keys in PKCS#12, using the first");
+            return;
+        }
+        self.key = Some((key, key_id));
+    }
+}
+
+/// The `[0] EXPLICIT` value of a SafeBag.
+fn bag_value<'a, T: Decode<'a>>(bag: &'a SafeBag) -> Result<T, Error> {
+    let value = ContextSpecific::<T>::from_der(&bag.bag_value).map_err(|_| MALFORMED)?;
+    Ok(value.value)
+}
+
+fn local_key_id(attributes: &Attributes) -> LocalKeyId {
+    let attr = attributes.iter().find(|a| a.oid == ID_LOCAL_KEY_ID)?;
+    let id: OctetString = attr.values.iter().next()?.decode_as().ok()?;
+    Some(id.into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use base64::prelude::{Engine, BASE64_STANDARD};
+
+    use super::*;
+    use crate::tls::parse_pkcs12;
+
+    /// Certificate and key of the pin tests, plus a CA certificate, with
+    /// `openssl pkcs12 -export -passout pass:secret`.
+    const P12: &str = "\
+MIIFzAIBAzCCBYIGCSqGSIb3DQEHAaCCBXMEggVvMIIFazCCBBoGCSqGSIb3DQEH
+BqCCBAswggQHAgEAMIIEAAYJKoZIhvcNAQcBMF8GCSqGSIb3DQEFDTBSMDEGCSqG
+SIb3DQEFDDAkBBD2rJAk74kYw61uQDJ0E7S+AgIIADAMBggqhkiG9w0CCQUAMB0G
+CWCGSAFlAwQBKgQQin219BZF95RZkfHBj0X5zICCA5DHrKWmenFJ1KuTwn/TKjFX
+nAh3rbwKPDeNvfttalARuSvkdqPXW7FT2okt+EdNw5goRgaxwCA0q8XVlG1Ei4KO
+1s+VFegDQcVIKC66b65jDDAb9/0zbCTKX83ht5lZ8vvKrcL8mkECIqU/DFkYdCc9
+2ShNSq4AxgxZKSDxdb2PVeiddVqEyzEUlWwrvPf+7y+bOu5LMC1QykB+nkFvvc5p
+YtrZH5rUzjqcrwPYJ1pjOj0j6L6+v2UcR/G0P48lG56tT+vwUl4z8vfj+PqpO6NA
+UnK6CAauTQZQz1hx0W8tpKoGX14t2kVIK13xsudh5WgKr+b76Hawa246CVUVgiOJ
+wFP6/gRhvpj2NTF0SSF06Nrz1oenHDSpFaADLsMdtXcW6illrij/GtfTAMBYIIdv
+/e0zvJ0tlomYWTvJcra2gsWpydG8StYfRHH0ujkC9MyrnS9iFbCF6eJV8jLAJJsq
+6G4N7VNJ/QtH6jDUkcGe26B+tj6/s/vmc/+x2lGEwHorINRZ5pRGobr1R1yev5J/
+SRQSEM4e9ARh+83pwGi+RM4YtEtDS9r1oJckdLVLWVByH1XxwYvKSObRtuCypy+m
+F9+Ww7MwwgTPIPJYYoWwO5yTen73EqiAPf48BZcgjtXZSXz/hpZ7+Odt1vKeM1Lk
+4PqZ4Sn/B9UR/DHeVdGHnkk1b/e9yWy+B7SAkvnIDGVVLMcqQq04kw5iVd2usioU
+ofvyixyODfMIZEWH8Ej+syJc+4yA4T4jmTAkbxfREbVKfLZs47KfnFohxm2XecdY
+zvE/d8JJqOPMG3viQXUxl+by+IUkuspDMOLJpPqX7cjCwi5nIyppS0Irrw20Y0k0
+Mji6JdDs4fPY4J5sJuDdJYRfCK/Mi4k9jlSa3w/T35wqqWzVD4xV6CgyLwU1EvNu
+Enxrj4g5ZSy9SbeXhW0HckuO3++cWPSnLzWbY9PdWcU1tEj7OxbXECpnt4bssCKC
+6jGsorV7O+qh2+kno8nqxVN20SXZvZg1HHYOVn0giwKYOOnmhdkFcjmNk1RjFr9i
+j+SOiRSCxdEMv6DCDpG9kwpSZpJG/BpDId2SbKS4JD5+/lqYLGWzS0ZlTVJRHg1e
+EI4PbI47/j1O8tcD8mc7oueqeAsm3vaYtxjgaJxn8dndYTy2gYVRz5Uhcb2liuFd
+TjX8O9HJhocFw8k58mim0eYsrnEsgUNIsIOTiEW/UK0wggFJBgkqhkiG9w0BBwGg
+ggE6BIIBNjCCATIwggEuBgsqhkiG9w0BDAoBAqCB9zCB9DBfBgkqhkiG9w0BBQ0w
+UjAxBgkqhkiG9w0BBQwwJAQQrHTmgX6iXgwAj6WodfDbKQICCAAwDAYIKoZIhvcN
+AgkFADAdBglghkgBZQMEASoEEMsBit86egVQyl3RDAbReu4EgZAImWx0Qa6o1ayV
+Czh4l+T0V2OpwBpLRZl8I6xpYcdf0lzs86ETH8C7NsAOy2EU/ZYTGewxofvD45LM
+VNvPSb2JUPZu0wWM2Rl5AQZncbHN6skT74EggjgW5FqJdeLh5/K7xCQ4IksVMHBt
+iBbJk5zCadZGZImEURVa17Pcqf0A7AJHaNt79ZOo/ZWpooy8ef8xJTAjBgkqhkiG
+9w0BCRUxFgQU4nhAJ8j0szTBF+4V+izVZRrvelUwQTAxMA0GCWCGSAFlAwQCAQUA
+BCBRSnEWRSwJ8jTlDQhM3ToWbqE20AfNzLc7QFJYQWLQxAQIn2pHoNv60nECAggA";
+
+    /// Certificate and key of the pin tests, with `openssl pkcs12 -export -nomac`.
+    const P12_WITHOUT_MAC: &str = "\
+MIIDcQIBAzCCA2oGCSqGSIb3DQEHAaCCA1sEggNXMIIDUzCCAgIGCSqGSIb3DQEH
+AaCCAfMEggHvMIIB6zCCAecGCyqGSIb3DQEMCgEDoIIBrzCCAasGCiqGSIb3DQEJ
+FgGgggGbBIIBlzCCAZMwggE5oAMCAQICFG9nIkE++3nVUGsTtqve/ldAtcNFMAoG
+CCqGSM49BAMCMBQxEjAQBgNVBAMMCWxvY2FsaG9zdDAeFw0yNjEwMTgxNzI5Mzla
+Fw0zNjEwMTUxNzI5MzlaMBQxEjAQBgNVBAMMCWxvY2FsaG9zdDBZMBMGByqGSM49
+AgEGCCqGSM49AwEHA0IABAp4k7tLfUilO43OZUvDRVI8TliCkmx4NjPc9RIwzSdk
+AbKLLfeLarQCnv4WIV/IEQrQhqri0vcRfAvRGVFXqhujaTBnMB0GA1UdDgQWBBRF
+ZrhZckzNFTAHBQvPTyAK1c/y5jAfBgNVHSMEGDAWgBRFZrhZckzNFTAHBQvPTyAK
+1c/y5jAPBgNVHRMBAf8EBTADAQH/MBQGA1UdEQQNMAuCCWxvY2FsaG9zdDAKBggq
+hkjOPQQDAgNIADBFAiAlIbE6ekxxZr9eqe1Y6C5dRFUzI85bl2RZngbLmv7THgIh
+AL9aCGbFDglNbEr5+YK5RPPy2BWf4GObZwvYPp7nE18bMSUwIwYJKoZIhvcNAQkV
+MRYEFOJ4QCfI9LM0wRfuFfos1WUa73pVMIIBSQYJKoZIhvcNAQcBoIIBOgSCATYw
+ggEyMIIBLgYLKoZIhvcNAQwKAQKggfcwgfQwXwYJKoZIhvcNAQUNMFIwMQYJKoZI
+hvcNAQUMMCQEELgXdlF/g339SunyGp9+4XECAggAMAwGCCqGSIb3DQIJBQAwHQYJ
+YIZIAWUDBAEqBBAhH6vV9C+cpE5qqkZpR3UgBIGQgoVu3sXKSg9K0JWVNzoLKKQk
+b33zmmHhrUgzad6dNBObB88EDXXtJ2FMrLO7ntmvCtgIjUQUwBnf3x7iCYV3lLUO
+xn8kvNvq/e+Sn6fdu7ms/erAki7aNWALadrK7e24AJBFnBhmnpUf3KOSXVMvy62v
+SmLxIOS7Onv7u8bqzolPLnshRTwU5S75sNHkZh3mMSUwIwYJKoZIhvcNAQkVMRYE
+FOJ4QCfI9LM0wRfuFfos1WUa73pV";
+
+    fn p12() -> Vec<u8> {
+        BASE64_STANDARD.decode(P12.replace('\n', "")).unwrap()
+    }
+
+    #[test]
+    fn parse_openssl_p12() {
+        let (certs, key) = parse_pkcs12(&p12(), "secret").unwrap();
+
+        assert_eq!(certs.len(), 2);
+        assert_eq!(certs[0].der(), crate::tls::pin::test::cert_der());
+        assert_eq!(key.der(), crate::tls::pin::test::key_der());
+    }
+
+    #[test]
+    fn wrong_password() {
+        let err = parse(&p12(), "wrong").err().unwrap();
+        assert_eq!(err.to_string(), "PKCS#12 MAC mismatch, wrong password?");
+    }
+
+    #[test]
+    fn malformed() {
+        let mut der = p12();
+        der.truncate(100);
+        assert!(parse(&der, "secret").is_err());
+    }
+
+    #[test]
+    fn without_mac() {
+        let der = BASE64_STANDARD
+            .decode(P12_WITHOUT_MAC.replace('\n', ""))
+            .unwrap();
+        let err = parse(&der, "secret").err().unwrap();
+        assert_eq!(err.to_string(), "PKCS#12 without MAC");
+    }
+}
@@ -0,0 +1,478 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::cert::read_pem_certs;
+use super::{load_root_certs, Certificate, PrivateKey, RootCerts, TlsConfig};
+use crate::Error;
+
+/// Source of certificates that can change while the [`Agent`](crate::Agent) is running.
+///
+/// Set in [`TlsConfig::cert_provider`]. Before opening a new connection, the TLS
+/// connector checks the [`generation()`][CertProvider::generation] and rebuilds its TLS
+/// configuration when it has changed. If the TLS implementation rejects the new
+/// certificates, the previous configuration is kept. Connections already in the pool
+/// keep using the certificates they were opened with.
+///
+/// [`CertFiles`] is a provider reloading PEM files when they change on disk.
+pub trait CertProvider: fmt::Debug + Send + Sync + 'static {
+    /// A number that changes whenever the provided certificates change.
+    fn generation(&self) -> u64;
+
+    /// Client certificate chain with corresponding private key.
+    ///
+    /// `None` means to use [`TlsConfig::client_cert`].
+    fn client_cert(&self) -> Option<(Vec<Certificate<'static>>, Arc<PrivateKey<'static>>)> {
+        None
+    }
+
+    /// The root certificates.
+    ///
+    /// `None` means to use [`TlsConfig::root_certs`].
+    fn root_certs(&self) -> Option<RootCerts> {
+        None
+    }
+}
+
+/// [`CertProvider`] reading PEM files, and reloading them when they change.
+///
+/// The files are checked for changes (modification time and size) before each new
+/// connection. If reloading fails, for instance when a file is only half written, the
+/// previously loaded certificates are kept.
+///
+/// ```
+/// use std::sync::Arc;
+/// use ureq::tls::{CertFiles, TlsConfig};
+///
+/// let files = CertFiles::new()
+///     .client_cert_files("/etc/certs/client.pem", "/etc/certs/client.key")
+///     .root_cert_file("/etc/certs/ca.pem");
+///
+/// let tls_config = TlsConfig {
+///     cert_provider: Some(Arc::new(files)),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Default)]
+pub struct CertFiles {
+    client_cert: Option<(PathBuf, PathBuf)>,
+    root_certs: Option<PathBuf>,
+    loaded: Mutex<Loaded>,
+}
+
+#[derive(Debug, Default)]
+struct Loaded {
+    generation: u64,
+    stamps: Vec<Option<(SystemTime, u64)>>,
+    client_cert: Option<(Vec<Certificate<'static>>, Arc<PrivateKey<'static>>)>,
+    root_certs: Option<RootCerts>,
+}
+
+impl CertFiles {
+    /// Creates a provider without any files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the client certificate chain and private key from PEM files.
+    ///
+    /// The files may be the same, if both the chain and key are in it.
+    pub fn client_cert_files(
+        mut self,
+        cert_chain: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+    ) -> Self {
+        self.client_cert = Some((cert_chain.into(), key.into()));
+        self
+    }
+
+    /// Read the root certificates from a PEM file.
+    pub fn root_cert_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root_certs = Some(path.into());
+        self
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        let client = self.client_cert.iter().flat_map(|(c, k)| [c, k]);
+        client.chain(self.root_certs.iter())
+    }
+
+    fn reload_if_changed(&self) -> u64 {
+        let mut loaded = self.loaded.lock().unwrap();
+
+        let stamps: Vec<_> = self
+            .paths()
+            .map(|p| {
+                let meta = fs::metadata(p).ok()?;
+                Some((meta.modified().ok()?, meta.len()))
+            })
+            .collect();
+
+        if stamps == loaded.stamps {
+            return loaded.generation;
+        }
+
+        match self.load() {
+            Ok((client_cert, root_certs)) => {
+                debug!("Loaded certificate files");
+                loaded.client_cert = client_cert;
+                loaded.root_certs = root_certs;
+                loaded.generation += 1;
+            }
+            Err(e) => {
+                // Keep the previous, and try again next time.
+                warn!("Failed to load certificate files: {}", e);
+                return loaded.generation;
+            }
+        }
+        loaded.stamps = stamps;
+
+        loaded.generation
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn load(
+        &self,
+    ) -> Result<
+        (
+            Option<(Vec<Certificate<'static>>, Arc<PrivateKey<'static>>)>,
+            Option<RootCerts>,
+        ),
+        Error,
+    > {
+        let client_cert = match &self.client_cert {
+            Some((cert_path, key_path)) => {
+                let certs = read_pem_certs(cert_path)?;
+                if certs.is_empty() {
+                    return Err(Error::Tls("No pem encoded cert found"));
+                }
+                let key = fs::read(key_path)?;
+                let key = PrivateKey::from_pem(&key)?.to_owned();
+                Some((certs, Arc::new(key)))
+            }
+            None => None,
+        };
+
+        let root_certs = match &self.root_certs {
+            Some(path) => Some(RootCerts::SpecificCerts(load_root_certs(path)?)),
+            None => None,
+        };
+
+        Ok((client_cert, root_certs))
+    }
+}
+
+impl CertProvider for CertFiles {
+    fn generation(&self) -> u64 {
+        self.reload_if_changed()
+    }
+
+    fn client_cert(&self) -> Option<(Vec<Certificate<'static>>, Arc<PrivateKey<'static>>)> {
+        self.loaded.lock().unwrap().client_cert.clone()
+    }
+
+    fn root_certs(&self) -> Option<RootCerts> {
+        self.loaded.lock().unwrap().root_certs.clone()
+    }
+}
+
+/// The config of a TLS connector, built from the [`TlsConfig`], and rebuilt when the
+/// [`CertProvider`] changes.
+///
+/// There is one config per [`TlsConfig`], since upgrades and CONNECT requests don't
+/// offer `h2`, and an `https` proxy can have TLS settings of its own.
+pub(crate) struct ConfigCache<T> {
+    cached: Mutex<Vec<CacheEntry<T>>>,
+}
+
+/// Max number of configs kept, in case the [`TlsConfig`] keeps changing.
+const MAX_CACHED_CONFIGS: usize = 8;
+
+struct CacheEntry<T> {
+    tls_config: TlsConfig,
+    generation: u64,
+    config: Arc<T>,
+}
+
+impl<T> ConfigCache<T> {
+    pub fn get_or_build(
+        &self,
+        tls_config: &TlsConfig,
+        build: impl FnOnce(&TlsConfig) -> Result<Arc<T>, Error>,
+    ) -> Result<Arc<T>, Error> {
+        let provider = tls_config.cert_provider.as_ref();
+        let generation = provider.map(|p| p.generation()).unwrap_or(0);
+
+        let mut cached = self.cached.lock().unwrap();
+        let entry = cached
+            .iter()
+            .position(|e| same_config(&e.tls_config, tls_config));
+
+        if let Some(i) = entry {
+            if cached[i].generation == generation {
+                return Ok(cached[i].config.clone()); // cheap clone due to Arc
+            }
+            debug!("Certificates changed, rebuild TLS config");
+        }
+
+        let key = tls_config.clone();
+
+        let tls_config = match provider {
+            Some(p) => Cow::Owned(TlsConfig {
+                client_cert: p.client_cert().or_else(|| tls_config.client_cert.clone()),
+                root_certs: p
+                    .root_certs()
+                    .unwrap_or_else(|| tls_config.root_certs.clone()),
+                ..tls_config.clone()
+            }),
+            None => Cow::Borrowed(tls_config),
+        };
+
+        let config = match (build(&tls_config), entry) {
+            (Ok(v), _) => v,
+            // Keep the previous config, and try again when the certificates change.
+            (Err(e), Some(i)) => {
+                warn!("Failed to rebuild TLS config, keep the previous: {}", e);
+                cached[i].generation = generation;
+                return Ok(cached[i].config.clone());
+            }
+            (Err(e), None) => return Err(e),
+        };
+
+        let value = CacheEntry {
+            tls_config: key,
+            generation,
+            config: config.clone(),
+        };
+
+        match entry {
+            Some(i) => cached[i] = value,
+            None => {
+                if cached.len() >= MAX_CACHED_CONFIGS {
+                    cached.remove(0);
+                }
+                cached.push(value)
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Whether two configs build the same TLS config.
+///
+/// Certificates are compared by value, and callbacks and providers by identity.
+fn same_config(a: &TlsConfig, b: &TlsConfig) -> bool {
+    fn same_certs(a: &[Certificate], b: &[Certificate]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.der() == b.der())
+    }
+
+    fn same_roots(a: &RootCerts, b: &RootCerts) -> bool {
+        use RootCerts::*;
+        match (a, b) {
+            (SpecificCerts(a), SpecificCerts(b)) => same_certs(a, b),
+            (PlatformVerifier, PlatformVerifier) => true,
+            (WebPki, WebPki) => true,
+            (PlatformVerifierWith(a), PlatformVerifierWith(b)) => same_certs(a, b),
+            (WebPkiWith(a), WebPkiWith(b)) => same_certs(a, b),
+            _ => false,
+        }
+    }
+
+    let same_client_cert = match (&a.client_cert, &b.client_cert) {
+        (Some((ac, ak)), Some((bc, bk))) => {
+            same_certs(ac, bc) && ak.kind() == bk.kind() && ak.der() == bk.der()
+        }
+        (None, None) => true,
+        _ => false,
+    };
+
+    let same_verifier = match (&a.cert_verifier, &b.cert_verifier) {
+        (Some(a), Some(b)) => a.ptr_eq(b),
+        (None, None) => true,
+        _ => false,
+    };
+
+    let same_provider = match (&a.cert_provider, &b.cert_provider) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    };
+
+    #[cfg(feature = "_rustls")]
+    let same_crypto = match (&a.rustls_crypto_provider, &b.rustls_crypto_provider) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    };
+    #[cfg(not(feature = "_rustls"))]
+    let same_crypto = true;
+
+    let same_crls =
+        a.crls.len() == b.crls.len() && a.crls.iter().zip(&b.crls).all(|(a, b)| a.der() == b.der());
+
+    a.provider == b.provider
+        && same_client_cert
+        && same_roots(&a.root_certs, &b.root_certs)
+        && same_verifier
+        && same_provider
+        && a.use_sni == b.use_sni
+        && a.alpn_protocols == b.alpn_protocols
+        && a.min_version == b.min_version
+        && a.max_version == b.max_version
+        && a.cipher_suites == b.cipher_suites
+        && same_crypto
+        && a.kx_groups == b.kx_groups
+        && a.spki_pins == b.spki_pins
+        && same_crls
+        && a.ocsp_stapling == b.ocsp_stapling
+        && a.session_cache_size == b.session_cache_size
+        && a.key_log == b.key_log
+        && a.disable_verification == b.disable_verification
+}
+
+impl<T> Default for ConfigCache<T> {
+    fn default() -> Self {
+        Self {
+            cached: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+
+    use super::*;
+    use crate::tls::pin::test::{cert_der, key_der};
+
+    fn pem(label: &str, der: &[u8]) -> String {
+        let b64 = BASE64_STANDARD.encode(der);
+        format!("-----BEGIN {label}-----\n{b64}\n-----END {label}-----\n")
+    }
+
+    #[derive(Debug, Default)]
+    struct Counter(AtomicU64);
+
+    impl CertProvider for Counter {
+        fn generation(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn config_cache_rebuilds_on_new_generation() {
+        let counter = Arc::new(Counter::default());
+        let tls_config = TlsConfig {
+            cert_provider: Some(counter.clone()),
+            ..Default::default()
+        };
+
+        let cache = ConfigCache::default();
+        let builds = AtomicU64::new(0);
+        let build = |_: &TlsConfig| Ok(Arc::new(builds.fetch_add(1, Ordering::SeqCst)));
+
+        assert_eq!(*cache.get_or_build(&tls_config, build).unwrap(), 0);
+        assert_eq!(*cache.get_or_build(&tls_config, build).unwrap(), 0);
+
+        counter.0.store(1, Ordering::SeqCst);
+        assert_eq!(*cache.get_or_build(&tls_config, build).unwrap(), 1);
+        assert_eq!(*cache.get_or_build(&tls_config, build).unwrap(), 1);
+
+        // Another config for other ALPN protocols.
+        let http1 = TlsConfig {
+            alpn_protocols: vec!["http/1.1".into()],
+            ..tls_config.clone()
+        };
+        assert_eq!(*cache.get_or_build(&http1, build).unwrap(), 2);
+        assert_eq!(*cache.get_or_build(&tls_config, build).unwrap(), 1);
+
+        // Another config for other root certificates, such as of a proxy.
+        let webpki = TlsConfig {
+            root_certs: RootCerts::WebPki,
+            ..tls_config.clone()
+        };
+        assert_eq!(*cache.get_or_build(&webpki, build).unwrap(), 3);
+        assert_eq!(*cache.get_or_build(&webpki.clone(), build).unwrap(), 3);
+        assert_eq!(*cache.get_or_build(&tls_config, build).unwrap(), 1);
+    }
+
+    #[test]
+    fn config_cache_keeps_previous_on_failed_build() {
+        let counter = Arc::new(Counter::default());
+        let tls_config = TlsConfig {
+            cert_provider: Some(counter.clone()),
+            ..Default::default()
+        };
+
+        let cache = ConfigCache::default();
+        let builds = AtomicU64::new(0);
+        let failing = |_: &TlsConfig| {
+            builds.fetch_add(1, Ordering::SeqCst);
+            Err(Error::Tls("bad certificate"))
+        };
+
+        // Without a previous config, the error is returned.
+        assert!(cache.get_or_build(&tls_config, failing).is_err());
+        assert_eq!(
+            *cache
+                .get_or_build(&tls_config, |_| Ok(Arc::new(7)))
+                .unwrap(),
+            7
+        );
+
+        counter.0.store(1, Ordering::SeqCst);
+        assert_eq!(*cache.get_or_build(&tls_config, failing).unwrap(), 7);
+        // Not retried until the next change.
+        assert_eq!(*cache.get_or_build(&tls_config, failing).unwrap(), 7);
+        assert_eq!(builds.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn cert_files_reload_on_change() {
+        let dir = std::env::temp_dir().join(format!("ureq-certfiles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+
+        let cert = pem("CERTIFICATE", &cert_der());
+        fs::write(&cert_path, &cert).unwrap();
+        fs::write(&key_path, pem("PRIVATE KEY", &key_der())).unwrap();
+
+        let files = CertFiles::new()
+            .client_cert_files(&cert_path, &key_path)
+            .root_cert_file(&cert_path);
+
+        assert_eq!(files.generation(), 1);
+        assert_eq!(files.generation(), 1);
+
+        let (certs, key) = files.client_cert().unwrap();
+        assert_eq!(certs[0].der(), cert_der());
+        assert_eq!(key.der(), key_der());
+        let Some(RootCerts::SpecificCerts(roots)) = files.root_certs() else {
+            panic!("expected root certs");
+        };
+        assert_eq!(roots.len(), 1);
+
+        // A different size is a change, regardless of mtime resolution.
+        fs::write(&cert_path, format!("{cert}{cert}")).unwrap();
+        assert_eq!(files.generation(), 2);
+        let Some(RootCerts::SpecificCerts(roots)) = files.root_certs() else {
+            panic!("expected root certs");
+        };
+        assert_eq!(roots.len(), 2);
+
+        // A broken file keeps the previous certificates.
+        fs::write(&key_path, "garbage").unwrap();
+        assert_eq!(files.generation(), 2);
+        assert!(files.client_cert().is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
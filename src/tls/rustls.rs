@@ -1,18 +1,30 @@
+use std::collections::HashMap;
 use std::fmt;
-use std::io::{Read, Write};
-use std::sync::{Arc, OnceLock};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
 
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
-use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned, ALL_VERSIONS};
+use rustls::client::{Resumption, VerifierBuilderError, WebPkiServerVerifier};
+use rustls::crypto::{CryptoProvider, SupportedKxGroup};
+use rustls::version::{TLS12, TLS13};
+use rustls::{CertificateError, HandshakeKind, OtherError};
+use rustls::{ClientConfig, ClientConnection, KeyLog, KeyLogFile, RootCertStore, StreamOwned};
+use rustls::{SupportedCipherSuite, SupportedProtocolVersion};
 use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer};
-use rustls_pki_types::{PrivateSec1KeyDer, ServerName};
+use rustls_pki_types::{CertificateRevocationListDer, PrivateSec1KeyDer, ServerName};
+use rustls_pki_types::{SignatureVerificationAlgorithm, TrustAnchor, UnixTime};
 
 use crate::tls::cert::KeyKind;
-use crate::tls::{RootCerts, TlsProvider};
-use crate::transport::time::NextTimeout;
+use crate::tls::ocsp;
+use crate::tls::ocsp::sequence_of;
+use crate::tls::pin::{pins_for, verify_spki_pins};
+use crate::tls::provider::ConfigCache;
+use crate::tls::{CertVerifier, Certificate, Crl, OcspStapling, RootCerts, ServerCertificate};
+use crate::tls::{SpkiPin, TlsHandshake, TlsProvider, TlsVersion};
+use crate::transport::time::{Duration, NextTimeout};
 use crate::transport::{Buffers, ConnectionDetails, Connector, LazyBuffers};
 use crate::transport::{Transport, TransportAdapter};
-use crate::Error;
+use crate::{Error, TimeoutReason};
 
 use super::TlsConfig;
 
@@ -21,7 +33,31 @@ use super::TlsConfig;
 /// Requires feature flag **rustls**.
 #[derive(Default)]
 pub struct RustlsConnector {
-    config: OnceLock<Arc<ClientConfig>>,
+    config: ConfigCache<ClientConfig>,
+    /// Where tests log TLS secrets, instead of `SSLKEYLOGFILE`.
+    #[cfg(test)]
+    key_log_path: Option<std::path::PathBuf>,
+}
+
+impl RustlsConnector {
+    /// Connector logging TLS secrets to `path`, when enabled by [`TlsConfig::key_log`].
+    #[cfg(test)]
+    fn with_key_log_path(path: std::path::PathBuf) -> Self {
+        RustlsConnector {
+            key_log_path: Some(path),
+            ..Default::default()
+        }
+    }
+
+    fn build_config(&self, tls_config: &TlsConfig) -> Result<Arc<ClientConfig>, Error> {
+        #[cfg(test)]
+        if let Some(path) = &self.key_log_path {
+            let path = path.clone();
+            return build_config_with_key_log(tls_config, move || test::PathKeyLog(path));
+        }
+
+        build_config(tls_config)
+    }
 }
 
 impl Connector for RustlsConnector {
@@ -50,9 +86,10 @@ impl Connector for RustlsConnector {
 
         let tls_config = &details.config.tls_config;
 
-        // Initialize the config on first run.
-        let config_ref = self.config.get_or_init(|| build_config(tls_config));
-        let config = config_ref.clone(); // cheap clone due to Arc
+        // Built on first run, and rebuilt if the certificates change.
+        let config = self
+            .config
+            .get_or_build(tls_config, |c| self.build_config(c))?;
 
         let name_borrowed: ServerName<'_> = details
             .uri
@@ -78,7 +115,12 @@ impl Connector for RustlsConnector {
             details.config.output_buffer_size,
         );
 
-        let transport = Box::new(RustlsTransport { buffers, stream });
+        let mut transport = Box::new(RustlsTransport { buffers, stream });
+
+        // The negotiated protocol must be known before the first request.
+        if !tls_config.alpn_protocols.is_empty() {
+            transport.handshake(details.timeout)?;
+        }
 
         debug!("Wrapped TLS");
 
@@ -86,48 +128,128 @@ impl Connector for RustlsConnector {
     }
 }
 
-fn build_config(tls_config: &TlsConfig) -> Arc<ClientConfig> {
-    // Improve chances of ureq working out-of-the-box by not requiring the user
-    // to select a default crypto provider.
-    let provider = rustls::crypto::CryptoProvider::get_default()
-        .cloned()
-        .unwrap_or(Arc::new(rustls::crypto::ring::default_provider()));
+fn build_config(tls_config: &TlsConfig) -> Result<Arc<ClientConfig>, Error> {
+    build_config_with_key_log(tls_config, KeyLogFile::new)
+}
 
-    let builder = ClientConfig::builder_with_provider(provider.clone())
-        .with_protocol_versions(ALL_VERSIONS)
-        .expect("all TLS versions");
+/// Like [`build_config`], with the log for TLS secrets if [`TlsConfig::key_log`] is enabled.
+fn build_config_with_key_log<K: KeyLog + 'static>(
+    tls_config: &TlsConfig,
+    key_log: impl FnOnce() -> K,
+) -> Result<Arc<ClientConfig>, Error> {
+    let mut provider = match &tls_config.rustls_crypto_provider {
+        Some(provider) => (**provider).clone(),
+        None => match CryptoProvider::get_default() {
+            Some(provider) => (**provider).clone(),
+            // Improve chances of ureq working out-of-the-box by not requiring the user
+            // to select a default crypto provider.
+            None => feature_provider()?,
+        },
+    };
 
-    let builder = if tls_config.disable_verification {
+    if !tls_config.cipher_suites.is_empty() {
+        provider.cipher_suites = select_cipher_suites(&provider, &tls_config.cipher_suites)?;
+    }
+
+    if !tls_config.kx_groups.is_empty() {
+        provider.kx_groups = select_kx_groups(&provider, &tls_config.kx_groups)?;
+    }
+
+    let provider = Arc::new(provider);
+
+    let versions = protocol_versions(tls_config)?;
+
+    let builder =
+        ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(&versions)?;
+
+    let crls = &tls_config.crls;
+    let uses_platform = matches!(
+        tls_config.root_certs,
+        RootCerts::PlatformVerifier | RootCerts::PlatformVerifierWith(_)
+    );
+    if !crls.is_empty() && uses_platform && !tls_config.disable_verification {
+        return Err(Error::UnsupportedTlsConfig(
+            "CRLs can't be used with the platform verifier".into(),
+        ));
+    }
+
+    let verifier: Arc<dyn ServerCertVerifier> = if tls_config.disable_verification {
         debug!("Certificate verification disabled");
-        builder
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(DisabledVerifier))
+        Arc::new(DisabledVerifier)
     } else {
-        match &tls_config.root_certs {
+        let verifier: Arc<dyn ServerCertVerifier> = match &tls_config.root_certs {
             RootCerts::SpecificCerts(certs) => {
-                let root_certs = certs.iter().map(|c| CertificateDer::from(c.der()));
-
-                let mut root_store = RootCertStore::empty();
-                let (added, ignored) = root_store.add_parsable_certificates(root_certs);
-                debug!("Added {} and ignored {} root certs", added, ignored);
-
-                builder.with_root_certificates(root_store)
+                webpki_verifier(root_store_of(certs), crls, provider.clone())?
+            }
+            RootCerts::PlatformVerifier => {
+                Arc::new(rustls_platform_verifier::Verifier::new().with_provider(provider.clone()))
             }
-            RootCerts::PlatformVerifier => builder
-                // This actually not dangerous. The rustls_platform_verifier is safe.
-                .dangerous()
-                .with_custom_certificate_verifier(Arc::new(
-                    rustls_platform_verifier::Verifier::new().with_provider(provider),
-                )),
             RootCerts::WebPki => {
                 let root_store = RootCertStore {
                     roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
                 };
-                builder.with_root_certificates(root_store)
+                webpki_verifier(root_store, crls, provider.clone())?
+            }
+            RootCerts::PlatformVerifierWith(certs) => {
+                let platform =
+                    rustls_platform_verifier::Verifier::new().with_provider(provider.clone());
+
+                Arc::new(FallbackVerifier {
+                    inner: Arc::new(platform),
+                    fallback: webpki_verifier(root_store_of(certs), crls, provider.clone())?,
+                })
             }
+            RootCerts::WebPkiWith(certs) => {
+                let mut root_store = root_store_of(certs);
+                root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+                webpki_verifier(root_store, crls, provider.clone())?
+            }
+        };
+
+        if tls_config.ocsp_stapling == OcspStapling::Ignore {
+            verifier
+        } else {
+            debug!(
+                "Check stapled OCSP responses: {:?}",
+                tls_config.ocsp_stapling
+            );
+            Arc::new(OcspVerifier {
+                inner: verifier,
+                required: tls_config.ocsp_stapling == OcspStapling::Require,
+                anchors: ocsp_anchors(&tls_config.root_certs),
+                provider: provider.clone(),
+            })
         }
     };
 
+    let verifier: Arc<dyn ServerCertVerifier> = match &tls_config.cert_verifier {
+        Some(callback) => {
+            debug!("Use custom certificate verifier");
+            Arc::new(CallbackVerifier {
+                inner: verifier,
+                callback: callback.clone(),
+            })
+        }
+        None => verifier,
+    };
+
+    let verifier: Arc<dyn ServerCertVerifier> = if tls_config.spki_pins.is_empty() {
+        verifier
+    } else {
+        debug!("Pin public keys for {} hosts", tls_config.spki_pins.len());
+        Arc::new(PinningVerifier {
+            inner: verifier,
+            spki_pins: tls_config.spki_pins.clone(),
+            anchors: ocsp_anchors(&tls_config.root_certs),
+            provider: provider.clone(),
+        })
+    };
+
+    // This is only dangerous for the DisabledVerifier, the others verify.
+    let builder = builder
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+
     let mut config = if let Some((certs, key)) = &tls_config.client_cert {
         let cert_chain = certs
             .iter()
@@ -141,20 +263,181 @@ fn build_config(tls_config: &TlsConfig) -> Arc<ClientConfig> {
         .clone_key();
         debug!("Use client certficiate with key kind {:?}", key.kind());
 
-        builder
-            .with_client_auth_cert(cert_chain.collect(), key_der)
-            .expect("valid client auth certificate")
+        // The certificates might come from a CertProvider, which means they can be
+        // invalid at runtime.
+        builder.with_client_auth_cert(cert_chain.collect(), key_der)?
     } else {
         builder.with_no_client_auth()
     };
 
     config.enable_sni = tls_config.use_sni;
 
+    config.alpn_protocols = tls_config
+        .alpn_protocols
+        .iter()
+        .map(|p| p.as_bytes().to_vec())
+        .collect();
+
     if !tls_config.use_sni {
         debug!("Disable SNI");
     }
 
-    Arc::new(config)
+    // The config is shared by all connections of the agent, and so is the session cache.
+    config.resumption = if tls_config.session_cache_size > 0 {
+        Resumption::in_memory_sessions(tls_config.session_cache_size)
+    } else {
+        debug!("Disable TLS session resumption");
+        Resumption::disabled()
+    };
+
+    // We never write early data, and it would be open to replay attacks.
+    config.enable_early_data = false;
+
+    if tls_config.key_log {
+        warn!("TLS key logging enabled, secrets are written to SSLKEYLOGFILE");
+        config.key_log = Arc::new(key_log());
+    }
+
+    Ok(Arc::new(config))
+}
+
+/// The crypto provider selected by feature flags, preferring aws-lc-rs if both are enabled.
+fn feature_provider() -> Result<CryptoProvider, Error> {
+    #[cfg(feature = "rustls-aws-lc-rs")]
+    return Ok(rustls::crypto::aws_lc_rs::default_provider());
+
+    #[cfg(all(feature = "_ring", not(feature = "rustls-aws-lc-rs")))]
+    return Ok(rustls::crypto::ring::default_provider());
+
+    #[cfg(not(any(feature = "_ring", feature = "rustls-aws-lc-rs")))]
+    Err(Error::Tls(
+        "No rustls crypto provider, set TlsConfig::rustls_crypto_provider",
+    ))
+}
+
+fn root_store_of(certs: &[Certificate<'static>]) -> RootCertStore {
+    let root_certs = certs.iter().map(|c| CertificateDer::from(c.der()));
+
+    let mut root_store = RootCertStore::empty();
+    let (added, ignored) = root_store.add_parsable_certificates(root_certs);
+    debug!("Added {} and ignored {} root certs", added, ignored);
+
+    root_store
+}
+
+fn webpki_verifier(
+    root_store: RootCertStore,
+    crls: &[Crl<'static>],
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn ServerCertVerifier>, Error> {
+    let mut builder = WebPkiServerVerifier::builder_with_provider(Arc::new(root_store), provider);
+
+    if !crls.is_empty() {
+        debug!("Check revocation with {} CRLs", crls.len());
+        let crls = crls
+            .iter()
+            .map(|c| CertificateRevocationListDer::from(c.der().to_vec()));
+        // Certificates not covered by the CRLs are accepted, like with no CRLs at all.
+        builder = builder.with_crls(crls).allow_unknown_revocation_status();
+    }
+
+    let verifier = builder.build().map_err(|e| {
+        warn!("rustls failed to build verifier: {}", e);
+        match e {
+            VerifierBuilderError::InvalidCrl(_) => {
+                Error::Tls("Invalid certificate revocation list")
+            }
+            _ => Error::Tls("Rustls failed to build certificate verifier"),
+        }
+    })?;
+    Ok(verifier)
+}
+
+/// The roots, in which to find the issuer of a server certificate for checking OCSP.
+fn ocsp_anchors(root_certs: &RootCerts) -> Vec<TrustAnchor<'static>> {
+    match root_certs {
+        RootCerts::SpecificCerts(certs) | RootCerts::PlatformVerifierWith(certs) => {
+            root_store_of(certs).roots
+        }
+        RootCerts::WebPki => webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        RootCerts::WebPkiWith(certs) => {
+            let mut root_store = root_store_of(certs);
+            root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            root_store.roots
+        }
+        // The platform roots aren't available, only the intermediates sent by the server.
+        RootCerts::PlatformVerifier => vec![],
+    }
+}
+
+fn protocol_versions(
+    tls_config: &TlsConfig,
+) -> Result<Vec<&'static SupportedProtocolVersion>, Error> {
+    let min = tls_config.min_version.unwrap_or(TlsVersion::Tls12);
+    let max = tls_config.max_version.unwrap_or(TlsVersion::Tls13);
+
+    let versions: Vec<_> = [(TlsVersion::Tls12, &TLS12), (TlsVersion::Tls13, &TLS13)]
+        .into_iter()
+        .filter(|(v, _)| *v >= min && *v <= max)
+        .map(|(_, v)| v)
+        .collect();
+
+    if versions.is_empty() {
+        return Err(Error::UnsupportedTlsConfig(format!(
+            "no TLS version between {:?} and {:?}",
+            min, max
+        )));
+    }
+
+    Ok(versions)
+}
+
+fn select_cipher_suites(
+    provider: &CryptoProvider,
+    names: &[String],
+) -> Result<Vec<SupportedCipherSuite>, Error> {
+    names
+        .iter()
+        .map(|name| {
+            provider
+                .cipher_suites
+                .iter()
+                .find(|s| cipher_suite_name(s).eq_ignore_ascii_case(name))
+                .copied()
+                .ok_or_else(|| {
+                    Error::UnsupportedTlsConfig(format!("unknown cipher suite: {}", name))
+                })
+        })
+        .collect()
+}
+
+/// The IANA name of the cipher suite.
+fn cipher_suite_name(suite: &SupportedCipherSuite) -> String {
+    let name = format!("{:?}", suite.suite());
+    // rustls names the TLS 1.3 suites TLS13_*.
+    match name.strip_prefix("TLS13_") {
+        Some(rest) => format!("TLS_{}", rest),
+        None => name,
+    }
+}
+
+fn select_kx_groups(
+    provider: &CryptoProvider,
+    names: &[String],
+) -> Result<Vec<&'static dyn SupportedKxGroup>, Error> {
+    names
+        .iter()
+        .map(|name| {
+            provider
+                .kx_groups
+                .iter()
+                .find(|g| format!("{:?}", g.name()).eq_ignore_ascii_case(name))
+                .copied()
+                .ok_or_else(|| {
+                    Error::UnsupportedTlsConfig(format!("unknown key exchange group: {}", name))
+                })
+        })
+        .collect()
 }
 
 struct RustlsTransport {
@@ -162,6 +445,27 @@ struct RustlsTransport {
     stream: StreamOwned<ClientConnection, TransportAdapter>,
 }
 
+impl RustlsTransport {
+    fn handshake(&mut self, timeout: NextTimeout) -> Result<(), Error> {
+        self.stream.sock.timeout = timeout;
+        while self.stream.conn.is_handshaking() {
+            self.stream
+                .conn
+                .complete_io(&mut self.stream.sock)
+                .map_err(from_io)?;
+        }
+        debug!(
+            "TLS handshake: {:?}, negotiated ALPN: {:?}",
+            self.stream.conn.handshake_kind(),
+            self.stream
+                .conn
+                .alpn_protocol()
+                .map(String::from_utf8_lossy)
+        );
+        Ok(())
+    }
+}
+
 impl Transport for RustlsTransport {
     fn buffers(&mut self) -> &mut dyn Buffers {
         &mut self.buffers
@@ -171,7 +475,7 @@ impl Transport for RustlsTransport {
         self.stream.get_mut().timeout = timeout;
 
         let output = &self.buffers.output()[..amount];
-        self.stream.write_all(output)?;
+        self.stream.write_all(output).map_err(from_io)?;
 
         Ok(())
     }
@@ -184,7 +488,7 @@ impl Transport for RustlsTransport {
         self.stream.get_mut().timeout = timeout;
 
         let input = self.buffers.input_mut();
-        let amount = self.stream.read(input)?;
+        let amount = self.stream.read(input).map_err(from_io)?;
         self.buffers.add_filled(amount);
 
         Ok(amount > 0)
@@ -194,9 +498,540 @@ impl Transport for RustlsTransport {
         self.stream.get_mut().get_mut().is_open()
     }
 
+    fn split(self: Box<Self>) -> Option<(Box<dyn Transport>, Box<dyn Transport>)> {
+        let (conn, sock) = self.stream.into_parts();
+        let (reader, writer) = sock.into_inner().split()?;
+
+        let shared = Arc::new(RustlsShared {
+            conn: Mutex::new(conn),
+            writer: Mutex::new(Some(writer)),
+        });
+
+        let writer = RustlsWriter {
+            buffers: self.buffers.new_like(),
+            shared: shared.clone(),
+        };
+        let reader = RustlsReader {
+            buffers: self.buffers,
+            shared,
+            reader,
+        };
+
+        Some((Box::new(reader), Box::new(writer)))
+    }
+
     fn is_tls(&self) -> bool {
         true
     }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.stream.conn.alpn_protocol()
+    }
+
+    fn tls_handshake(&self) -> Option<TlsHandshake> {
+        match self.stream.conn.handshake_kind()? {
+            HandshakeKind::Resumed => Some(TlsHandshake::Resumed),
+            _ => Some(TlsHandshake::Full),
+        }
+    }
+
+    fn peer_certificates(&self) -> Option<Vec<Certificate<'static>>> {
+        let certs = self.stream.conn.peer_certificates()?;
+        Some(
+            certs
+                .iter()
+                .map(|c| Certificate::from_der(c.as_ref()).to_owned())
+                .collect(),
+        )
+    }
+}
+
+/// TLS connection shared by the halves of a split [`RustlsTransport`].
+struct RustlsShared {
+    conn: Mutex<ClientConnection>,
+    /// Writing half of the transport below. Locked before `conn` while sending, which
+    /// keeps the TLS records in order.
+    ///
+    /// Taken when the [`RustlsWriter`] is dropped, to close the connection.
+    writer: Mutex<Option<Box<dyn Transport>>>,
+}
+
+impl RustlsShared {
+    /// Encrypt and send `plain`, along with any other TLS records the connection wants
+    /// to send.
+    fn send(&self, mut plain: &[u8], timeout: NextTimeout) -> Result<(), Error> {
+        let mut writer = self.writer.lock().unwrap();
+        let writer = writer.as_mut().ok_or_else(Error::disconnected)?;
+
+        loop {
+            let mut records = vec![];
+            {
+                let mut conn = self.conn.lock().unwrap();
+                let n = conn.writer().write(plain)?;
+                plain = &plain[n..];
+                while conn.wants_write() {
+                    conn.write_tls(&mut records)?;
+                }
+            }
+
+            // The connection is not locked while sending, such that the reading half
+            // can decrypt input meanwhile.
+            let mut records = &records[..];
+            while !records.is_empty() {
+                let output = writer.buffers().output_mut();
+                let max = output.len().min(records.len());
+                output[..max].copy_from_slice(&records[..max]);
+                writer.transmit_output(max, timeout)?;
+                records = &records[max..];
+            }
+
+            if plain.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Reading half of a split [`RustlsTransport`].
+struct RustlsReader {
+    buffers: LazyBuffers,
+    shared: Arc<RustlsShared>,
+    /// Reading half of the transport below.
+    reader: Box<dyn Transport>,
+}
+
+impl Transport for RustlsReader {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        &mut self.buffers
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), Error> {
+        let output = &self.buffers.output()[..amount];
+        self.shared.send(output, timeout)
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
+        if self.buffers.can_use_input() {
+            return Ok(true);
+        }
+
+        loop {
+            let mut conn = self.shared.conn.lock().unwrap();
+
+            let input = self.buffers.input_mut();
+            match conn.reader().read(input) {
+                Ok(amount) => {
+                    self.buffers.add_filled(amount);
+                    return Ok(amount > 0);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(from_io(e)),
+            }
+
+            // Wait for TLS records without locking the connection, which would stop
+            // the writing half.
+            drop(conn);
+            if !self.reader.await_input(timeout)? {
+                return Ok(false);
+            }
+
+            let mut conn = self.shared.conn.lock().unwrap();
+            let records = self.reader.buffers().input();
+            let amount = conn.read_tls(&mut &records[..])?;
+            self.reader.buffers().consume(amount);
+            conn.process_new_packets()?;
+
+            // Such as alerts or responses to key updates.
+            let wants_write = conn.wants_write();
+            drop(conn);
+            if wants_write {
+                self.shared.send(&[], timeout)?;
+            }
+        }
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.reader.is_open()
+    }
+}
+
+/// Writing half of a split [`RustlsTransport`].
+struct RustlsWriter {
+    buffers: LazyBuffers,
+    shared: Arc<RustlsShared>,
+}
+
+impl Transport for RustlsWriter {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        &mut self.buffers
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), Error> {
+        let output = &self.buffers.output()[..amount];
+        self.shared.send(output, timeout)
+    }
+
+    fn await_input(&mut self, _timeout: NextTimeout) -> Result<bool, Error> {
+        // Input is only read by the reading half.
+        Ok(false)
+    }
+
+    fn is_open(&mut self) -> bool {
+        let mut writer = self.shared.writer.lock().unwrap();
+        writer.as_mut().map(|w| w.is_open()).unwrap_or(false)
+    }
+}
+
+impl Drop for RustlsWriter {
+    fn drop(&mut self) {
+        let timeout = NextTimeout {
+            after: Duration::NotHappening,
+            reason: TimeoutReason::Global,
+        };
+        self.shared.conn.lock().unwrap().send_close_notify();
+        // Best effort, the connection is closed anyway.
+        let _ = self.shared.send(&[], timeout);
+
+        // Dropping the writing half below closes the connection, which ends a read
+        // blocked on the reading half.
+        self.shared.writer.lock().unwrap().take();
+    }
+}
+
+/// Wrap an error from our verifiers to pass it through rustls.
+fn to_rustls(e: Error) -> rustls::Error {
+    rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(e))))
+}
+
+/// rustls reports errors from the verifier wrapped in an `io::Error`. Unwrap
+/// the errors from our verifiers to make them visible to the user.
+fn from_io(e: io::Error) -> Error {
+    let rustls_error = e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>());
+
+    if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(other))) = rustls_error {
+        match other.0.downcast_ref::<Error>() {
+            Some(Error::PinMismatch(host)) => return Error::PinMismatch(host.clone()),
+            Some(Error::CertificateRejected(host)) => {
+                return Error::CertificateRejected(host.clone())
+            }
+            Some(Error::Revocation(reason)) => return Error::Revocation(reason.clone()),
+            _ => {}
+        }
+    }
+
+    // Revoked according to the CRLs.
+    if let Some(rustls::Error::InvalidCertificate(CertificateError::Revoked)) = rustls_error {
+        return Error::Revocation("certificate is revoked".into());
+    }
+
+    e.into()
+}
+
+/// Verifier trying another verifier when the first rejects the certificate.
+#[derive(Debug)]
+struct FallbackVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    fallback: Arc<dyn ServerCertVerifier>,
+}
+
+impl ServerCertVerifier for FallbackVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let err = match self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+
+        self.fallback
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+            .map_err(|e| {
+                debug!("Certificate rejected by both verifiers: {}, {}", err, e);
+                err
+            })
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Runs the inner verifier, then checks the stapled OCSP response.
+#[derive(Debug)]
+struct OcspVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    required: bool,
+    anchors: Vec<TrustAnchor<'static>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for OcspVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        if ocsp_response.is_empty() {
+            if self.required {
+                debug!("No stapled OCSP response: {}", server_name.to_str());
+                let reason = "no stapled OCSP response".to_string();
+                return Err(to_rustls(Error::Revocation(reason)));
+            }
+            return Ok(verified);
+        }
+
+        let intermediates: Vec<&[u8]> = intermediates.iter().map(|c| c.as_ref()).collect();
+
+        ocsp::check(
+            ocsp_response,
+            end_entity,
+            &intermediates,
+            &self.anchors,
+            self.provider.signature_verification_algorithms.all,
+            now,
+        )
+        .map_err(|e| {
+            debug!("OCSP check failed for {}: {}", server_name.to_str(), e);
+            to_rustls(e)
+        })?;
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Runs the inner verifier, then lets the user callback decide.
+#[derive(Debug)]
+struct CallbackVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    callback: CertVerifier,
+}
+
+impl ServerCertVerifier for CallbackVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let default_outcome = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+
+        let chain: Vec<_> = Some(end_entity)
+            .into_iter()
+            .chain(intermediates)
+            .map(|c| Certificate::from_der(c.as_ref()))
+            .collect();
+
+        let host = server_name.to_str();
+
+        let server = ServerCertificate {
+            chain: &chain,
+            server_name: &host,
+            default_outcome,
+        };
+
+        if self.callback.verify(&server) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            debug!("Certificate rejected by verifier: {}", host);
+            Err(to_rustls(Error::CertificateRejected(host.into_owned())))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Verifies the certificate with the inner verifier, then checks the public key pins
+/// against the certificate path to the trust anchor.
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    spki_pins: HashMap<String, Vec<SpkiPin>>,
+    anchors: Vec<TrustAnchor<'static>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let host = server_name.to_str();
+        let Some(pins) = pins_for(&self.spki_pins, &host) else {
+            return Ok(verified);
+        };
+
+        let algs = self.provider.signature_verification_algorithms.all;
+        let spkis = path_spkis(end_entity, intermediates, &self.anchors, algs, now);
+        verify_spki_pins(&host, pins, spkis.iter().map(|s| s.as_slice())).map_err(to_rustls)?;
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// The public keys (DER SubjectPublicKeyInfo) of the certificate path from the
+/// `end_entity` to one of the `anchors`, including the anchor.
+///
+/// Without a path to the `anchors`, such as for the platform verifier whose roots aren't
+/// known, or a certificate accepted by a [`CertVerifier`], the path ends at the
+/// `intermediates` the end entity chains to. Certificates the server sent that aren't
+/// part of a path are never included.
+fn path_spkis(
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    anchors: &[TrustAnchor<'_>],
+    algs: &[&dyn SignatureVerificationAlgorithm],
+    now: UnixTime,
+) -> Vec<Vec<u8>> {
+    let Ok(cert) = webpki::EndEntityCert::try_from(end_entity) else {
+        return vec![];
+    };
+    let mut spkis = vec![cert.subject_public_key_info().to_vec()];
+
+    let mut add_path = |anchors: &[TrustAnchor<'_>]| {
+        let usage = webpki::KeyUsage::server_auth();
+        let Ok(path) = cert.verify_for_usage(algs, anchors, intermediates, now, usage, None, None)
+        else {
+            return false;
+        };
+        for c in path.intermediate_certificates() {
+            spkis.push(c.subject_public_key_info().to_vec());
+        }
+        spkis.push(sequence_of(path.anchor().subject_public_key_info.as_ref()));
+        true
+    };
+
+    if !add_path(anchors) {
+        trace!("No path to a trust anchor, pin the chain sent by the server");
+        for c in intermediates {
+            if let Ok(anchor) = webpki::anchor_from_trusted_cert(c) {
+                add_path(&[anchor]);
+            }
+        }
+    }
+
+    spkis
 }
 
 #[derive(Debug)]
@@ -250,3 +1085,712 @@ impl fmt::Debug for RustlsTransport {
             .finish()
     }
 }
+
+impl fmt::Debug for RustlsReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RustlsReader")
+            .field("chained", &self.reader)
+            .finish()
+    }
+}
+
+impl fmt::Debug for RustlsWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RustlsWriter").finish()
+    }
+}
+
+#[cfg(all(test, feature = "_ring"))]
+mod test {
+    use super::*;
+
+    fn ring() -> CryptoProvider {
+        rustls::crypto::ring::default_provider()
+    }
+
+    #[test]
+    fn versions_between_min_and_max() {
+        let mut config = TlsConfig::default();
+        assert_eq!(protocol_versions(&config).unwrap(), vec![&TLS12, &TLS13]);
+
+        config.min_version = Some(TlsVersion::Tls13);
+        assert_eq!(protocol_versions(&config).unwrap(), vec![&TLS13]);
+
+        config.max_version = Some(TlsVersion::Tls12);
+        assert!(matches!(
+            protocol_versions(&config),
+            Err(Error::UnsupportedTlsConfig(_))
+        ));
+    }
+
+    #[test]
+    fn cipher_suites_by_iana_name() {
+        let names = [
+            "TLS_CHACHA20_POLY1305_SHA256".to_string(),
+            "tls_ecdhe_rsa_with_aes_128_gcm_sha256".to_string(),
+        ];
+        let suites = select_cipher_suites(&ring(), &names).unwrap();
+        let selected: Vec<_> = suites.iter().map(|s| s.suite()).collect();
+        assert_eq!(
+            selected,
+            vec![
+                rustls::CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+                rustls::CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+            ]
+        );
+
+        let err = select_cipher_suites(&ring(), &["TLS_NULL".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported TLS config: unknown cipher suite: TLS_NULL"
+        );
+    }
+
+    #[test]
+    fn kx_groups_by_iana_name() {
+        let groups = select_kx_groups(&ring(), &["x25519".to_string()]).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name(), rustls::NamedGroup::X25519);
+
+        assert!(select_kx_groups(&ring(), &["ffdhe2048".to_string()]).is_err());
+    }
+
+    #[test]
+    fn config_tls13_only_suites_with_tls12() {
+        // TLS 1.2 without any TLS 1.2 suite is an error from rustls.
+        let config = TlsConfig {
+            max_version: Some(TlsVersion::Tls12),
+            cipher_suites: vec!["TLS_AES_128_GCM_SHA256".into()],
+            root_certs: RootCerts::WebPki,
+            ..Default::default()
+        };
+        assert!(build_config(&config).is_err());
+    }
+
+    #[test]
+    fn crypto_provider_from_config() {
+        let mut provider = ring();
+        provider.cipher_suites.truncate(1);
+        let suite = provider.cipher_suites[0].suite();
+
+        let config = TlsConfig {
+            rustls_crypto_provider: Some(Arc::new(provider)),
+            root_certs: RootCerts::WebPki,
+            ..Default::default()
+        };
+        let client_config = build_config(&config).unwrap();
+
+        let suites: Vec<_> = client_config
+            .crypto_provider()
+            .cipher_suites
+            .iter()
+            .map(|s| s.suite())
+            .collect();
+        assert_eq!(suites, vec![suite]);
+    }
+
+    #[test]
+    fn callback_verifier_accepts_self_signed() {
+        let trusted = crate::tls::pin::test::cert_der();
+        let cert = CertificateDer::from(trusted.clone());
+        let name = ServerName::try_from("localhost").unwrap();
+
+        let verifier = |accept: bool| {
+            let root_store = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            let trusted = trusted.clone();
+            CallbackVerifier {
+                inner: webpki_verifier(root_store, &[], Arc::new(ring())).unwrap(),
+                callback: CertVerifier::new(move |server| {
+                    assert_eq!(server.server_name, "localhost");
+                    assert_eq!(server.chain.len(), 1);
+                    // Self-signed is not trusted by the webpki roots.
+                    assert!(server.default_outcome.is_err());
+                    accept && server.chain[0].der() == trusted
+                }),
+            }
+        };
+
+        let result = verifier(true).verify_server_cert(&cert, &[], &name, &[], UnixTime::now());
+        assert!(result.is_ok());
+
+        let err = verifier(false)
+            .verify_server_cert(&cert, &[], &name, &[], UnixTime::now())
+            .unwrap_err();
+        let err = from_io(io::Error::new(io::ErrorKind::InvalidData, err));
+        assert!(matches!(err, Error::CertificateRejected(h) if h == "localhost"));
+    }
+
+    /// Serve `count` connections with TLS, answering one request on each.
+    fn serve_tls(count: usize) -> std::net::SocketAddr {
+        let cert = crate::tls::pin::test::cert_der();
+        let key = crate::tls::pin::test::key_der();
+        serve_tls_with(count, vec![cert], key, vec![])
+    }
+
+    /// Like [`serve_tls`], with a specific certificate chain, PKCS#8 key and stapled OCSP
+    /// response.
+    fn serve_tls_with(
+        count: usize,
+        chain: Vec<Vec<u8>>,
+        key: Vec<u8>,
+        ocsp: Vec<u8>,
+    ) -> std::net::SocketAddr {
+        use std::net::TcpListener;
+
+        let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(ring()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert_with_ocsp(
+                chain.into_iter().map(CertificateDer::from).collect(),
+                PrivateKeyDer::Pkcs8(key.into()),
+                ocsp,
+            )
+            .unwrap();
+        let server_config = Arc::new(server_config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            for _ in 0..count {
+                let (sock, _) = listener.accept().unwrap();
+                let conn = rustls::ServerConnection::new(server_config.clone()).unwrap();
+                let mut stream = StreamOwned::new(conn, sock);
+
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+                    )
+                    .unwrap();
+                stream.conn.send_close_notify();
+                stream.flush().unwrap();
+            }
+        });
+
+        addr
+    }
+
+    /// Agent trusting the self-signed test certificate, connecting to `addr`.
+    fn test_agent(addr: std::net::SocketAddr, tls_config: TlsConfig) -> crate::Agent {
+        let trusted = crate::tls::pin::test::cert_der();
+        let tls_config = TlsConfig {
+            root_certs: RootCerts::WebPki,
+            cert_verifier: Some(CertVerifier::new(move |s| s.chain[0].der() == trusted)),
+            ..tls_config
+        };
+        plain_agent(addr, tls_config)
+    }
+
+    /// Agent connecting to `addr` with the `tls_config` as is.
+    fn plain_agent(addr: std::net::SocketAddr, tls_config: TlsConfig) -> crate::Agent {
+        use crate::test::FixedResolver;
+        use crate::transport::{ChainedConnector, TcpConnector};
+
+        let config = crate::AgentConfig {
+            tls_config: TlsConfig {
+                provider: TlsProvider::Rustls,
+                ..tls_config
+            },
+            ..Default::default()
+        };
+        let connector = ChainedConnector::new([
+            TcpConnector::default().boxed(),
+            RustlsConnector::default().boxed(),
+        ]);
+        crate::Agent::with_parts(config, connector, FixedResolver(addr))
+    }
+
+    #[test]
+    #[cfg(all(feature = "http2", feature = "websocket"))]
+    fn websocket_against_h2_capable_server() {
+        use crate::websocket::{accept_key, Message};
+        use std::net::TcpListener;
+
+        let cert = crate::tls::pin::test::cert_der();
+        let key = crate::tls::pin::test::key_der();
+        let mut server_config = rustls::ServerConfig::builder_with_provider(Arc::new(ring()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(cert)],
+                PrivateKeyDer::Pkcs8(key.into()),
+            )
+            .unwrap();
+        // The server prefers h2 whenever the client offers it.
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let server_config = Arc::new(server_config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(server_config).unwrap();
+            let mut stream = StreamOwned::new(conn, sock);
+
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let alpn = stream.conn.alpn_protocol().map(|p| p.to_vec());
+
+            let request = String::from_utf8(request).unwrap();
+            let key = request
+                .lines()
+                .find_map(|l| l.strip_prefix("sec-websocket-key: "))
+                .unwrap();
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                upgrade: websocket\r\n\
+                connection: Upgrade\r\n\
+                sec-websocket-accept: {}\r\n\
+                \r\n",
+                accept_key(key)
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            stream.write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+            stream.flush().unwrap();
+            alpn
+        });
+
+        let agent = test_agent(addr, TlsConfig::default());
+        let mut socket = agent.websocket("wss://localhost/").connect().unwrap();
+        assert_eq!(socket.recv().unwrap(), Message::Text("hi".into()));
+
+        let alpn = server.join().unwrap();
+        assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+    }
+
+    #[test]
+    #[cfg(feature = "http2")]
+    fn http2_over_split_transport() {
+        use std::net::TcpListener;
+
+        let cert = crate::tls::pin::test::cert_der();
+        let key = crate::tls::pin::test::key_der();
+        let mut server_config = rustls::ServerConfig::builder_with_provider(Arc::new(ring()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(cert)],
+                PrivateKeyDer::Pkcs8(key.into()),
+            )
+            .unwrap();
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+        let server_config = Arc::new(server_config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(server_config).unwrap();
+            crate::http2::test::serve(StreamOwned::new(conn, sock), "https://localhost");
+        });
+
+        let agent = test_agent(addr, TlsConfig::default());
+
+        let res = agent.get("https://localhost/a").call().unwrap();
+        assert_eq!(res.into_body().read_to_string().unwrap(), "GET /a 0");
+
+        // Larger than the flow control window, sent while the response is awaited.
+        let res = agent
+            .post("https://localhost/upload")
+            .send(&vec![7; 100_000][..])
+            .unwrap();
+        assert_eq!(
+            res.into_body().read_to_string().unwrap(),
+            "POST /upload 100000"
+        );
+
+        drop(agent);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn peer_certificates_in_response() {
+        use crate::tls::PeerCertificates;
+
+        let addr = serve_tls(1);
+        let agent = test_agent(addr, TlsConfig::default());
+
+        let mut res = agent.get("https://localhost/").call().unwrap();
+        assert_eq!(res.body_mut().read_to_string().unwrap(), "ok");
+
+        let certs = res.extensions().get::<PeerCertificates>().unwrap();
+        assert_eq!(certs.chain().len(), 1);
+        assert_eq!(certs.chain()[0].der(), crate::tls::pin::test::cert_der());
+    }
+
+    #[test]
+    fn session_resumption() {
+        let handshakes = |tls_config: TlsConfig| {
+            let addr = serve_tls(2);
+            let agent = test_agent(addr, tls_config);
+            (0..2)
+                .map(|_| {
+                    let mut res = agent.get("https://localhost/").call().unwrap();
+                    res.body_mut().read_to_string().unwrap();
+                    *res.extensions().get::<TlsHandshake>().unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            handshakes(TlsConfig::default()),
+            [TlsHandshake::Full, TlsHandshake::Resumed]
+        );
+
+        let disabled = TlsConfig {
+            session_cache_size: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            handshakes(disabled),
+            [TlsHandshake::Full, TlsHandshake::Full]
+        );
+    }
+
+    /// Appends TLS secrets to a file in the `SSLKEYLOGFILE` format.
+    #[derive(Debug)]
+    pub(super) struct PathKeyLog(pub std::path::PathBuf);
+
+    impl KeyLog for PathKeyLog {
+        fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+            let hex = |b: &[u8]| b.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.0)
+                .unwrap();
+            writeln!(file, "{} {} {}", label, hex(client_random), hex(secret)).unwrap();
+        }
+    }
+
+    #[test]
+    fn key_log_to_file() {
+        use crate::test::FixedResolver;
+        use crate::transport::{ChainedConnector, TcpConnector};
+
+        let call = |key_log: bool| {
+            let name = format!("ureq-keylog-{}-{}", std::process::id(), key_log);
+            let path = std::env::temp_dir().join(name);
+            let _ = std::fs::remove_file(&path);
+
+            let addr = serve_tls(1);
+            let trusted = crate::tls::pin::test::cert_der();
+            let config = crate::AgentConfig {
+                tls_config: TlsConfig {
+                    provider: TlsProvider::Rustls,
+                    root_certs: RootCerts::WebPki,
+                    cert_verifier: Some(CertVerifier::new(move |s| s.chain[0].der() == trusted)),
+                    key_log,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let connector = ChainedConnector::new([
+                TcpConnector::default().boxed(),
+                RustlsConnector::with_key_log_path(path.clone()).boxed(),
+            ]);
+            let agent = crate::Agent::with_parts(config, connector, FixedResolver(addr));
+
+            let mut res = agent.get("https://localhost/").call().unwrap();
+            res.body_mut().read_to_string().unwrap();
+
+            let log = std::fs::read_to_string(&path).ok();
+            let _ = std::fs::remove_file(&path);
+            log
+        };
+
+        let log = call(true).unwrap();
+        assert!(log.contains("CLIENT_TRAFFIC_SECRET_0") || log.contains("CLIENT_RANDOM"));
+
+        assert_eq!(call(false), None);
+    }
+
+    #[test]
+    fn extra_root_certs() {
+        use crate::tls::pin::test::{cert_der, leaf_cert_der, leaf_key_der};
+
+        let ca = vec![Certificate::from_der(&cert_der()).to_owned()];
+
+        for root_certs in [
+            RootCerts::WebPkiWith(ca.clone()),
+            RootCerts::PlatformVerifierWith(ca.clone()),
+        ] {
+            let addr = serve_tls_with(1, vec![leaf_cert_der()], leaf_key_der(), vec![]);
+            let tls_config = TlsConfig {
+                root_certs,
+                ..Default::default()
+            };
+            let agent = plain_agent(addr, tls_config);
+            agent.get("https://localhost/").call().unwrap();
+        }
+
+        // Without the extra root, the certificate is unknown.
+        let addr = serve_tls_with(1, vec![leaf_cert_der()], leaf_key_der(), vec![]);
+        let tls_config = TlsConfig {
+            root_certs: RootCerts::WebPki,
+            ..Default::default()
+        };
+        let agent = plain_agent(addr, tls_config);
+        assert!(agent.get("https://localhost/").call().is_err());
+    }
+
+    #[test]
+    fn ocsp_stapling() {
+        use crate::tls::ocsp::test::{good_response, revoked_response};
+        use crate::tls::pin::test::{cert_der, leaf_cert_der, leaf_key_der};
+
+        let ca = vec![Certificate::from_der(&cert_der()).to_owned()];
+
+        let call = |ocsp: Vec<u8>, ocsp_stapling: OcspStapling| {
+            let addr = serve_tls_with(1, vec![leaf_cert_der()], leaf_key_der(), ocsp);
+            let tls_config = TlsConfig {
+                root_certs: RootCerts::SpecificCerts(ca.clone()),
+                ocsp_stapling,
+                ..Default::default()
+            };
+            let agent = plain_agent(addr, tls_config);
+            agent.get("https://localhost/").call()
+        };
+
+        call(good_response(), OcspStapling::Require).unwrap();
+        call(vec![], OcspStapling::Verify).unwrap();
+
+        let err = call(revoked_response(), OcspStapling::Verify).unwrap_err();
+        assert!(matches!(err, Error::Revocation(r) if r == "certificate is revoked"));
+
+        let err = call(vec![], OcspStapling::Require).unwrap_err();
+        assert!(matches!(err, Error::Revocation(_)));
+
+        // Not checked unless asked for.
+        call(revoked_response(), OcspStapling::Ignore).unwrap();
+    }
+
+    #[test]
+    fn pins_checked_against_verified_path() {
+        use crate::tls::pin::test::{cert_der, leaf_cert_der, leaf_key_der, other_cert_der};
+
+        let pin_of = |der: Vec<u8>| SpkiPin::from_certificate(&Certificate::from_der(&der));
+
+        // The server sends the leaf and an unrelated certificate, but not the root.
+        let call = |pin: SpkiPin| {
+            let addr = serve_tls_with(
+                1,
+                vec![leaf_cert_der(), other_cert_der()],
+                leaf_key_der(),
+                vec![],
+            );
+            let tls_config = TlsConfig {
+                root_certs: RootCerts::SpecificCerts(vec![
+                    Certificate::from_der(&cert_der()).to_owned()
+                ]),
+                spki_pins: [("localhost".to_string(), vec![pin])].into(),
+                ..Default::default()
+            };
+            let agent = plain_agent(addr, tls_config);
+            agent.get("https://localhost/").call()
+        };
+
+        // The trust anchor is part of the path.
+        call(pin_of(cert_der()).unwrap()).unwrap();
+        call(pin_of(leaf_cert_der()).unwrap()).unwrap();
+
+        let err = call(pin_of(other_cert_der()).unwrap()).unwrap_err();
+        assert!(
+            matches!(&err, Error::PinMismatch(h) if h == "localhost"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn crl_revoked() {
+        use crate::tls::pin::test::{cert_der, leaf_cert_der, leaf_key_der};
+
+        // CRL of the test CA, revoking the leaf certificate.
+        const CRL: &str = "-----BEGIN X509 CRL-----
+MIHTMHwCAQEwCgYIKoZIzj0EAwIwFDESMBAGA1UEAwwJbG9jYWxob3N0Fw0yNjEw
+MTgxODAyNTlaFw0zNjEwMTUxODAyNTlaMCcwJQIUHKm67axDitcU5yvQc/VQKXrU
+U2oXDTI2MTAxODAwMDAwMFqgDjAMMAoGA1UdFAQDAgEBMAoGCCqGSM49BAMCA0cA
+MEQCIFc8CNb6Wgmw3RXTJuCknKdHumIQMe96YNa2a+G9AOgWAiB6592IA9+xo+iB
+Xygu7kaFNofLZZPQOknKdfTMVugUyQ==
+-----END X509 CRL-----
+";
+        let crl = Crl::from_pem(CRL.as_bytes()).unwrap().to_owned();
+        let ca = vec![Certificate::from_der(&cert_der()).to_owned()];
+
+        let call = |crls: Vec<Crl<'static>>| {
+            let addr = serve_tls_with(1, vec![leaf_cert_der()], leaf_key_der(), vec![]);
+            let tls_config = TlsConfig {
+                root_certs: RootCerts::SpecificCerts(ca.clone()),
+                crls,
+                ..Default::default()
+            };
+            let agent = plain_agent(addr, tls_config);
+            agent.get("https://localhost/").call()
+        };
+
+        call(vec![]).unwrap();
+
+        let err = call(vec![crl.clone()]).unwrap_err();
+        assert!(matches!(err, Error::Revocation(_)), "{:?}", err);
+
+        // The platform verifier does its own revocation checking.
+        let tls_config = TlsConfig {
+            crls: vec![crl],
+            ..Default::default()
+        };
+        assert!(matches!(
+            build_config(&tls_config),
+            Err(Error::UnsupportedTlsConfig(_))
+        ));
+    }
+
+    /// CONNECT proxy over TLS with the self-signed test certificate, relaying `count`
+    /// tunnels to `origin`.
+    fn serve_tls_proxy(count: usize, origin: std::net::SocketAddr) -> std::net::SocketAddr {
+        use std::net::{TcpListener, TcpStream};
+        use std::time::Duration;
+
+        let cert = crate::tls::pin::test::cert_der();
+        let key = crate::tls::pin::test::key_der();
+
+        let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(ring()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(cert)],
+                PrivateKeyDer::Pkcs8(key.into()),
+            )
+            .unwrap();
+        let server_config = Arc::new(server_config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let tunnel = move |sock: TcpStream| -> io::Result<()> {
+            let conn = rustls::ServerConnection::new(server_config.clone()).unwrap();
+            let mut client = StreamOwned::new(conn, sock);
+
+            let mut request = vec![];
+            let mut buf = [0; 16 * 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = client.read(&mut buf)?;
+                request.extend_from_slice(&buf[..n]);
+            }
+            assert!(request.starts_with(b"CONNECT localhost:443 HTTP/1.1\r\n"));
+            client.write_all(b"HTTP/1.1 200 OK\r\n\r\n")?;
+
+            let mut server = TcpStream::connect(origin)?;
+            let poll = Some(Duration::from_millis(10));
+            client.sock.set_read_timeout(poll)?;
+            server.set_read_timeout(poll)?;
+
+            let would_block = |e: &io::Error| {
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                )
+            };
+
+            loop {
+                match client.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => server.write_all(&buf[..n])?,
+                    Err(e) if would_block(&e) => {}
+                    Err(e) => return Err(e),
+                }
+                match server.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => client.write_all(&buf[..n])?,
+                    Err(e) if would_block(&e) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            client.conn.send_close_notify();
+            client.flush()
+        };
+
+        std::thread::spawn(move || {
+            for _ in 0..count {
+                let (sock, _) = listener.accept().unwrap();
+                // Fails when the client rejects the proxy certificate.
+                let _ = tunnel(sock);
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn https_proxy_tls_in_tls() {
+        use crate::tls::pin::test::{cert_der, leaf_cert_der, leaf_key_der};
+        use crate::Proxy;
+
+        let ca = vec![Certificate::from_der(&cert_der()).to_owned()];
+
+        // Server certificate issued by the test CA.
+        let tls_config = TlsConfig {
+            provider: TlsProvider::Rustls,
+            root_certs: RootCerts::SpecificCerts(ca),
+            ..Default::default()
+        };
+
+        // Proxy with the self-signed test certificate, only trusted via callback.
+        let trusted = cert_der();
+        let proxy_tls_config = TlsConfig {
+            provider: TlsProvider::Rustls,
+            root_certs: RootCerts::WebPki,
+            cert_verifier: Some(CertVerifier::new(move |s| {
+                s.server_name == "localhost" && s.chain[0].der() == trusted
+            })),
+            ..Default::default()
+        };
+
+        let call = |proxy_tls_config: Option<TlsConfig>| {
+            use crate::test::FixedResolver;
+            use crate::transport::{ChainedConnector, ConnectProxyConnector, TcpConnector};
+
+            let origin = serve_tls_with(1, vec![leaf_cert_der()], leaf_key_der(), vec![]);
+            let proxy = serve_tls_proxy(1, origin);
+
+            let config = crate::AgentConfig {
+                tls_config: tls_config.clone(),
+                proxy: Some(Proxy::new(&format!("https://localhost:{}", proxy.port())).unwrap()),
+                proxy_tls_config,
+                ..Default::default()
+            };
+            let connector = ChainedConnector::new([
+                TcpConnector::default().boxed(),
+                ConnectProxyConnector.boxed(),
+                RustlsConnector::default().boxed(),
+            ]);
+            let agent = crate::Agent::with_parts(config, connector, FixedResolver(proxy));
+            agent.get("https://localhost/").call()
+        };
+
+        let mut res = call(Some(proxy_tls_config)).unwrap();
+        assert_eq!(res.body_mut().read_to_string().unwrap(), "ok");
+
+        // The certificates of the server are not valid for the proxy.
+        let err = call(None).unwrap_err();
+        assert!(matches!(err, Error::Io(_)), "{:?}", err);
+    }
+}
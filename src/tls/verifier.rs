@@ -0,0 +1,69 @@
+use std::fmt;
+use std::sync::Arc;
+
+use super::Certificate;
+
+/// Callback to verify the server certificate.
+///
+/// See [`TlsConfig::cert_verifier`](crate::tls::TlsConfig::cert_verifier).
+///
+/// ```
+/// use ureq::tls::{CertVerifier, TlsConfig};
+///
+/// // Accept one specific self-signed certificate, in addition to the default verification.
+/// let trusted: Vec<u8> = vec![/* DER of the certificate */];
+///
+/// let tls_config = TlsConfig {
+///     cert_verifier: Some(CertVerifier::new(move |server| {
+///         server.default_outcome.is_ok() || server.chain[0].der() == trusted
+///     })),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone)]
+#[cfg_attr(not(feature = "_rustls"), allow(dead_code))]
+pub struct CertVerifier(Arc<dyn Fn(&ServerCertificate<'_>) -> bool + Send + Sync>);
+
+impl CertVerifier {
+    /// Create a verifier from a callback.
+    ///
+    /// The callback returns `true` to accept the certificate and `false` to reject it.
+    /// The result replaces the outcome of the default verification.
+    pub fn new(f: impl Fn(&ServerCertificate<'_>) -> bool + Send + Sync + 'static) -> Self {
+        CertVerifier(Arc::new(f))
+    }
+
+    /// Whether both are the same callback.
+    pub(crate) fn ptr_eq(&self, other: &CertVerifier) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    #[cfg_attr(not(feature = "_rustls"), allow(dead_code))]
+    pub(crate) fn verify(&self, server: &ServerCertificate<'_>) -> bool {
+        (self.0)(server)
+    }
+}
+
+/// The certificate presented by the server, given to a [`CertVerifier`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ServerCertificate<'a> {
+    /// The certificate chain, starting with the server (end entity) certificate,
+    /// followed by the intermediates as sent by the server.
+    pub chain: &'a [Certificate<'a>],
+
+    /// The name of the server, which is the host of the request URI.
+    pub server_name: &'a str,
+
+    /// The outcome of the default verification using the configured
+    /// [`RootCerts`](crate::tls::RootCerts).
+    ///
+    /// The error is a description of why the verification failed.
+    pub default_outcome: Result<(), String>,
+}
+
+impl fmt::Debug for CertVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertVerifier").finish()
+    }
+}
@@ -100,6 +100,11 @@ impl LazyBuffers {
         }
     }
 
+    /// New buffers of the same sizes, for another half of a split transport.
+    pub(crate) fn new_like(&self) -> Self {
+        LazyBuffers::new(self.input_size, self.output_size)
+    }
+
     fn ensure_allocation(&mut self) {
         if self.output.len() < self.output_size {
             self.output.resize(self.output_size, 0);
@@ -1,3 +1,6 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
 use crate::Error;
 
 use super::{ConnectionDetails, Connector, Transport};
@@ -16,9 +19,11 @@ use super::{ConnectionDetails, Connector, Transport};
 /// The chain is always looped fully. There is no early return.
 #[derive(Debug)]
 pub struct ChainedConnector {
-    chain: Vec<Box<dyn Connector>>,
+    chain: Chain,
 }
 
+type Chain = Arc<[Box<dyn Connector>]>;
+
 impl ChainedConnector {
     /// Creates a new chain of connectors.
     ///
@@ -39,12 +44,71 @@ impl Connector for ChainedConnector {
         details: &ConnectionDetails,
         chained: Option<Box<dyn Transport>>,
     ) -> Result<Option<Box<dyn Transport>>, Error> {
-        let mut conn = chained;
+        connect_range(&self.chain, 0..self.chain.len(), details, chained)
+    }
+}
 
-        for connector in &self.chain {
-            conn = connector.connect(details, conn)?;
-        }
+thread_local! {
+    /// The chains, and the index in them, of the connectors being called on this thread.
+    /// Nested chains are pushed on top.
+    static CALLING: RefCell<Vec<(Chain, usize)>> = const { RefCell::new(Vec::new()) };
+}
 
-        Ok(conn)
+/// Removes the entry pushed to CALLING, also when the connector panics.
+struct Calling;
+
+impl Calling {
+    fn enter(chain: &Chain, index: usize) -> Self {
+        CALLING.with(|c| c.borrow_mut().push((chain.clone(), index)));
+        Calling
     }
 }
+
+impl Drop for Calling {
+    fn drop(&mut self) {
+        CALLING.with(|c| c.borrow_mut().pop());
+    }
+}
+
+fn connect_range(
+    chain: &Chain,
+    range: std::ops::Range<usize>,
+    details: &ConnectionDetails,
+    chained: Option<Box<dyn Transport>>,
+) -> Result<Option<Box<dyn Transport>>, Error> {
+    let mut conn = chained;
+
+    for i in range {
+        let _calling = Calling::enter(chain, i);
+        conn = chain[i].connect(details, conn)?;
+    }
+
+    Ok(conn)
+}
+
+/// Wrap `chained` using the connectors after the one being called by a [`ChainedConnector`].
+///
+/// Returns `chained` as is when the current connector is not called by a `ChainedConnector`.
+#[cfg(feature = "_tls")]
+pub(crate) fn connect_following(
+    details: &ConnectionDetails,
+    chained: Option<Box<dyn Transport>>,
+) -> Result<Option<Box<dyn Transport>>, Error> {
+    let Some((chain, index)) = CALLING.with(|c| c.borrow().last().cloned()) else {
+        return Ok(chained);
+    };
+    connect_range(&chain, index + 1..chain.len(), details, chained)
+}
+
+/// Open a new connection using the connectors before the one being called by a
+/// [`ChainedConnector`], the same way the chained transport was made.
+///
+/// Returns `None` when the current connector is not called by a `ChainedConnector`.
+pub(crate) fn connect_previous(
+    details: &ConnectionDetails,
+) -> Result<Option<Box<dyn Transport>>, Error> {
+    let Some((chain, index)) = CALLING.with(|c| c.borrow().last().cloned()) else {
+        return Ok(None);
+    };
+    connect_range(&chain, 0..index, details, None)
+}
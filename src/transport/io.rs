@@ -10,6 +10,7 @@ use super::Transport;
 /// This is useful when integrating with components that expect a regular `Read`/`Write`. In
 /// ureq this is used both for the [`RustlsConnector`](crate::tls::RustlsConnector) and the
 /// [`NativeTlsConnector`](crate::tls::NativeTlsConnector).
+#[derive(Debug)]
 pub struct TransportAdapter {
     pub(crate) timeout: NextTimeout,
     pub(crate) transport: Box<dyn Transport>,
@@ -27,6 +28,18 @@ impl TransportAdapter {
         }
     }
 
+    /// Set the timeout of each read and write, `None` for no timeout.
+    ///
+    /// A read or write taking longer fails with [`Error::Timeout`](crate::Error::Timeout),
+    /// as the inner error of the [`io::Error`].
+    ///
+    /// Defaults to no timeout.
+    pub fn set_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.timeout.after = timeout
+            .map(Duration::Exact)
+            .unwrap_or(Duration::NotHappening);
+    }
+
     /// Reference to the adapted transport
     pub fn get_ref(&self) -> &dyn Transport {
         &*self.transport
@@ -45,9 +58,12 @@ impl TransportAdapter {
 
 impl io::Read for TransportAdapter {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.transport
-            .await_input(self.timeout)
-            .map_err(|e| e.into_io())?;
+        // Input might already be buffered, such as after an HTTP upgrade.
+        if !self.transport.buffers().can_use_input() {
+            self.transport
+                .await_input(self.timeout)
+                .map_err(|e| e.into_io())?;
+        }
         let input = self.transport.buffers().input();
 
         let max = buf.len().min(input.len());
@@ -26,7 +26,6 @@ use std::fmt::Debug;
 use http::uri::Scheme;
 use http::Uri;
 
-use crate::proxy::Proto;
 use crate::resolver::{ResolvedSocketAddrs, Resolver};
 use crate::{AgentConfig, Error};
 
@@ -38,17 +37,22 @@ pub(crate) use buf::NoBuffers;
 pub use buf::{Buffers, LazyBuffers};
 
 mod tcp;
+#[cfg(all(test, feature = "http2"))]
+pub(crate) use tcp::TcpTransport;
 
 mod io;
 pub use io::TransportAdapter;
 
 mod chain;
+#[cfg(feature = "_tls")]
+pub(crate) use chain::connect_following;
+pub(crate) use chain::connect_previous;
 pub use chain::ChainedConnector;
 
 #[cfg(any(test, feature = "_test"))]
 mod test;
 #[cfg(any(test, feature = "_test"))]
-pub use test::set_handler;
+pub use test::{set_handler, set_handler_fn};
 
 #[cfg(feature = "socks-proxy")]
 mod socks;
@@ -107,7 +111,8 @@ pub struct ConnectionDetails<'a> {
 
     /// The resolved IP address + port for the uri being requested. See [`Resolver`].
     ///
-    /// For CONNECT proxy, this is the address of the proxy server.
+    /// For CONNECT proxy, this is the address of the proxy server. For a SOCKS proxy
+    /// resolving hostnames itself (`socks4a`, `socks5h`), this is empty.
     pub addrs: ResolvedSocketAddrs,
 
     /// The Agent configuration.
@@ -131,15 +136,10 @@ pub struct ConnectionDetails<'a> {
 impl<'a> ConnectionDetails<'a> {
     /// Tell if the requested socket need TLS wrapping.
     ///
-    /// This is (obviously) true for URLs starting `https`, but
-    /// also in the case of using a CONNECT proxy over https.
+    /// This is true for URLs starting `https`. The TLS to a CONNECT proxy over https
+    /// is handled by [`ConnectProxyConnector`], before the tunnel to the server is
+    /// wrapped in TLS.
     pub fn needs_tls(&self) -> bool {
-        if let Some(p) = &self.config.proxy {
-            if p.proto() == Proto::Https {
-                return true;
-            }
-        }
-
         self.uri.scheme() == Some(&Scheme::HTTPS)
     }
 }
@@ -190,12 +190,49 @@ pub trait Transport: Debug + Send + Sync {
     /// for connection pooling to work.
     fn is_open(&mut self) -> bool;
 
+    /// Split the transport into a reading half and a writing half, which can be used
+    /// on different threads at the same time.
+    ///
+    /// HTTP/2 needs this to receive frames while requests are being sent. Only
+    /// [`Transport::await_input()`] is used on the reading half, and only
+    /// [`Transport::transmit_output()`] on the writing half. Dropping the writing half
+    /// must close the connection, such that the reading half stops waiting for input.
+    ///
+    /// Defaults to `None`, meaning the transport can't be split and HTTP/2 can't be
+    /// used on it.
+    fn split(self: Box<Self>) -> Option<(Box<dyn Transport>, Box<dyn Transport>)> {
+        None
+    }
+
     /// Whether the transport is TLS.
     ///
     /// Defaults to `false`, override in TLS transports.
     fn is_tls(&self) -> bool {
         false
     }
+
+    /// The application protocol negotiated via TLS ALPN, such as `h2`.
+    ///
+    /// Defaults to `None`, override in TLS transports.
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// The certificate chain presented by the server, starting with the server certificate.
+    ///
+    /// Defaults to `None`, override in TLS transports.
+    #[cfg(feature = "_tls")]
+    fn peer_certificates(&self) -> Option<Vec<crate::tls::Certificate<'static>>> {
+        None
+    }
+
+    /// How the TLS connection was established, if known.
+    ///
+    /// Defaults to `None`, override in TLS transports.
+    #[cfg(feature = "_tls")]
+    fn tls_handshake(&self) -> Option<crate::tls::TlsHandshake> {
+        None
+    }
 }
 
 /// Default connector providing TCP sockets, TLS and SOCKS proxy.
@@ -204,12 +241,14 @@ pub trait Transport: Debug + Send + Sync {
 ///
 /// 1. [`SocksConnector`] to handle proxy settings if set.
 /// 2. [`TcpConnector`] to open a socket directly if a proxy is not used.
-/// 3. [`RustlsConnector`](crate::tls::RustlsConnector) which wraps the
-///    connection from 1 or 2 in TLS if the scheme is `https` and the
+/// 3. [`ConnectProxyConnector`] to open a tunnel through a CONNECT proxy if set.
+///    An `https` proxy is first connected to using TLS.
+/// 4. [`RustlsConnector`](crate::tls::RustlsConnector) which wraps the
+///    connection from 1, 2 or 3 in TLS if the scheme is `https` and the
 ///    [`TlsConfig`](crate::tls::TlsConfig) indicate we are using **rustls**.
 ///    This is the default TLS provider.
-/// 4. [`NativeTlsConnector`](crate::tls::NativeTlsConnector) which wraps
-///    the connection from 1 or 2 in TLS if the scheme is `https` and
+/// 5. [`NativeTlsConnector`](crate::tls::NativeTlsConnector) which wraps
+///    the connection from 1, 2 or 3 in TLS if the scheme is `https` and
 ///    [`TlsConfig`](crate::tls::TlsConfig) indicate we are using **native-tls**.
 ///
 #[derive(Debug)]
@@ -245,32 +284,40 @@ impl Default for DefaultConnector {
             // If we didn't get a socks-proxy, open a Tcp connection
             TcpConnector::default().boxed(),
             //
-            // If rustls is enabled, prefer that
-            #[cfg(feature = "rustls")]
-            crate::tls::RustlsConnector::default().boxed(),
-            //
-            // Panic if the config calls for rustls, the uri scheme is https and that
-            // TLS provider is not enabled by feature flags.
-            #[cfg(feature = "_tls")]
-            no_tls::WarnOnMissingTlsProvider(crate::tls::TlsProvider::Rustls).boxed(),
-            //
-            // As a fallback if rustls isn't enabled, use native-tls
-            #[cfg(feature = "native-tls")]
-            crate::tls::NativeTlsConnector::default().boxed(),
-            //
-            // Panic if the config calls for native-tls, the uri scheme is https and that
-            // TLS provider is not enabled by feature flags.
-            #[cfg(feature = "_tls")]
-            no_tls::WarnOnMissingTlsProvider(crate::tls::TlsProvider::NativeTls).boxed(),
-            //
-            // Do the final CONNECT proxy on top of the connection if indicated by config.
+            // Open the tunnel if the config indicates a CONNECT proxy.
             ConnectProxyConnector.boxed(),
+            //
+            // Wrap the connection in TLS if the scheme is https.
+            tls_connector().boxed(),
         ]);
 
         DefaultConnector { chain }
     }
 }
 
+/// The TLS connectors of the [`DefaultConnector`].
+fn tls_connector() -> ChainedConnector {
+    ChainedConnector::new([
+        // If rustls is enabled, prefer that
+        #[cfg(feature = "_rustls")]
+        crate::tls::RustlsConnector::default().boxed(),
+        //
+        // Panic if the config calls for rustls, the uri scheme is https and that
+        // TLS provider is not enabled by feature flags.
+        #[cfg(feature = "_tls")]
+        no_tls::WarnOnMissingTlsProvider(crate::tls::TlsProvider::Rustls).boxed(),
+        //
+        // As a fallback if rustls isn't enabled, use native-tls
+        #[cfg(feature = "native-tls")]
+        crate::tls::NativeTlsConnector::default().boxed(),
+        //
+        // Panic if the config calls for native-tls, the uri scheme is https and that
+        // TLS provider is not enabled by feature flags.
+        #[cfg(feature = "_tls")]
+        no_tls::WarnOnMissingTlsProvider(crate::tls::TlsProvider::NativeTls).boxed(),
+    ])
+}
+
 impl Connector for DefaultConnector {
     fn connect(
         &self,
@@ -1,5 +1,5 @@
 use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpStream};
+use std::net::{Shutdown, SocketAddr, TcpStream};
 use std::{fmt, io, time};
 
 use crate::resolver::ResolvedSocketAddrs;
@@ -142,9 +142,11 @@ impl Transport for TcpTransport {
         )?;
 
         let output = &self.buffers.output()[..amount];
-        self.stream.write_all(output).normalize_would_block()?;
-
-        Ok(())
+        match self.stream.write_all(output).normalize_would_block() {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Err(Error::Timeout(timeout.reason)),
+            Err(e) => Err(e.into()),
+        }
     }
 
     fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
@@ -161,7 +163,13 @@ impl Transport for TcpTransport {
         )?;
 
         let input = self.buffers.input_mut();
-        let amount = self.stream.read(input)?;
+        let amount = match self.stream.read(input).normalize_would_block() {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                return Err(Error::Timeout(timeout.reason))
+            }
+            Err(e) => return Err(e.into()),
+        };
         self.buffers.add_filled(amount);
 
         Ok(amount > 0)
@@ -170,6 +178,42 @@ impl Transport for TcpTransport {
     fn is_open(&mut self) -> bool {
         probe_tcp_stream(&mut self.stream).unwrap_or(false)
     }
+
+    fn split(self: Box<Self>) -> Option<(Box<dyn Transport>, Box<dyn Transport>)> {
+        let stream = self.stream.try_clone().ok()?;
+        let writer = TcpTransport::new(stream, self.buffers.new_like());
+        Some((self, Box::new(TcpWriter(writer))))
+    }
+}
+
+/// Writing half of a split [`TcpTransport`].
+///
+/// Shuts down the socket when dropped, which ends a read blocked on the other half.
+#[derive(Debug)]
+struct TcpWriter(TcpTransport);
+
+impl Transport for TcpWriter {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        self.0.buffers()
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), Error> {
+        self.0.transmit_output(amount, timeout)
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
+        self.0.await_input(timeout)
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.0.is_open()
+    }
+}
+
+impl Drop for TcpWriter {
+    fn drop(&mut self) {
+        let _ = self.0.stream.shutdown(Shutdown::Both);
+    }
 }
 
 fn probe_tcp_stream(stream: &mut TcpStream) -> Result<bool, Error> {
@@ -92,6 +92,15 @@ pub fn set_handler(pattern: &'static str, status: u16, headers: &[(&str, &str)],
     HANDLERS.with(|h| (*h).borrow_mut().push(handler));
 }
 
+/// Helper for **_test** feature tests that need to look at the request.
+pub fn set_handler_fn(
+    pattern: &'static str,
+    handler: impl Fn(Uri, Request<()>, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static,
+) {
+    let handler = TestHandler::new(pattern, handler);
+    HANDLERS.with(|h| (*h).borrow_mut().push(handler));
+}
+
 #[derive(Clone)]
 struct TestHandler {
     pattern: &'static str,
@@ -391,6 +400,10 @@ impl Transport for TestTransport {
     }
 
     fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
+        if self.buffers.can_use_input() {
+            return Ok(true);
+        }
+
         let input = self.buffers.input_mut();
         let buf = match self.rx.recv_timeout(timeout.after) {
             Ok(v) => v,
@@ -0,0 +1,227 @@
+//! WebSocket frame codec (RFC 6455 section 5).
+
+use std::io::{self, Read, Write};
+
+use crate::Error;
+
+/// Max payload of a control frame.
+pub(crate) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_u8(v: u8) -> Option<Self> {
+        Some(match v {
+            0x0 => OpCode::Continuation,
+            0x1 => OpCode::Text,
+            0x2 => OpCode::Binary,
+            0x8 => OpCode::Close,
+            0x9 => OpCode::Ping,
+            0xA => OpCode::Pong,
+            _ => return None,
+        })
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+
+    pub fn is_control(&self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Frame {
+    pub fin: bool,
+    pub opcode: OpCode,
+    pub masked: bool,
+    pub payload: Vec<u8>,
+}
+
+/// Read one frame, unmasking the payload if it is masked.
+///
+/// Frames with a payload larger than `max_payload` are refused.
+pub(crate) fn read_frame(r: &mut impl Read, max_payload: usize) -> Result<Frame, Error> {
+    let mut head = [0_u8; 2];
+    r.read_exact(&mut head)?;
+
+    let fin = head[0] & 0x80 > 0;
+
+    if head[0] & 0x70 > 0 {
+        // We never negotiate extensions, which means RSV1-3 must be 0.
+        return Err(Error::WebSocket("reserved bits set".into()));
+    }
+
+    let opcode = OpCode::from_u8(head[0] & 0x0F)
+        .ok_or_else(|| Error::WebSocket(format!("unknown opcode: {}", head[0] & 0x0F)))?;
+
+    let masked = head[1] & 0x80 > 0;
+
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut b = [0_u8; 2];
+            r.read_exact(&mut b)?;
+            u16::from_be_bytes(b) as u64
+        }
+        127 => {
+            let mut b = [0_u8; 8];
+            r.read_exact(&mut b)?;
+            u64::from_be_bytes(b)
+        }
+        v => v as u64,
+    };
+
+    if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(Error::WebSocket("bad control frame".into()));
+    }
+
+    if len > max_payload as u64 {
+        return Err(Error::WebSocket(format!(
+            "frame of {} bytes exceeds max size of {}",
+            len, max_payload
+        )));
+    }
+
+    let mut mask = [0_u8; 4];
+    if masked {
+        r.read_exact(&mut mask)?;
+    }
+
+    let mut payload = vec![0; len as usize];
+    r.read_exact(&mut payload)?;
+
+    if masked {
+        apply_mask(&mut payload, mask);
+    }
+
+    Ok(Frame {
+        fin,
+        opcode,
+        masked,
+        payload,
+    })
+}
+
+/// Write one frame, masking the payload if a mask is given.
+pub(crate) fn write_frame(
+    w: &mut impl Write,
+    fin: bool,
+    opcode: OpCode,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+    // Max header size is 2 + 8 + 4
+    let mut frame = Vec::with_capacity(14 + payload.len());
+
+    frame.push(if fin { 0x80 } else { 0 } | opcode.as_u8());
+
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let len = payload.len();
+
+    if len < 126 {
+        frame.push(mask_bit | len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(mask_bit | 126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(mask_bit | 127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+
+    if let Some(mask) = mask {
+        frame.extend_from_slice(&mask);
+    }
+
+    let start = frame.len();
+    frame.extend_from_slice(payload);
+
+    if let Some(mask) = mask {
+        apply_mask(&mut frame[start..], mask);
+    }
+
+    w.write_all(&frame)?;
+    w.flush()
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn roundtrip(payload: &[u8], mask: Option<[u8; 4]>) -> Frame {
+        let mut buf = vec![];
+        write_frame(&mut buf, true, OpCode::Binary, payload, mask).unwrap();
+        read_frame(&mut Cursor::new(buf), usize::MAX).unwrap()
+    }
+
+    #[test]
+    fn frame_roundtrip_lengths() {
+        for len in [0, 125, 126, 65535, 65536] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let frame = roundtrip(&payload, None);
+            assert!(frame.fin);
+            assert!(!frame.masked);
+            assert_eq!(frame.payload, payload);
+        }
+    }
+
+    #[test]
+    fn frame_masked() {
+        let mut buf = vec![];
+        write_frame(
+            &mut buf,
+            true,
+            OpCode::Text,
+            b"Hello",
+            Some([0x37, 0xfa, 0x21, 0x3d]),
+        )
+        .unwrap();
+        // Example from RFC 6455 section 5.7
+        assert_eq!(
+            buf,
+            [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+
+        let frame = roundtrip(b"Hello", Some([1, 2, 3, 4]));
+        assert!(frame.masked);
+        assert_eq!(frame.payload, b"Hello");
+    }
+
+    #[test]
+    fn frame_refuse_bad() {
+        // Fragmented ping
+        let r = read_frame(&mut Cursor::new([0x09, 0x00]), usize::MAX);
+        assert!(matches!(r, Err(Error::WebSocket(_))));
+
+        // Reserved bit
+        let r = read_frame(&mut Cursor::new([0xC1, 0x00]), usize::MAX);
+        assert!(matches!(r, Err(Error::WebSocket(_))));
+
+        // Too large
+        let r = read_frame(&mut Cursor::new([0x82, 0x05, 1, 2, 3, 4, 5]), 4);
+        assert!(matches!(r, Err(Error::WebSocket(_))));
+    }
+}
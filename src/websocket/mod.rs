@@ -0,0 +1,644 @@
+//! WebSocket client (RFC 6455).
+//!
+//! Requires the **websocket** feature.
+//!
+//! The connection is made with a regular HTTP/1.1 request upgraded to a
+//! WebSocket. This means it uses the same connectors as every other request,
+//! including TLS and proxies.
+//!
+//! ```no_run
+//! use ureq::websocket::Message;
+//!
+//! let mut socket = ureq::agent()
+//!     .websocket("wss://echo.example.com/")
+//!     .connect()?;
+//!
+//! socket.send(Message::Text("Hello".into()))?;
+//!
+//! match socket.recv()? {
+//!     Message::Text(text) => println!("Got: {}", text),
+//!     other => println!("Got other: {:?}", other),
+//! }
+//!
+//! socket.close(None)?;
+//! # Ok::<_, ureq::Error>(())
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use http::uri::Scheme;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use sha1::{Digest, Sha1};
+
+use crate::transport::TransportAdapter;
+use crate::{Agent, Error};
+
+use self::frame::{read_frame, write_frame, OpCode, MAX_CONTROL_PAYLOAD};
+
+mod frame;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+const DEFAULT_FRAME_SIZE: usize = 64 * 1024;
+
+/// A WebSocket message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// UTF-8 text data.
+    Text(String),
+    /// Binary data.
+    Binary(Vec<u8>),
+    /// Ping with up to 125 bytes of data.
+    ///
+    /// Received pings are answered with a pong automatically.
+    Ping(Vec<u8>),
+    /// Pong with up to 125 bytes of data.
+    Pong(Vec<u8>),
+    /// Close of the connection, with an optional status code and reason.
+    Close(Option<CloseFrame>),
+}
+
+/// Status code and reason of a close message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// Status code, like 1000 for normal closure.
+    pub code: u16,
+    /// The reason for closing. Must fit in 123 bytes.
+    pub reason: String,
+}
+
+/// Builder of a WebSocket connection.
+///
+/// Obtained via [`Agent::websocket()`](crate::Agent::websocket).
+pub struct WebSocketBuilder {
+    agent: Agent,
+    uri: Result<Uri, http::Error>,
+    headers: HeaderMap,
+    protocols: Vec<String>,
+    max_message_size: usize,
+    frame_size: usize,
+}
+
+impl WebSocketBuilder {
+    pub(crate) fn new<T>(agent: Agent, uri: T) -> Self
+    where
+        Uri: TryFrom<T>,
+        <Uri as TryFrom<T>>::Error: Into<http::Error>,
+    {
+        WebSocketBuilder {
+            agent,
+            uri: Uri::try_from(uri).map_err(Into::into),
+            headers: HeaderMap::new(),
+            protocols: vec![],
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            frame_size: DEFAULT_FRAME_SIZE,
+        }
+    }
+
+    /// Appends a header to the upgrade request.
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        HeaderValue: TryFrom<V>,
+    {
+        let key = HeaderName::try_from(key);
+        let value = HeaderValue::try_from(value);
+        if let (Ok(key), Ok(value)) = (key, value) {
+            self.headers.append(key, value);
+        } else {
+            warn!("Ignore invalid WebSocket header");
+        }
+        self
+    }
+
+    /// Subprotocols to offer the server, in order of preference.
+    ///
+    /// The one picked by the server is available in [`WebSocket::protocol()`].
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Max size of a received message, after joining fragments.
+    ///
+    /// Defaults to 64MB.
+    pub fn max_message_size(mut self, value: usize) -> Self {
+        self.max_message_size = value;
+        self
+    }
+
+    /// Max payload of sent frames. Larger messages are fragmented.
+    ///
+    /// Defaults to 64kB.
+    pub fn frame_size(mut self, value: usize) -> Self {
+        self.frame_size = value.max(1);
+        self
+    }
+
+    /// Perform the upgrade handshake.
+    pub fn connect(self) -> Result<WebSocket, Error> {
+        let uri = http_uri(self.uri?)?;
+
+        let mut nonce = [0_u8; 16];
+        getrandom::getrandom(&mut nonce).map_err(std::io::Error::from)?;
+        let key = BASE64_STANDARD.encode(nonce);
+
+        let mut builder = self
+            .agent
+            .get(uri)
+            .header("connection", "Upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", &key);
+
+        for (k, v) in &self.headers {
+            builder = builder.header(k, v);
+        }
+
+        if !self.protocols.is_empty() {
+            builder = builder.header("sec-websocket-protocol", self.protocols.join(", "));
+        }
+
+        let res = builder.call()?;
+
+        if res.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(Error::WebSocket(format!(
+                "expected 101 response, got {}",
+                res.status().as_u16()
+            )));
+        }
+
+        let headers = res.headers();
+
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+        let is_upgrade = header("upgrade")
+            .map(|v| v.eq_ignore_ascii_case("websocket"))
+            .unwrap_or(false);
+
+        let is_connection_upgrade = header("connection")
+            .map(|v| {
+                v.split(',')
+                    .any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+            })
+            .unwrap_or(false);
+
+        if !is_upgrade || !is_connection_upgrade {
+            return Err(Error::WebSocket("missing upgrade headers".into()));
+        }
+
+        if header("sec-websocket-accept") != Some(&accept_key(&key)) {
+            return Err(Error::WebSocket("bad Sec-WebSocket-Accept".into()));
+        }
+
+        if headers.contains_key("sec-websocket-extensions") {
+            // We never offer extensions.
+            return Err(Error::WebSocket("unexpected extension".into()));
+        }
+
+        let protocol = header("sec-websocket-protocol").map(|v| v.to_string());
+
+        if let Some(p) = &protocol {
+            if !self.protocols.contains(p) {
+                return Err(Error::WebSocket(format!("unexpected protocol: {}", p)));
+            }
+        }
+
+        let stream = res.into_body().into_upgraded()?;
+
+        debug!("WebSocket connected");
+
+        Ok(WebSocket {
+            stream,
+            protocol,
+            max_message_size: self.max_message_size,
+            frame_size: self.frame_size,
+            partial: None,
+            close_sent: false,
+            close_received: false,
+        })
+    }
+}
+
+/// A WebSocket connection.
+///
+/// Obtained via [`WebSocketBuilder::connect()`].
+pub struct WebSocket {
+    stream: TransportAdapter,
+    protocol: Option<String>,
+    max_message_size: usize,
+    frame_size: usize,
+    // Fragmented data message being received.
+    partial: Option<(OpCode, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    /// The subprotocol picked by the server, if any.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Set the timeout of each read and write on the connection, `None` for no timeout.
+    ///
+    /// A [`recv()`](WebSocket::recv) waiting longer fails with
+    /// [`Error::Timeout`], after which there are no more messages to receive, since
+    /// part of a frame might have been read.
+    ///
+    /// Defaults to no timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.stream.set_timeout(timeout);
+    }
+
+    /// Send a message.
+    ///
+    /// Text and binary messages larger than the
+    /// [frame size](WebSocketBuilder::frame_size) are fragmented.
+    pub fn send(&mut self, message: Message) -> Result<(), Error> {
+        if self.close_sent {
+            return Err(Error::WebSocket("connection is closing".into()));
+        }
+
+        match message {
+            Message::Text(v) => self.send_data(OpCode::Text, v.as_bytes()),
+            Message::Binary(v) => self.send_data(OpCode::Binary, &v),
+            Message::Ping(v) => self.send_control(OpCode::Ping, &v),
+            Message::Pong(v) => self.send_control(OpCode::Pong, &v),
+            Message::Close(v) => {
+                let payload = match v {
+                    Some(c) => {
+                        let mut p = c.code.to_be_bytes().to_vec();
+                        p.extend_from_slice(c.reason.as_bytes());
+                        p
+                    }
+                    None => vec![],
+                };
+                self.send_control(OpCode::Close, &payload)?;
+                self.close_sent = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Receive the next message.
+    ///
+    /// Pings are answered automatically, but still returned. After a close
+    /// message, there are no more messages to receive.
+    pub fn recv(&mut self) -> Result<Message, Error> {
+        loop {
+            if self.close_received {
+                return Err(Error::WebSocket("connection is closed".into()));
+            }
+
+            let frame = match read_frame(&mut self.stream, self.max_message_size) {
+                Ok(v) => v,
+                Err(e) => return Err(self.fail(e)),
+            };
+
+            if frame.masked {
+                return Err(self.fail(Error::WebSocket("masked frame from server".into())));
+            }
+
+            let payload = frame.payload;
+
+            let (opcode, data) = match frame.opcode {
+                OpCode::Ping => {
+                    if !self.close_sent {
+                        self.send_control(OpCode::Pong, &payload)?;
+                    }
+                    return Ok(Message::Ping(payload));
+                }
+
+                OpCode::Pong => return Ok(Message::Pong(payload)),
+
+                OpCode::Close => {
+                    self.close_received = true;
+                    let close = parse_close(&payload)?;
+
+                    if !self.close_sent {
+                        // Echo the status code back as the close handshake.
+                        let echo = payload.get(..2).unwrap_or_default().to_vec();
+                        self.send_control(OpCode::Close, &echo)?;
+                        self.close_sent = true;
+                    }
+
+                    return Ok(Message::Close(close));
+                }
+
+                OpCode::Text | OpCode::Binary => {
+                    if self.partial.is_some() {
+                        return Err(self.fail(Error::WebSocket("expected continuation".into())));
+                    }
+                    if !frame.fin {
+                        self.partial = Some((frame.opcode, payload));
+                        continue;
+                    }
+                    (frame.opcode, payload)
+                }
+
+                OpCode::Continuation => {
+                    let Some((_, data)) = &mut self.partial else {
+                        return Err(self.fail(Error::WebSocket("unexpected continuation".into())));
+                    };
+
+                    if data.len() + payload.len() > self.max_message_size {
+                        return Err(self.fail(Error::WebSocket(format!(
+                            "message exceeds max size of {}",
+                            self.max_message_size
+                        ))));
+                    }
+
+                    data.extend_from_slice(&payload);
+
+                    if !frame.fin {
+                        continue;
+                    }
+
+                    // unwrap is ok because of the let-else above.
+                    self.partial.take().unwrap()
+                }
+            };
+
+            return if opcode == OpCode::Text {
+                match String::from_utf8(data) {
+                    Ok(v) => Ok(Message::Text(v)),
+                    Err(_) => Err(self.fail(Error::WebSocket("text is not utf-8".into()))),
+                }
+            } else {
+                Ok(Message::Binary(data))
+            };
+        }
+    }
+
+    /// Close the connection.
+    ///
+    /// Sends a close message and waits for the server to acknowledge it. Any
+    /// messages received before the acknowledgement are dropped.
+    pub fn close(&mut self, frame: Option<CloseFrame>) -> Result<(), Error> {
+        if !self.close_sent {
+            self.send(Message::Close(frame))?;
+        }
+
+        while !self.close_received {
+            self.recv()?;
+        }
+
+        Ok(())
+    }
+
+    fn send_data(&mut self, opcode: OpCode, data: &[u8]) -> Result<(), Error> {
+        let mut chunks = data.chunks(self.frame_size).peekable();
+
+        if chunks.peek().is_none() {
+            return self.write(true, opcode, &[]);
+        }
+
+        let mut opcode = opcode;
+        while let Some(chunk) = chunks.next() {
+            let fin = chunks.peek().is_none();
+            self.write(fin, opcode, chunk)?;
+            opcode = OpCode::Continuation;
+        }
+
+        Ok(())
+    }
+
+    fn send_control(&mut self, opcode: OpCode, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(Error::WebSocket("control payload too large".into()));
+        }
+        self.write(true, opcode, payload)
+    }
+
+    fn write(&mut self, fin: bool, opcode: OpCode, payload: &[u8]) -> Result<(), Error> {
+        let mut mask = [0_u8; 4];
+        getrandom::getrandom(&mut mask).map_err(std::io::Error::from)?;
+
+        write_frame(&mut self.stream, fin, opcode, payload, Some(mask))?;
+        Ok(())
+    }
+
+    /// Fail the connection, no more messages will be received.
+    fn fail(&mut self, e: Error) -> Error {
+        debug!("WebSocket failed: {}", e);
+        self.close_received = true;
+        e
+    }
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, Error> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(Error::WebSocket("bad close payload".into())),
+        [a, b, reason @ ..] => {
+            let reason = std::str::from_utf8(reason)
+                .map_err(|_| Error::WebSocket("close reason is not utf-8".into()))?;
+            Ok(Some(CloseFrame {
+                code: u16::from_be_bytes([*a, *b]),
+                reason: reason.to_string(),
+            }))
+        }
+    }
+}
+
+/// Sec-WebSocket-Accept for a Sec-WebSocket-Key.
+pub(crate) fn accept_key(key: &str) -> String {
+    let hash = Sha1::digest(format!("{}{}", key, GUID).as_bytes());
+    BASE64_STANDARD.encode(hash)
+}
+
+/// Map ws/wss to http/https.
+fn http_uri(uri: Uri) -> Result<Uri, Error> {
+    let scheme = match uri.scheme_str() {
+        Some("ws") | Some("http") => Scheme::HTTP,
+        Some("wss") | Some("https") => Scheme::HTTPS,
+        _ => return Err(Error::BadUri(format!("expected ws or wss: {}", uri))),
+    };
+
+    let mut parts = uri.into_parts();
+    parts.scheme = Some(scheme);
+
+    Uri::from_parts(parts).map_err(|e| Error::BadUri(e.to_string()))
+}
+
+impl fmt::Debug for WebSocketBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketBuilder")
+            .field("uri", &self.uri)
+            .field("protocols", &self.protocols)
+            .finish()
+    }
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("transport", &self.stream.get_ref())
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "_test"))]
+mod test {
+    use super::*;
+    use crate::test::init_test_log;
+    use crate::transport::set_handler_fn;
+
+    #[test]
+    fn websocket_accept_key() {
+        // Example from RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn websocket_http_uri() {
+        let uri = http_uri("wss://example.com/chat?x=1".parse().unwrap()).unwrap();
+        assert_eq!(uri.to_string(), "https://example.com/chat?x=1");
+        assert!(http_uri("ftp://example.com".parse().unwrap()).is_err());
+    }
+
+    fn server(path: &'static str, accept: Option<&'static str>, frames: &'static [u8]) {
+        set_handler_fn(path, move |_uri, req, w| {
+            let key = req.headers().get("sec-websocket-key").unwrap();
+            let accept = match accept {
+                Some(v) => v.to_string(),
+                None => accept_key(key.to_str().unwrap()),
+            };
+            let mut data = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                Upgrade: websocket\r\n\
+                Connection: Upgrade\r\n\
+                Sec-WebSocket-Accept: {}\r\n\
+                \r\n",
+                accept
+            )
+            .into_bytes();
+            // Frames in the same write means they are buffered with the 101 response.
+            data.extend_from_slice(frames);
+            w.write_all(&data)
+        });
+    }
+
+    #[test]
+    fn websocket_receive_messages() {
+        init_test_log();
+        server(
+            "/ws-messages",
+            None,
+            &[
+                0x81, 0x05, b'H', b'e', b'l', b'l', b'o', // text "Hello"
+                0x02, 0x02, 1, 2, // binary fragment
+                0x89, 0x01, b'p', // interleaved ping
+                0x80, 0x01, 3, // final continuation
+                0x88, 0x04, 0x03, 0xE8, b'o', b'k', // close 1000 "ok"
+            ],
+        );
+
+        let mut socket = crate::agent()
+            .websocket("wss://my.test/ws-messages")
+            .connect()
+            .unwrap();
+
+        assert_eq!(socket.recv().unwrap(), Message::Text("Hello".into()));
+        assert_eq!(socket.recv().unwrap(), Message::Ping(b"p".to_vec()));
+        assert_eq!(socket.recv().unwrap(), Message::Binary(vec![1, 2, 3]));
+        assert_eq!(
+            socket.recv().unwrap(),
+            Message::Close(Some(CloseFrame {
+                code: 1000,
+                reason: "ok".into()
+            }))
+        );
+        assert!(socket.recv().is_err());
+        assert!(socket.send(Message::Text("late".into())).is_err());
+    }
+
+    #[test]
+    fn websocket_bad_accept() {
+        init_test_log();
+        server("/ws-bad-accept", Some("nope"), &[]);
+
+        let err = crate::agent()
+            .websocket("ws://my.test/ws-bad-accept")
+            .connect()
+            .unwrap_err();
+
+        assert!(matches!(err, Error::WebSocket(_)));
+    }
+
+    #[test]
+    fn websocket_refuse_masked_frame() {
+        init_test_log();
+        server("/ws-masked", None, &[0x81, 0x81, 1, 2, 3, 4, b'a' ^ 1]);
+
+        let mut socket = crate::agent()
+            .websocket("wss://my.test/ws-masked")
+            .connect()
+            .unwrap();
+
+        assert!(matches!(socket.recv(), Err(Error::WebSocket(_))));
+    }
+
+    #[test]
+    fn websocket_timeout() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        use crate::test::FixedResolver;
+        use crate::transport::{ChainedConnector, Connector, TcpConnector};
+
+        init_test_log();
+
+        // Accepts the upgrade, then never sends a frame.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(sock.try_clone().unwrap());
+            let mut key = String::new();
+            let mut line = String::new();
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if let Some(v) = line.strip_prefix("sec-websocket-key: ") {
+                    key = v.trim().to_string();
+                }
+            }
+            write!(
+                sock,
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                upgrade: websocket\r\n\
+                connection: upgrade\r\n\
+                sec-websocket-accept: {}\r\n\
+                \r\n",
+                accept_key(&key)
+            )
+            .unwrap();
+            // Keep the connection open until the client is done.
+            let _ = reader.read_line(&mut line);
+        });
+
+        let connector = ChainedConnector::new([TcpConnector::default().boxed()]);
+        let agent = Agent::with_parts(Default::default(), connector, FixedResolver(addr));
+
+        let mut socket = agent
+            .websocket(format!("ws://{}/silent", addr))
+            .connect()
+            .unwrap();
+        socket.set_timeout(Some(Duration::from_millis(100)));
+
+        let err = socket.recv().unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)), "{:?}", err);
+        assert!(matches!(socket.recv(), Err(Error::WebSocket(_))));
+    }
+}